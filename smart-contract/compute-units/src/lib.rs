@@ -0,0 +1,144 @@
+//! Compute units the wallet instructions consume on the SBF build, measured in a
+//! `solana-program-test` bank. Build the program with and without `debug-logs` first:
+//!
+//! ```sh
+//! cargo build-sbf --sbf-out-dir target/deploy/release
+//! cargo build-sbf --features debug-logs --sbf-out-dir target/deploy/debug-logs
+//! ```
+//!
+//! then run `cargo test -- --nocapture` here to compare the two and check each instruction
+//! against its budget in `budgets.json`; `UPDATE_BUDGETS=1` records the measured figures
+//! there. `RELEASE_SO` and `DEBUG_LOGS_SO` point at other builds of `smart_contract.so`.
+
+use std::{env, fs, path::PathBuf};
+
+use smart_contract::{
+    client,
+    state::{derive_approval_address, derive_wallet_address},
+};
+use solana_program_test::{ProgramTest, ProgramTestContext};
+use solana_sdk::{
+    account::Account,
+    bpf_loader,
+    clock::Clock,
+    instruction::Instruction,
+    native_token::LAMPORTS_PER_SOL,
+    pubkey::Pubkey,
+    rent::Rent,
+    signature::{Keypair, Signer},
+    system_instruction,
+    transaction::Transaction,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Build {
+    Release,
+    DebugLogs,
+}
+
+impl Build {
+    pub const ALL: [Self; 2] = [Self::Release, Self::DebugLogs];
+
+    fn program_path(self) -> PathBuf {
+        let (variable, directory) = match self {
+            Self::Release => ("RELEASE_SO", "release"),
+            Self::DebugLogs => ("DEBUG_LOGS_SO", "debug-logs"),
+        };
+        env::var_os(variable).map(PathBuf::from).unwrap_or_else(|| {
+            PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../target/deploy").join(directory).join("smart_contract.so")
+        })
+    }
+}
+
+/// A bank running one build of the program, with a funded owner and dApp
+pub struct Bench {
+    pub context: ProgramTestContext,
+    pub program_id: Pubkey,
+    pub owner: Keypair,
+    pub dapp: Keypair,
+}
+
+impl Bench {
+    pub async fn new(build: Build) -> Self {
+        let path = build.program_path();
+        let data = fs::read(&path).unwrap_or_else(|error| panic!("{} ({}): build the program with cargo build-sbf", path.display(), error));
+
+        let program_id = Pubkey::new_unique();
+        let owner = Keypair::new();
+        let dapp = Keypair::new();
+
+        let mut program_test = ProgramTest::default();
+        program_test.add_account(program_id, Account {
+            lamports: Rent::default().minimum_balance(data.len()).max(1),
+            data,
+            owner: bpf_loader::id(),
+            executable: true,
+            rent_epoch: 0,
+        });
+        for signer in [&owner, &dapp] {
+            program_test.add_account(signer.pubkey(), Account::new(10 * LAMPORTS_PER_SOL, 0, &solana_sdk::system_program::id()));
+        }
+
+        let context = program_test.start_with_context().await;
+        Self { context, program_id, owner, dapp }
+    }
+
+    pub fn wallet(&self) -> Pubkey {
+        derive_wallet_address(&self.owner.pubkey(), &self.program_id).0
+    }
+
+    pub fn approval(&self) -> Pubkey {
+        derive_approval_address(&self.wallet(), &self.dapp.pubkey(), &Pubkey::default(), &self.program_id).0
+    }
+
+    pub async fn now(&mut self) -> i64 {
+        self.context.banks_client.get_sysvar::<Clock>().await.unwrap().unix_timestamp
+    }
+
+    fn transaction(&self, instruction: Instruction, signers: &[&Keypair]) -> Transaction {
+        let mut all_signers = vec![&self.context.payer];
+        all_signers.extend(signers);
+        Transaction::new_signed_with_payer(&[instruction], Some(&self.context.payer.pubkey()), &all_signers, self.context.last_blockhash)
+    }
+
+    /// Compute units the instruction consumes, without applying it
+    pub async fn measure(&mut self, instruction: Instruction, signers: &[&Keypair]) -> u64 {
+        let transaction = self.transaction(instruction, signers);
+        let simulation = self.context.banks_client.simulate_transaction(transaction).await.unwrap();
+        let details = simulation.simulation_details.unwrap();
+        if let Some(Err(error)) = simulation.result {
+            panic!("simulation failed: {}\n{}", error, details.logs.join("\n"));
+        }
+        details.units_consumed
+    }
+
+    /// Measures the instruction, then applies it
+    pub async fn run(&mut self, instruction: Instruction, signers: &[&Keypair]) -> u64 {
+        let units = self.measure(instruction.clone(), signers).await;
+        let transaction = self.transaction(instruction, signers);
+        self.context.banks_client.process_transaction(transaction).await.unwrap();
+        self.context.last_blockhash = self.context.banks_client.get_latest_blockhash().await.unwrap();
+        units
+    }
+
+    /// Creates the owner's wallet, funds it and approves the dApp for SOL transfers
+    pub async fn create_wallet(&mut self, max_amount: u64) -> (u64, u64) {
+        let owner = self.owner.insecure_clone();
+        let create = self.run(client::create_wallet(&self.program_id, &owner.pubkey()), &[&owner]).await;
+
+        let fund = system_instruction::transfer(&self.context.payer.pubkey(), &self.wallet(), LAMPORTS_PER_SOL);
+        self.run(fund, &[]).await;
+
+        let expiry = self.now().await + 3600;
+        let approve = client::approve_dapp(&self.program_id, &owner.pubkey(), &self.dapp.pubkey(), &Pubkey::default(), max_amount, expiry);
+        let approve = self.run(approve, &[&owner]).await;
+        (create, approve)
+    }
+
+    /// Rewrites an account's data in place, e.g. to drop a stored bump
+    pub async fn update_data(&mut self, address: &Pubkey, update: impl FnOnce(&mut [u8])) {
+        let mut account = self.context.banks_client.get_account(*address).await.unwrap().unwrap();
+        update(&mut account.data);
+        self.context.set_account(address, &account.into());
+    }
+}
@@ -0,0 +1,136 @@
+//! Whole-instruction compute units on the SBF build, with and without `debug-logs`, checked
+//! against the budgets in `budgets.json`. Run with `UPDATE_BUDGETS=1` to record new figures.
+
+use std::{collections::BTreeMap, env, fs, mem::offset_of, path::Path};
+
+use smart_contract::{
+    client,
+    state::{DAppApproval, WalletState, ACCOUNT_HEADER_SIZE},
+};
+use smart_contract_compute_units::{Bench, Build};
+use solana_sdk::{pubkey::Pubkey, signature::Signer};
+
+const MAX_AMOUNT: u64 = 100_000_000;
+const AMOUNT: u64 = 1_000_000;
+
+/// How far, in percent, an instruction may go over its recorded budget
+const BUDGET_MARGIN_PERCENT: u64 = 10;
+
+// Compute units the runtime charges for a `create_program_address`, and for each bump
+// tried by `find_program_address`
+const PDA_DERIVATION_UNITS: u64 = 1500;
+
+/// Compute units of the hot-path instructions, in the order of `INSTRUCTIONS`
+async fn hot_path_units(build: Build) -> [u64; 5] {
+    let mut bench = Bench::new(build).await;
+    let owner = bench.owner.insecure_clone();
+    let dapp = bench.dapp.insecure_clone();
+    let recipient = Pubkey::new_unique();
+
+    let (create, approve) = bench.create_wallet(MAX_AMOUNT).await;
+    let update = client::set_approval_status(&bench.program_id, &owner.pubkey(), &dapp.pubkey(), &Pubkey::default(), true);
+    let update = bench.run(update, &[&owner]).await;
+
+    let execute = client::execute_transaction(&bench.program_id, &dapp.pubkey(), &owner.pubkey(), &recipient, AMOUNT, None, None);
+    let execute = bench.run(execute, &[&dapp]).await;
+    let withdraw = client::withdraw(&bench.program_id, &owner.pubkey(), &recipient, AMOUNT, None);
+    let withdraw = bench.run(withdraw, &[&owner]).await;
+
+    [create, approve, update, execute, withdraw]
+}
+
+const INSTRUCTIONS: [&str; 5] = ["CreateWallet", "ApproveDapp", "UpdateApproval", "ExecuteTransaction", "Withdraw"];
+
+#[tokio::test]
+async fn debug_logs_cost_more_than_release() {
+    let release = hot_path_units(Build::Release).await;
+    let debug_logs = hot_path_units(Build::DebugLogs).await;
+
+    for ((instruction, release), debug_logs) in INSTRUCTIONS.iter().zip(release).zip(debug_logs) {
+        println!("{:<24} release {:>7} debug-logs {:>7}", instruction, release, debug_logs);
+        // `CreateWallet` has no diagnostics to gate
+        if *instruction != "CreateWallet" {
+            assert!(debug_logs > release, "{} should log diagnostics only with debug-logs", instruction);
+        }
+    }
+}
+
+/// Units per instruction, per build
+type Budgets = BTreeMap<String, BTreeMap<String, u64>>;
+
+#[tokio::test]
+async fn hot_path_stays_within_its_budget() {
+    let mut measured = Budgets::new();
+    for build in Build::ALL {
+        let units = INSTRUCTIONS.iter().map(|instruction| instruction.to_string()).zip(hot_path_units(build).await);
+        measured.insert(format!("{:?}", build), units.collect());
+    }
+
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("budgets.json");
+    if env::var_os("UPDATE_BUDGETS").is_some() {
+        fs::write(&path, serde_json::to_string_pretty(&measured).unwrap() + "\n").unwrap();
+    }
+    let budgets: Budgets = fs::read_to_string(&path)
+        .map(|budgets| serde_json::from_str(&budgets).unwrap())
+        .unwrap_or_default();
+
+    for (build, units) in &measured {
+        for (instruction, units) in units {
+            let budget = *budgets.get(build).and_then(|budgets| budgets.get(instruction)).unwrap_or_else(|| {
+                panic!("no budget for {} on the {} build; run with `UPDATE_BUDGETS=1` to record one", instruction, build)
+            });
+            println!("{:<24} {:<10} {:>7} budget {:>7}", instruction, build, units, budget);
+            assert!(
+                *units <= budget + budget * BUDGET_MARGIN_PERCENT / 100,
+                "{} on the {} build uses {} units, more than {}% over its budget of {}",
+                instruction,
+                build,
+                units,
+                BUDGET_MARGIN_PERCENT,
+                budget
+            );
+        }
+    }
+}
+
+fn break_bump(data: &mut [u8], offset: usize) {
+    let bump = &mut data[ACCOUNT_HEADER_SIZE + offset];
+    *bump = bump.wrapping_sub(1);
+}
+
+#[tokio::test]
+async fn stored_bumps_skip_the_bump_search() {
+    let mut bench = Bench::new(Build::Release).await;
+    let owner = bench.owner.insecure_clone();
+    let dapp = bench.dapp.insecure_clone();
+    let recipient = Pubkey::new_unique();
+    bench.create_wallet(MAX_AMOUNT).await;
+    let (wallet, approval) = (bench.wallet(), bench.approval());
+
+    // PDAs checked by each instruction: wallet for `Withdraw`, wallet and approval for the others
+    let instructions = [
+        ("ExecuteTransaction", client::execute_transaction(&bench.program_id, &dapp.pubkey(), &owner.pubkey(), &recipient, AMOUNT, None, None), &dapp, 2),
+        ("Withdraw", client::withdraw(&bench.program_id, &owner.pubkey(), &recipient, AMOUNT, None), &owner, 1),
+        ("UpdateApproval", client::set_approval_status(&bench.program_id, &owner.pubkey(), &dapp.pubkey(), &Pubkey::default(), true), &owner, 2),
+    ];
+
+    let mut stored = vec![];
+    for (_, instruction, signer, _) in &instructions {
+        stored.push(bench.measure(instruction.clone(), &[*signer]).await);
+    }
+
+    // A bump that doesn't derive the account's address makes the program search for it
+    bench.update_data(&wallet, |data| break_bump(data, offset_of!(WalletState, bump))).await;
+    bench.update_data(&approval, |data| break_bump(data, offset_of!(DAppApproval, bump))).await;
+
+    for ((name, instruction, signer, checks), stored) in instructions.into_iter().zip(stored) {
+        let searched = bench.measure(instruction, &[signer]).await;
+        println!("{:<24} stored bump {:>7} bump search {:>7}", name, stored, searched);
+        assert!(
+            searched >= stored + checks * PDA_DERIVATION_UNITS,
+            "{} should verify its {} PDAs without a bump search",
+            name,
+            checks
+        );
+    }
+}
@@ -0,0 +1,15 @@
+//! Decodes arbitrary bytes as a wallet instruction. Decoding must never panic, and anything
+//! it accepts has to be the canonical encoding of the decoded instruction.
+//!
+//! Run with `cargo fuzz run unpack_instruction` from `smart-contract/`.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use smart_contract::instruction::WalletInstruction;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(instruction) = WalletInstruction::unpack(data) {
+        assert_eq!(instruction.pack(), data);
+    }
+});
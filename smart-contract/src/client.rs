@@ -0,0 +1,584 @@
+//! Off-chain helpers for building wallet instructions and reading wallet accounts
+
+use crate::{
+    error::WalletErrorCode,
+    instruction::{TransferType, WalletInstruction},
+    state::{
+        derive_approval_address, derive_permit_address, derive_wallet_address, unpack_account_data, AccountKind, DAppApproval, ProgramAccount,
+        APPROVAL_DAPP_OFFSET, APPROVAL_WALLET_OFFSET,
+    },
+};
+use solana_program::{
+    instruction::{AccountMeta, Instruction, InstructionError},
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    system_program,
+};
+use spl_token_2022::offchain::{resolve_extra_transfer_account_metas, AccountDataResult, AccountFetchError};
+use std::{fmt, future::Future};
+
+/// Creates `owner`'s wallet
+pub fn create_wallet(program_id: &Pubkey, owner: &Pubkey) -> Instruction {
+    let (wallet, _) = derive_wallet_address(owner, program_id);
+    Instruction::new_with_bytes(
+        *program_id,
+        &WalletInstruction::CreateWallet.pack(),
+        vec![
+            AccountMeta::new(*owner, true),
+            AccountMeta::new(wallet, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    )
+}
+
+/// Approves `dapp` to spend up to `max_amount` of `token_mint` (`Pubkey::default()` for SOL)
+/// per transaction until `expiry`
+pub fn approve_dapp(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    dapp: &Pubkey,
+    token_mint: &Pubkey,
+    max_amount: u64,
+    expiry: i64,
+) -> Instruction {
+    let (wallet, _) = derive_wallet_address(owner, program_id);
+    let (approval, _) = derive_approval_address(&wallet, dapp, token_mint, program_id);
+    Instruction::new_with_bytes(
+        *program_id,
+        &WalletInstruction::ApproveDapp { max_amount, expiry }.pack(),
+        vec![
+            AccountMeta::new(*owner, true),
+            AccountMeta::new_readonly(wallet, false),
+            AccountMeta::new_readonly(*dapp, false),
+            AccountMeta::new_readonly(*token_mint, false),
+            AccountMeta::new(approval, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    )
+}
+
+/// Pauses (`is_approved` false) or resumes `dapp`'s approval for `token_mint`, keeping
+/// the rest of it unchanged
+pub fn set_approval_status(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    dapp: &Pubkey,
+    token_mint: &Pubkey,
+    is_approved: bool,
+) -> Instruction {
+    let (wallet, _) = derive_wallet_address(owner, program_id);
+    let (approval, _) = derive_approval_address(&wallet, dapp, token_mint, program_id);
+    let instruction = WalletInstruction::UpdateApproval {
+        max_amount: None,
+        expiry: None,
+        recipients: None,
+        is_approved: Some(is_approved),
+        valid_from: None,
+        max_executions: None,
+        single_use: None,
+        usd_limit: None,
+        auto_limit: None,
+        cosign_limit: None,
+    };
+    Instruction::new_with_bytes(
+        *program_id,
+        &instruction.pack(),
+        vec![
+            AccountMeta::new(*owner, true),
+            AccountMeta::new_readonly(wallet, false),
+            AccountMeta::new_readonly(*dapp, false),
+            AccountMeta::new(approval, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    )
+}
+
+/// Burns permit `nonce` of `owner`'s wallet, so that a permit signed with it can't be used
+pub fn cancel_permit(program_id: &Pubkey, owner: &Pubkey, nonce: u64) -> Instruction {
+    let (wallet, _) = derive_wallet_address(owner, program_id);
+    let (permit, _) = derive_permit_address(&wallet, nonce, program_id);
+    Instruction::new_with_bytes(
+        *program_id,
+        &WalletInstruction::CancelPermit { nonce }.pack(),
+        vec![
+            AccountMeta::new(*owner, true),
+            AccountMeta::new_readonly(wallet, false),
+            AccountMeta::new(permit, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    )
+}
+
+/// The token accounts of a transfer out of the wallet
+#[derive(Clone, Debug)]
+pub struct TokenTransfer {
+    pub mint: Pubkey,
+    /// The wallet's token account for `mint`
+    pub wallet_token_account: Pubkey,
+    /// The program that owns `mint`, SPL Token or Token-2022
+    pub token_program: Pubkey,
+}
+
+impl TokenTransfer {
+    fn append_to(&self, accounts: &mut Vec<AccountMeta>) {
+        accounts.push(AccountMeta::new_readonly(self.mint, false));
+        accounts.push(AccountMeta::new(self.wallet_token_account, false));
+        accounts.push(AccountMeta::new_readonly(self.token_program, false));
+    }
+}
+
+/// Transfers `amount` to `recipient` (the recipient's token account for tokens) under
+/// `dapp`'s approval. `price_feed` is the approval's USD price account, if it has a USD limit.
+/// Mints with a transfer hook also need `add_transfer_hook_accounts`
+pub fn execute_transaction(
+    program_id: &Pubkey,
+    dapp: &Pubkey,
+    owner: &Pubkey,
+    recipient: &Pubkey,
+    amount: u64,
+    price_feed: Option<&Pubkey>,
+    token: Option<&TokenTransfer>,
+) -> Instruction {
+    let (wallet, _) = derive_wallet_address(owner, program_id);
+    let token_mint = token.map(|token| token.mint).unwrap_or_default();
+    let (approval, _) = derive_approval_address(&wallet, dapp, &token_mint, program_id);
+    let transfer_type = if token.is_some() { TransferType::Token } else { TransferType::Sol };
+
+    let mut accounts = vec![
+        AccountMeta::new(*dapp, true),
+        AccountMeta::new(*owner, false),
+        AccountMeta::new(wallet, false),
+        AccountMeta::new(approval, false),
+        AccountMeta::new(*recipient, false),
+        AccountMeta::new_readonly(system_program::id(), false),
+    ];
+    if let Some(price_feed) = price_feed {
+        accounts.push(AccountMeta::new_readonly(*price_feed, false));
+    }
+    if let Some(token) = token {
+        token.append_to(&mut accounts);
+    }
+    Instruction::new_with_bytes(
+        *program_id,
+        &WalletInstruction::ExecuteTransaction { amount, transfer_type }.pack(),
+        accounts,
+    )
+}
+
+/// Withdraws `amount` from `owner`'s wallet to `recipient` (a token account for tokens).
+/// Mints with a transfer hook also need `add_transfer_hook_accounts`
+pub fn withdraw(program_id: &Pubkey, owner: &Pubkey, recipient: &Pubkey, amount: u64, token: Option<&TokenTransfer>) -> Instruction {
+    let (wallet, _) = derive_wallet_address(owner, program_id);
+    let transfer_type = if token.is_some() { TransferType::Token } else { TransferType::Sol };
+
+    let mut accounts = vec![
+        AccountMeta::new_readonly(*owner, true),
+        AccountMeta::new(wallet, false),
+        AccountMeta::new(*recipient, false),
+        AccountMeta::new_readonly(system_program::id(), false),
+    ];
+    if let Some(token) = token {
+        token.append_to(&mut accounts);
+    }
+    Instruction::new_with_bytes(
+        *program_id,
+        &WalletInstruction::Withdraw { amount, transfer_type }.pack(),
+        accounts,
+    )
+}
+
+/// The ed25519 program instruction that must directly precede `ApproveDappWithPermit` or
+/// `ExecuteIntent`, verifying `signer`'s `signature` over `message` (see
+/// `permit::ApprovalPermit::message` and `permit::TransferIntent::message`)
+pub fn ed25519_signature(signer: &Pubkey, signature: &[u8; 64], message: &[u8]) -> Instruction {
+    // One signature whose public key, signature and message all follow the offsets in
+    // this same instruction, which is what the wallet program checks for
+    const DATA_START: u16 = 16;
+    let public_key_offset = DATA_START;
+    let signature_offset = public_key_offset + 32;
+    let message_offset = signature_offset + 64;
+
+    let mut data = vec![1, 0];
+    for offset in [
+        signature_offset,
+        u16::MAX,
+        public_key_offset,
+        u16::MAX,
+        message_offset,
+        message.len() as u16,
+        u16::MAX,
+    ] {
+        data.extend_from_slice(&offset.to_le_bytes());
+    }
+    data.extend_from_slice(signer.as_ref());
+    data.extend_from_slice(signature);
+    data.extend_from_slice(message);
+    Instruction::new_with_bytes(solana_program::ed25519_program::id(), &data, vec![])
+}
+
+/// Appends the accounts a Token-2022 transfer hook needs to a wallet instruction that moves
+/// tokens out of the wallet (`ExecuteTransaction`, `ExecuteBatch`, `Withdraw`, `CrankStandingOrder`).
+/// Mints without a transfer hook leave the instruction unchanged.
+///
+/// `fetch_account_data_fn` returns the data of an account, for example:
+///
+/// ```rust,ignore
+/// add_transfer_hook_accounts(
+///     &mut instruction,
+///     |address| client.get_account(&address).map_ok(|account| Some(account.data)),
+///     &wallet_token_account,
+///     &mint,
+///     &recipient_token_account,
+///     &wallet,
+///     amount,
+/// ).await?;
+/// ```
+pub async fn add_transfer_hook_accounts<F, Fut>(
+    instruction: &mut Instruction,
+    fetch_account_data_fn: F,
+    wallet_token_account: &Pubkey,
+    mint: &Pubkey,
+    destination: &Pubkey,
+    wallet: &Pubkey,
+    amount: u64,
+) -> Result<(), AccountFetchError>
+where
+    F: Fn(Pubkey) -> Fut,
+    Fut: Future<Output = AccountDataResult>,
+{
+    // Resolve against the transfer the wallet will make on-chain (decimals do not affect the
+    // resolved accounts), then keep only the extra accounts
+    let mut transfer_ix = spl_token_2022::instruction::transfer_checked(
+        &spl_token_2022::id(),
+        wallet_token_account,
+        mint,
+        destination,
+        wallet,
+        &[],
+        amount,
+        0,
+    )?;
+    let base_accounts = transfer_ix.accounts.len();
+
+    resolve_extra_transfer_account_metas(&mut transfer_ix, fetch_account_data_fn, mint).await?;
+
+    instruction
+        .accounts
+        .extend(transfer_ix.accounts.into_iter().skip(base_accounts));
+    Ok(())
+}
+
+/// A `getProgramAccounts` memcmp filter: the account data must contain `bytes` at `offset`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MemcmpFilter {
+    pub offset: usize,
+    pub bytes: Vec<u8>,
+}
+
+fn approval_filters(key_offset: usize, key: &Pubkey) -> Vec<MemcmpFilter> {
+    vec![
+        MemcmpFilter {
+            offset: 0,
+            bytes: vec![AccountKind::DAppApproval as u8, DAppApproval::VERSION],
+        },
+        MemcmpFilter {
+            offset: key_offset,
+            bytes: key.to_bytes().to_vec(),
+        },
+    ]
+}
+
+/// Filters matching every approval granted by `wallet`. Approvals still on a layout older
+/// than the current one don't store their wallet and are only found once migrated.
+pub fn approvals_for_wallet_filters(wallet: &Pubkey) -> Vec<MemcmpFilter> {
+    approval_filters(APPROVAL_WALLET_OFFSET, wallet)
+}
+
+/// Filters matching every approval granted to `dapp`, across all wallets
+pub fn approvals_for_dapp_filters(dapp: &Pubkey) -> Vec<MemcmpFilter> {
+    approval_filters(APPROVAL_DAPP_OFFSET, dapp)
+}
+
+/// Decodes the data of an approval account, whatever its layout version
+pub fn decode_approval(data: &[u8]) -> Result<DAppApproval, ProgramError> {
+    unpack_account_data(data)
+}
+
+async fn get_approvals<F, Fut, E>(get_program_accounts_fn: F, filters: Vec<MemcmpFilter>) -> Result<Vec<(Pubkey, DAppApproval)>, E>
+where
+    F: FnOnce(Vec<MemcmpFilter>) -> Fut,
+    Fut: Future<Output = Result<Vec<(Pubkey, Vec<u8>)>, E>>,
+{
+    let accounts = get_program_accounts_fn(filters).await?;
+    Ok(accounts
+        .into_iter()
+        .filter_map(|(address, data)| decode_approval(&data).ok().map(|approval| (address, approval)))
+        .collect())
+}
+
+/// Fetches and decodes all approvals granted by `wallet`.
+///
+/// `get_program_accounts_fn` runs `getProgramAccounts` for the wallet program with the given
+/// memcmp filters and returns each account's address and data, for example:
+///
+/// ```rust,ignore
+/// let approvals = get_approvals_for_wallet(
+///     |filters| async move {
+///         let filters = filters
+///             .into_iter()
+///             .map(|filter| RpcFilterType::Memcmp(Memcmp::new_raw_bytes(filter.offset, filter.bytes)))
+///             .collect();
+///         let config = RpcProgramAccountsConfig { filters: Some(filters), ..Default::default() };
+///         client
+///             .get_program_accounts_with_config(&program_id, config)
+///             .await
+///             .map(|accounts| accounts.into_iter().map(|(address, account)| (address, account.data)).collect())
+///     },
+///     &wallet,
+/// ).await?;
+/// ```
+pub async fn get_approvals_for_wallet<F, Fut, E>(get_program_accounts_fn: F, wallet: &Pubkey) -> Result<Vec<(Pubkey, DAppApproval)>, E>
+where
+    F: FnOnce(Vec<MemcmpFilter>) -> Fut,
+    Fut: Future<Output = Result<Vec<(Pubkey, Vec<u8>)>, E>>,
+{
+    get_approvals(get_program_accounts_fn, approvals_for_wallet_filters(wallet)).await
+}
+
+/// Fetches and decodes all approvals granted to `dapp`; see `get_approvals_for_wallet`
+pub async fn get_approvals_for_dapp<F, Fut, E>(get_program_accounts_fn: F, dapp: &Pubkey) -> Result<Vec<(Pubkey, DAppApproval)>, E>
+where
+    F: FnOnce(Vec<MemcmpFilter>) -> Fut,
+    Fut: Future<Output = Result<Vec<(Pubkey, Vec<u8>)>, E>>,
+{
+    get_approvals(get_program_accounts_fn, approvals_for_dapp_filters(dapp)).await
+}
+
+/// Why a wallet instruction failed. The kind of failure comes from the error code the
+/// program returned; the amounts and times in it come from the reason the program logged,
+/// and are `None` when the logs don't include it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WalletError {
+    /// The owner paused or revoked the dApp's approval
+    ApprovalRevoked,
+    ApprovalExpired { expiry: Option<i64> },
+    ApprovalNotYetValid { valid_from: Option<i64> },
+    /// The approval has run `executions` times, its maximum
+    ExecutionLimitReached { executions: Option<u64> },
+    /// The transfer debits `amount`, including any token transfer fees, which is more than
+    /// the `max_amount` the approval allows per transaction
+    AllowanceExceeded { amount: Option<u64>, max_amount: Option<u64> },
+    /// The transfer is worth `value` USD, above the approval's `max_usd`; both are
+    /// fixed-point with `price::USD_DECIMALS` decimals
+    UsdLimitExceeded { value: Option<u64>, max_usd: Option<u64> },
+    /// The amount is above the approval's auto limit and has to be proposed for the owner
+    /// to confirm
+    CosignRequired,
+    RecipientNotAllowed { recipient: Option<Pubkey> },
+    /// The wallet can't pay the amount and fees while staying rent exempt
+    InsufficientFunds,
+    /// Any other failure of the wallet program, with the last message it logged
+    Program { error: ProgramError, message: Option<String> },
+    /// The transaction failed outside the wallet program, for example in another of its
+    /// instructions or because the fee payer has no SOL
+    Transaction(String),
+    /// The transaction could not be simulated, for example because the RPC request failed
+    Simulation(String),
+}
+
+impl fmt::Display for WalletError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::ApprovalRevoked => write!(f, "the dApp's approval has been revoked"),
+            Self::ApprovalExpired { expiry: Some(expiry) } => write!(f, "the approval expired at {} (Unix time)", expiry),
+            Self::ApprovalExpired { expiry: None } => write!(f, "the approval has expired"),
+            Self::ApprovalNotYetValid { valid_from: Some(valid_from) } => {
+                write!(f, "the approval is not valid until {} (Unix time)", valid_from)
+            }
+            Self::ApprovalNotYetValid { valid_from: None } => write!(f, "the approval is not valid yet"),
+            Self::ExecutionLimitReached { executions: Some(executions) } => {
+                write!(f, "the approval has been used {} times, its maximum", executions)
+            }
+            Self::ExecutionLimitReached { executions: None } => write!(f, "the approval has been used its maximum number of times"),
+            Self::AllowanceExceeded { amount: Some(amount), max_amount: Some(max_amount) } => {
+                write!(f, "the transfer debits {}, more than the {} the approval allows", amount, max_amount)
+            }
+            Self::AllowanceExceeded { .. } => write!(f, "the transfer debits more than the approval allows"),
+            Self::UsdLimitExceeded { value: Some(value), max_usd: Some(max_usd) } => {
+                write!(f, "the transfer is worth {} USD micro-units, more than the limit of {}", value, max_usd)
+            }
+            Self::UsdLimitExceeded { .. } => write!(f, "the transfer is worth more than the approval's USD limit"),
+            Self::CosignRequired => write!(f, "the amount is above the auto limit and needs the owner's co-signature"),
+            Self::RecipientNotAllowed { recipient: Some(recipient) } => {
+                write!(f, "the approval does not allow sending to {}", recipient)
+            }
+            Self::RecipientNotAllowed { recipient: None } => write!(f, "the approval does not allow sending to the recipient"),
+            Self::InsufficientFunds => write!(f, "the wallet does not have enough balance"),
+            Self::Program { error, message: Some(message) } => write!(f, "{} ({})", message, error),
+            Self::Program { error, message: None } => write!(f, "{}", error),
+            Self::Transaction(error) => write!(f, "{}", error),
+            Self::Simulation(error) => write!(f, "simulation failed: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for WalletError {}
+
+/// What `simulateTransaction` reported for a transaction
+#[derive(Clone, Debug, Default)]
+pub struct SimulationOutcome {
+    /// Index and error of the instruction that failed, if one did
+    pub instruction_error: Option<(u8, InstructionError)>,
+    /// Any other transaction error
+    pub transaction_error: Option<String>,
+    pub logs: Vec<String>,
+    pub units_consumed: Option<u64>,
+}
+
+/// Simulates a transaction made of `instructions` with `simulate` and returns the compute
+/// units it consumed, or why it failed. Failures of the wallet program are decoded into the
+/// matching `WalletError`, for example:
+///
+/// ```rust,ignore
+/// let units = check_simulation(&program_id, &instructions, |instructions| {
+///     let transaction = Transaction::new_signed_with_payer(instructions, Some(&payer.pubkey()), &[&payer], blockhash);
+///     let response = client.simulate_transaction(&transaction)?.value;
+///     Ok::<_, ClientError>(SimulationOutcome {
+///         instruction_error: match &response.err {
+///             Some(TransactionError::InstructionError(index, error)) => Some((*index, error.clone())),
+///             _ => None,
+///         },
+///         transaction_error: response.err.map(|error| error.to_string()),
+///         logs: response.logs.unwrap_or_default(),
+///         units_consumed: response.units_consumed,
+///     })
+/// })?;
+/// ```
+pub fn check_simulation<E: fmt::Display>(
+    program_id: &Pubkey,
+    instructions: &[Instruction],
+    simulate: impl FnOnce(&[Instruction]) -> Result<SimulationOutcome, E>,
+) -> Result<u64, WalletError> {
+    let outcome = simulate(instructions).map_err(|error| WalletError::Simulation(error.to_string()))?;
+
+    let Some((index, error)) = &outcome.instruction_error else {
+        return match &outcome.transaction_error {
+            Some(error) => Err(WalletError::Transaction(error.clone())),
+            None => Ok(outcome.units_consumed.unwrap_or_default()),
+        };
+    };
+
+    let failed_program = instructions.get(*index as usize).map(|instruction| instruction.program_id);
+    if failed_program != Some(*program_id) {
+        return Err(WalletError::Transaction(format!("instruction {} failed: {}", index, error)));
+    }
+    let error = match ProgramError::try_from(error.clone()) {
+        Ok(error) => error,
+        Err(error) => return Err(WalletError::Transaction(format!("instruction {} failed: {}", index, error))),
+    };
+
+    // The processor logs the reason right before it fails
+    let messages = program_messages(program_id, &outcome.logs);
+    let message = messages.last().copied();
+    Err(match error {
+        ProgramError::Custom(code) => match WalletErrorCode::from_code(code) {
+            Some(code) => decode_wallet_error(code, message),
+            None => WalletError::Program { error, message: message.map(str::to_string) },
+        },
+        ProgramError::InsufficientFunds => WalletError::InsufficientFunds,
+        error => WalletError::Program { error, message: message.map(str::to_string) },
+    })
+}
+
+/// The messages the wallet program itself logged in its last top-level invocation, leaving
+/// out those of the programs it called
+fn program_messages<'a>(program_id: &Pubkey, logs: &'a [String]) -> Vec<&'a str> {
+    let invoke = format!("Program {} invoke [1]", program_id);
+    let Some(start) = logs.iter().rposition(|line| *line == invoke) else {
+        return Vec::new();
+    };
+
+    let mut depth = 0;
+    let mut messages = Vec::new();
+    for line in &logs[start..] {
+        if line.starts_with("Program ") && line.contains(" invoke [") {
+            depth += 1;
+        } else if line.starts_with("Program ") && (line.ends_with(" success") || line.contains(" failed: ")) {
+            depth -= 1;
+        } else if let Some(message) = line.strip_prefix("Program log: ") {
+            if depth == 1 {
+                messages.push(message);
+            }
+        }
+    }
+    messages
+}
+
+/// The `WalletError` for a wallet error code, with the details the processor logged in
+/// `message`
+fn decode_wallet_error(code: WalletErrorCode, message: Option<&str>) -> WalletError {
+    let message = message.unwrap_or_default();
+    match code {
+        WalletErrorCode::ApprovalRevoked => WalletError::ApprovalRevoked,
+        WalletErrorCode::ApprovalExpired => WalletError::ApprovalExpired { expiry: logged_number(message, "expired at ", 0) },
+        WalletErrorCode::ApprovalNotYetValid => {
+            WalletError::ApprovalNotYetValid { valid_from: logged_number(message, "not valid until ", 0) }
+        }
+        WalletErrorCode::ExecutionLimitReached => {
+            WalletError::ExecutionLimitReached { executions: logged_number(message, "has been used ", 0) }
+        }
+        WalletErrorCode::AllowanceExceeded => WalletError::AllowanceExceeded {
+            amount: logged_number(message, "greater than the max amount of ", 0),
+            max_amount: logged_number(message, "greater than the max amount of ", 1),
+        },
+        WalletErrorCode::UsdLimitExceeded => WalletError::UsdLimitExceeded {
+            value: logged_number(message, "Amount is worth ", 0),
+            max_usd: logged_number(message, "Amount is worth ", 1),
+        },
+        WalletErrorCode::CosignRequired => WalletError::CosignRequired,
+        WalletErrorCode::RecipientNotAllowed => WalletError::RecipientNotAllowed {
+            recipient: message
+                .strip_prefix("Recipient ")
+                .and_then(|rest| rest.strip_suffix(" is not allowed by the approval"))
+                .and_then(|recipient| recipient.parse().ok()),
+        },
+    }
+}
+
+/// The `index`th number in a logged `message` that contains `marker`. Numbers keep their
+/// sign, so negative timestamps decode as such
+fn logged_number<T: TryFrom<i128>>(message: &str, marker: &str, index: usize) -> Option<T> {
+    if !message.contains(marker) {
+        return None;
+    }
+    let number = message
+        .split(|c: char| !c.is_ascii_digit() && c != '-')
+        .filter_map(|number| number.parse::<i128>().ok())
+        .nth(index)?;
+    T::try_from(number).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{APPROVAL_CREATED_AT_OFFSET, APPROVAL_OWNER_OFFSET};
+
+    fn matches(filters: &[MemcmpFilter], data: &[u8]) -> bool {
+        filters.iter().all(|filter| data.get(filter.offset..filter.offset + filter.bytes.len()) == Some(&filter.bytes[..]))
+    }
+
+    #[test]
+    fn approval_filters_match_the_stored_keys() {
+        let (wallet, dapp, owner) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let approval = DAppApproval::new(wallet, dapp, owner, 1_700_000_000, 100, 1_800_000_000, Pubkey::new_unique());
+        let mut data = vec![AccountKind::DAppApproval as u8, DAppApproval::VERSION];
+        data.extend_from_slice(bytemuck::bytes_of(&approval));
+
+        assert!(matches(&approvals_for_wallet_filters(&wallet), &data));
+        assert!(matches(&approvals_for_dapp_filters(&dapp), &data));
+        assert!(!matches(&approvals_for_wallet_filters(&dapp), &data));
+        assert!(!matches(&approvals_for_dapp_filters(&wallet), &data));
+        assert_eq!(data[APPROVAL_OWNER_OFFSET..APPROVAL_OWNER_OFFSET + 32], owner.to_bytes());
+        assert_eq!(data[APPROVAL_CREATED_AT_OFFSET..APPROVAL_CREATED_AT_OFFSET + 8], 1_700_000_000i64.to_le_bytes());
+    }
+}
@@ -0,0 +1,69 @@
+//! Errors specific to the wallet program. They are returned as `ProgramError::Custom` with
+//! the variant's code, so clients can tell why an execution was refused without reading the
+//! logs; the processor still logs the details (amounts, timestamps) with `msg!`.
+
+use std::fmt;
+
+use solana_program::program_error::ProgramError;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum WalletErrorCode {
+    /// The owner paused or revoked the dApp's approval
+    ApprovalRevoked = 0,
+    ApprovalExpired = 1,
+    ApprovalNotYetValid = 2,
+    /// The approval has run as many times as it allows
+    ExecutionLimitReached = 3,
+    /// The transfer debits more, including any token transfer fees, than the approval allows
+    /// per transaction
+    AllowanceExceeded = 4,
+    UsdLimitExceeded = 5,
+    /// The amount is above the approval's auto limit and has to be proposed for the owner to
+    /// confirm
+    CosignRequired = 6,
+    RecipientNotAllowed = 7,
+}
+
+impl WalletErrorCode {
+    pub const ALL: [Self; 8] = [
+        Self::ApprovalRevoked,
+        Self::ApprovalExpired,
+        Self::ApprovalNotYetValid,
+        Self::ExecutionLimitReached,
+        Self::AllowanceExceeded,
+        Self::UsdLimitExceeded,
+        Self::CosignRequired,
+        Self::RecipientNotAllowed,
+    ];
+
+    pub fn code(self) -> u32 {
+        self as u32
+    }
+
+    pub fn from_code(code: u32) -> Option<Self> {
+        Self::ALL.into_iter().find(|error| error.code() == code)
+    }
+}
+
+impl fmt::Display for WalletErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            Self::ApprovalRevoked => "The dApp's approval has been revoked",
+            Self::ApprovalExpired => "The approval has expired",
+            Self::ApprovalNotYetValid => "The approval is not valid yet",
+            Self::ExecutionLimitReached => "The approval has been used its maximum number of times",
+            Self::AllowanceExceeded => "The amount is more than the approval allows",
+            Self::UsdLimitExceeded => "The amount is worth more than the approval's USD limit",
+            Self::CosignRequired => "The amount needs the owner's co-signature",
+            Self::RecipientNotAllowed => "The approval does not allow sending to the recipient",
+        };
+        write!(f, "{}", message)
+    }
+}
+
+impl From<WalletErrorCode> for ProgramError {
+    fn from(error: WalletErrorCode) -> Self {
+        ProgramError::Custom(error.code())
+    }
+}
@@ -0,0 +1,81 @@
+use borsh::{BorshDeserialize, BorshSchema, BorshSerialize};
+use solana_program::{log::sol_log_data, program_error::ProgramError, pubkey::Pubkey};
+
+use crate::state::UsdLimit;
+
+/// Structured events written to the transaction logs with `sol_log_data`. Each event is
+/// logged as a single Borsh-encoded field, so indexers decode it as a `WalletEvent`.
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, PartialEq, Eq)]
+pub enum WalletEvent {
+    ApprovalUpdated {
+        approval: Pubkey,
+        is_approved: bool,
+        max_amount: u64,
+        expiry: i64,
+        valid_from: i64,
+        max_executions: u64,
+        single_use: bool,
+        usd_limit: Option<UsdLimit>,
+        auto_limit: u64,
+        cosign_limit: u64,
+        recipients: Vec<Pubkey>,
+        spent_amount: u64,
+        execution_count: u64,
+    },
+    /// A transfer under a dApp approval, by `ExecuteTransaction`, `ExecuteTransactionOnce` or
+    /// a confirmed co-signed execution. `token_mint` is `Pubkey::default()` for SOL, and
+    /// `debited_amount` includes any token transfer fee
+    TransferExecuted {
+        approval: Pubkey,
+        wallet: Pubkey,
+        recipient: Pubkey,
+        token_mint: Pubkey,
+        amount: u64,
+        debited_amount: u64,
+    },
+    /// An `ExecuteBatch`, with the recipient and amount of each transfer
+    BatchExecuted {
+        approval: Pubkey,
+        wallet: Pubkey,
+        token_mint: Pubkey,
+        transfers: Vec<(Pubkey, u64)>,
+        debited_amount: u64,
+    },
+    /// An `ExecuteCpi` into `program_id`, made by the owner or a dApp with a CPI approval
+    CpiExecuted {
+        wallet: Pubkey,
+        authority: Pubkey,
+        program_id: Pubkey,
+    },
+    IntentExecuted {
+        wallet: Pubkey,
+        recipient: Pubkey,
+        token_mint: Pubkey,
+        amount: u64,
+        nonce: u64,
+    },
+    /// A standing order payment made by a crank; the next one is due at `next_due`
+    StandingOrderPaid {
+        order: Pubkey,
+        wallet: Pubkey,
+        recipient: Pubkey,
+        token_mint: Pubkey,
+        amount: u64,
+        next_due: i64,
+    },
+    /// An owner's `Withdraw`. `token_mint` is `Pubkey::default()` for SOL
+    Withdrawn {
+        wallet: Pubkey,
+        recipient: Pubkey,
+        token_mint: Pubkey,
+        amount: u64,
+    },
+}
+
+impl WalletEvent {
+    pub fn emit(&self) -> Result<(), ProgramError> {
+        let data = borsh::to_vec(self).map_err(|_| ProgramError::InvalidAccountData)?;
+        sol_log_data(&[&data]);
+        Ok(())
+    }
+}
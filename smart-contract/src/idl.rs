@@ -0,0 +1,596 @@
+//! Machine-readable description of the program interface (IDL): instructions with their
+//! arguments and accounts, account layouts, errors and events. Argument, type and event
+//! layouts come from the Borsh schemas of the Rust definitions and account layouts from the
+//! `Pod` structs, so the IDL follows them. `idl.json` at the crate root is the generated
+//! file that clients read; the `idl` test keeps it up to date.
+
+use std::mem::{offset_of, size_of};
+
+use borsh::{
+    schema::{BorshSchemaContainer, Definition, Fields},
+    BorshSchema,
+};
+use serde_json::{json, Value};
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+use spl_pod::primitives::{PodBool, PodI64, PodU64};
+
+use crate::{
+    error::WalletErrorCode,
+    event::WalletEvent,
+    instruction::{TransferType, WalletInstruction, INSTRUCTION_VERSION},
+    state::{
+        AccountKind, CpiApproval, DAppApproval, ExecutionReceipt, PendingExecution, ProgramAccount, StandingOrder,
+        UsdLimit, UsedPermit, WalletState, ACCOUNT_HEADER_SIZE,
+    },
+};
+
+/// An account an instruction expects, in order
+pub struct IdlAccount {
+    pub name: &'static str,
+    pub signer: bool,
+    pub writable: bool,
+    /// When set, the account is only passed if this condition holds
+    pub when: Option<&'static str>,
+}
+
+const fn account(name: &'static str, signer: bool, writable: bool) -> IdlAccount {
+    IdlAccount { name, signer, writable, when: None }
+}
+
+const fn optional(name: &'static str, writable: bool, when: &'static str) -> IdlAccount {
+    IdlAccount { name, signer: false, writable, when: Some(when) }
+}
+
+/// The accounts of an instruction, followed by a description of any variable-length tail
+pub struct InstructionAccounts {
+    pub accounts: Vec<IdlAccount>,
+    pub remaining: Option<&'static str>,
+}
+
+const SYSTEM_PROGRAM: IdlAccount = account("system_program", false, false);
+const PRICE_FEED: IdlAccount = optional("price_feed", false, "the approval has a USD limit");
+const TOKEN_MINT: IdlAccount = optional("token_mint", false, "transfer_type is Token");
+const WALLET_TOKEN_ACCOUNT: IdlAccount = optional("wallet_token_account", true, "transfer_type is Token");
+const TOKEN_PROGRAM: IdlAccount = optional("token_program", false, "transfer_type is Token");
+const TRANSFER_HOOK_ACCOUNTS: &str = "Any transfer-hook accounts the mint requires";
+
+/// Accounts of each instruction, as the processor reads them. Keep in sync with the
+/// `Accounts expected` docs of `WalletInstruction`
+pub fn instruction_accounts(instruction: &str) -> Option<InstructionAccounts> {
+    let (accounts, remaining) = match instruction {
+        "CreateWallet" => (
+            vec![account("owner", true, true), account("wallet", false, true), SYSTEM_PROGRAM],
+            None,
+        ),
+        "ApproveDapp" => (
+            vec![
+                account("owner", true, true),
+                account("wallet", false, false),
+                account("dapp", false, false),
+                account("token_mint", false, false),
+                account("approval", false, true),
+                SYSTEM_PROGRAM,
+            ],
+            None,
+        ),
+        "ExecuteTransaction" => (
+            vec![
+                account("dapp", true, true),
+                account("owner", false, true),
+                account("wallet", false, true),
+                account("approval", false, true),
+                account("recipient", false, true),
+                SYSTEM_PROGRAM,
+                PRICE_FEED,
+                TOKEN_MINT,
+                WALLET_TOKEN_ACCOUNT,
+                TOKEN_PROGRAM,
+            ],
+            Some(TRANSFER_HOOK_ACCOUNTS),
+        ),
+        "Withdraw" => (
+            vec![
+                account("owner", true, false),
+                account("wallet", false, true),
+                account("recipient", false, true),
+                SYSTEM_PROGRAM,
+                TOKEN_MINT,
+                WALLET_TOKEN_ACCOUNT,
+                TOKEN_PROGRAM,
+            ],
+            Some(TRANSFER_HOOK_ACCOUNTS),
+        ),
+        "CreateStandingOrder" => (
+            vec![
+                account("owner", true, true),
+                account("wallet", false, false),
+                account("recipient", false, false),
+                account("token_mint", false, false),
+                account("standing_order", false, true),
+                SYSTEM_PROGRAM,
+            ],
+            None,
+        ),
+        "CrankStandingOrder" => (
+            vec![
+                account("cranker", true, true),
+                account("wallet", false, true),
+                account("standing_order", false, true),
+                account("recipient", false, true),
+                SYSTEM_PROGRAM,
+                optional("token_mint", false, "the order pays out tokens"),
+                optional("wallet_token_account", true, "the order pays out tokens"),
+                optional("token_program", false, "the order pays out tokens"),
+            ],
+            Some(TRANSFER_HOOK_ACCOUNTS),
+        ),
+        "CancelStandingOrder" => (
+            vec![
+                account("owner", true, true),
+                account("wallet", false, false),
+                account("standing_order", false, true),
+            ],
+            None,
+        ),
+        "ExecuteBatch" => (
+            vec![
+                account("dapp", true, true),
+                account("owner", false, true),
+                account("wallet", false, true),
+                account("approval", false, true),
+                SYSTEM_PROGRAM,
+                PRICE_FEED,
+                TOKEN_MINT,
+                WALLET_TOKEN_ACCOUNT,
+                TOKEN_PROGRAM,
+            ],
+            Some("The writable recipient accounts indexed by the transfers, then any transfer-hook accounts the mint requires"),
+        ),
+        "ApproveCpi" => (
+            vec![
+                account("owner", true, true),
+                account("wallet", false, false),
+                account("dapp", false, false),
+                account("target_program", false, false),
+                account("cpi_approval", false, true),
+                SYSTEM_PROGRAM,
+            ],
+            None,
+        ),
+        "ExecuteCpi" => (
+            vec![
+                account("authority", true, false),
+                account("owner", false, false),
+                account("wallet", false, true),
+                account("cpi_approval", false, false),
+                account("target_program", false, false),
+            ],
+            Some("The accounts of the target instruction, in order"),
+        ),
+        "WrapSol" => (
+            vec![
+                account("owner", true, true),
+                account("wallet", false, true),
+                account("wallet_token_account", false, true),
+                account("native_mint", false, false),
+                account("token_program", false, false),
+                SYSTEM_PROGRAM,
+                account("associated_token_program", false, false),
+            ],
+            None,
+        ),
+        "UnwrapSol" => (
+            vec![
+                account("owner", true, false),
+                account("wallet", false, true),
+                account("wallet_token_account", false, true),
+                account("token_program", false, false),
+            ],
+            None,
+        ),
+        "ExecuteTransactionOnce" => (
+            vec![
+                account("dapp", true, true),
+                account("owner", false, true),
+                account("wallet", false, true),
+                account("approval", false, true),
+                account("recipient", false, true),
+                SYSTEM_PROGRAM,
+                account("receipt", false, true),
+                PRICE_FEED,
+                TOKEN_MINT,
+                WALLET_TOKEN_ACCOUNT,
+                TOKEN_PROGRAM,
+            ],
+            Some(TRANSFER_HOOK_ACCOUNTS),
+        ),
+        "ApproveDappWithPermit" => (
+            vec![
+                account("dapp", true, true),
+                account("owner", false, false),
+                account("wallet", false, false),
+                account("token_mint", false, false),
+                account("approval", false, true),
+                account("used_permit", false, true),
+                SYSTEM_PROGRAM,
+                account("instructions_sysvar", false, false),
+            ],
+            None,
+        ),
+        "ExecuteIntent" => (
+            vec![
+                account("relayer", true, true),
+                account("owner", false, false),
+                account("wallet", false, true),
+                account("recipient", false, true),
+                SYSTEM_PROGRAM,
+                account("instructions_sysvar", false, false),
+                TOKEN_MINT,
+                WALLET_TOKEN_ACCOUNT,
+                TOKEN_PROGRAM,
+            ],
+            Some(TRANSFER_HOOK_ACCOUNTS),
+        ),
+        "UpdateApproval" => (
+            vec![
+                account("owner", true, true),
+                account("wallet", false, false),
+                account("dapp", false, false),
+                account("approval", false, true),
+                SYSTEM_PROGRAM,
+            ],
+            None,
+        ),
+        "MigrateAccount" => (
+            vec![
+                account("payer", true, true),
+                account("account", false, true),
+                SYSTEM_PROGRAM,
+                optional("wallet", false, "kind is DAppApproval"),
+                optional("dapp", false, "kind is DAppApproval"),
+                optional("owner", false, "kind is Wallet or DAppApproval"),
+            ],
+            None,
+        ),
+        "ProposeExecution" => (
+            vec![
+                account("dapp", true, true),
+                account("owner", false, false),
+                account("wallet", false, false),
+                account("approval", false, false),
+                account("recipient", false, false),
+                account("pending_execution", false, true),
+                SYSTEM_PROGRAM,
+            ],
+            None,
+        ),
+        "ConfirmExecution" => (
+            vec![
+                account("owner", true, true),
+                account("wallet", false, true),
+                account("approval", false, true),
+                account("pending_execution", false, true),
+                account("dapp", false, true),
+                account("recipient", false, true),
+                SYSTEM_PROGRAM,
+                PRICE_FEED,
+                optional("token_mint", false, "the approval is for a token"),
+                optional("wallet_token_account", true, "the approval is for a token"),
+                optional("token_program", false, "the approval is for a token"),
+            ],
+            Some(TRANSFER_HOOK_ACCOUNTS),
+        ),
+        "CancelExecution" => (
+            vec![
+                account("authority", true, false),
+                account("pending_execution", false, true),
+                account("dapp", false, true),
+            ],
+            None,
+        ),
+        "CreateStake" => (
+            vec![
+                account("owner", true, false),
+                account("wallet", false, true),
+                account("stake_account", false, true),
+                SYSTEM_PROGRAM,
+                account("stake_program", false, false),
+                account("rent_sysvar", false, false),
+            ],
+            None,
+        ),
+        "DelegateStake" => (
+            vec![
+                account("owner", true, false),
+                account("wallet", false, false),
+                account("stake_account", false, true),
+                account("vote_account", false, false),
+                account("clock_sysvar", false, false),
+                account("stake_history_sysvar", false, false),
+                account("stake_config", false, false),
+                account("stake_program", false, false),
+            ],
+            None,
+        ),
+        "DeactivateStake" => (
+            vec![
+                account("owner", true, false),
+                account("wallet", false, false),
+                account("stake_account", false, true),
+                account("clock_sysvar", false, false),
+                account("stake_program", false, false),
+            ],
+            None,
+        ),
+        "WithdrawStake" => (
+            vec![
+                account("owner", true, false),
+                account("wallet", false, true),
+                account("stake_account", false, true),
+                account("clock_sysvar", false, false),
+                account("stake_history_sysvar", false, false),
+                account("stake_program", false, false),
+            ],
+            None,
+        ),
+        "CancelPermit" => (
+            vec![
+                account("owner", true, true),
+                account("wallet", false, false),
+                account("used_permit", false, true),
+                SYSTEM_PROGRAM,
+            ],
+            None,
+        ),
+        _ => return None,
+    };
+    Some(InstructionAccounts { accounts, remaining })
+}
+
+/// Builtin errors the program returns, besides its own `WalletErrorCode`s. The reason for a
+/// failure is logged with `msg!`
+pub fn program_errors() -> Vec<ProgramError> {
+    vec![
+        ProgramError::InvalidArgument,
+        ProgramError::InvalidInstructionData,
+        ProgramError::InvalidAccountData,
+        ProgramError::AccountDataTooSmall,
+        ProgramError::InsufficientFunds,
+        ProgramError::IncorrectProgramId,
+        ProgramError::MissingRequiredSignature,
+        ProgramError::AccountAlreadyInitialized,
+        ProgramError::NotEnoughAccountKeys,
+        ProgramError::AccountBorrowFailed,
+        ProgramError::UnsupportedSysvar,
+        ProgramError::IllegalOwner,
+        ProgramError::ArithmeticOverflow,
+    ]
+}
+
+/// Converts a Borsh schema declaration to an IDL type
+fn idl_type(schema: &BorshSchemaContainer, declaration: &str) -> Value {
+    if declaration == Pubkey::declaration() {
+        return json!("publicKey");
+    }
+    match schema.get_definition(declaration) {
+        Some(Definition::Primitive(_)) | None => json!(declaration),
+        Some(Definition::Sequence { length_width: Definition::ARRAY_LENGTH_WIDTH, length_range, elements }) => {
+            json!({ "array": [idl_type(schema, elements), length_range.end()] })
+        }
+        Some(Definition::Sequence { elements, .. }) => json!({ "vec": idl_type(schema, elements) }),
+        Some(Definition::Tuple { elements }) => {
+            json!({ "tuple": elements.iter().map(|element| idl_type(schema, element)).collect::<Vec<_>>() })
+        }
+        Some(Definition::Enum { variants, .. }) if declaration.starts_with("Option<") => {
+            json!({ "option": idl_type(schema, &variants[1].2) })
+        }
+        Some(_) => json!({ "defined": declaration }),
+    }
+}
+
+fn idl_fields(schema: &BorshSchemaContainer, declaration: &str) -> Vec<Value> {
+    match schema.get_definition(declaration) {
+        Some(Definition::Struct { fields: Fields::NamedFields(fields) }) => fields
+            .iter()
+            .map(|(name, field)| json!({ "name": name, "type": idl_type(schema, field) }))
+            .collect(),
+        _ => vec![],
+    }
+}
+
+/// The variants of a Borsh enum as `(discriminant, name, fields)`
+fn idl_variants<T: BorshSchema>() -> Vec<(i64, String, Vec<Value>)> {
+    let schema = BorshSchemaContainer::for_type::<T>();
+    match schema.get_definition(schema.declaration()) {
+        Some(Definition::Enum { variants, .. }) => variants
+            .iter()
+            .map(|(discriminant, name, variant)| (*discriminant, name.clone(), idl_fields(&schema, variant)))
+            .collect(),
+        _ => vec![],
+    }
+}
+
+fn defined_type<T: BorshSchema>() -> Value {
+    let schema = BorshSchemaContainer::for_type::<T>();
+    match schema.get_definition(schema.declaration()) {
+        Some(Definition::Enum { .. }) => json!({
+            "name": T::declaration(),
+            "kind": "enum",
+            "variants": idl_variants::<T>()
+                .into_iter()
+                .map(|(discriminant, name, _)| json!({ "name": name, "discriminant": discriminant }))
+                .collect::<Vec<_>>(),
+        }),
+        _ => json!({
+            "name": T::declaration(),
+            "kind": "struct",
+            "fields": idl_fields(&schema, schema.declaration()),
+        }),
+    }
+}
+
+/// IDL type of a field of a `Pod` account
+trait PodType {
+    fn idl_type() -> Value;
+}
+
+impl PodType for Pubkey {
+    fn idl_type() -> Value {
+        json!("publicKey")
+    }
+}
+
+impl PodType for PodU64 {
+    fn idl_type() -> Value {
+        json!("u64")
+    }
+}
+
+impl PodType for PodI64 {
+    fn idl_type() -> Value {
+        json!("i64")
+    }
+}
+
+impl PodType for PodBool {
+    fn idl_type() -> Value {
+        json!("bool")
+    }
+}
+
+impl PodType for u8 {
+    fn idl_type() -> Value {
+        json!("u8")
+    }
+}
+
+impl<T: PodType, const N: usize> PodType for [T; N] {
+    fn idl_type() -> Value {
+        json!({ "array": [T::idl_type(), N] })
+    }
+}
+
+/// Type and size of a field, picked out by `field`
+fn pod_field<A, T: PodType, F: Fn(&A) -> &T>(_field: F) -> (Value, usize) {
+    (T::idl_type(), size_of::<T>())
+}
+
+/// Describes the current layout of a program account. Field offsets include the account
+/// header; every field of the struct has to be listed
+macro_rules! pod_account {
+    ($account:ty { $($field:ident),* $(,)? }) => {{
+        let fields = vec![$({
+            let (field_type, size) = pod_field(|account: &$account| &account.$field);
+            (stringify!($field), field_type, offset_of!($account, $field), size)
+        }),*];
+        pod_account::<$account>(stringify!($account), fields)
+    }};
+}
+
+fn pod_account<T: ProgramAccount>(name: &str, fields: Vec<(&str, Value, usize, usize)>) -> Value {
+    let listed: usize = fields.iter().map(|(_, _, _, size)| size).sum();
+    assert_eq!(listed, size_of::<T>(), "IDL of {} does not list every field", name);
+
+    json!({
+        "name": name,
+        "discriminator": [T::KIND as u8, T::VERSION],
+        "size": T::SIZE,
+        "fields": fields
+            .into_iter()
+            .map(|(name, field_type, offset, _)| json!({
+                "name": name,
+                "type": field_type,
+                "offset": ACCOUNT_HEADER_SIZE + offset,
+            }))
+            .collect::<Vec<_>>(),
+    })
+}
+
+/// Builds the IDL
+pub fn idl() -> Value {
+    let instructions: Vec<Value> = idl_variants::<WalletInstruction>()
+        .into_iter()
+        .map(|(discriminant, name, args)| {
+            let InstructionAccounts { accounts, remaining } = instruction_accounts(&name)
+                .unwrap_or_else(|| panic!("No IDL accounts for instruction {}", name));
+            let accounts: Vec<Value> = accounts
+                .iter()
+                .map(|account| {
+                    let mut value = json!({
+                        "name": account.name,
+                        "signer": account.signer,
+                        "writable": account.writable,
+                    });
+                    if let Some(when) = account.when {
+                        value["when"] = json!(when);
+                    }
+                    value
+                })
+                .collect();
+            json!({
+                "name": name,
+                "discriminant": discriminant,
+                "args": args,
+                "accounts": accounts,
+                "remainingAccounts": remaining,
+            })
+        })
+        .collect();
+
+    let accounts = vec![
+        pod_account!(WalletState { owner, intent_nonce, bump }),
+        pod_account!(DAppApproval {
+            wallet,
+            dapp,
+            owner,
+            created_at,
+            is_approved,
+            max_amount,
+            expiry,
+            token_mint,
+            spent_amount,
+            execution_count,
+            valid_from,
+            max_executions,
+            single_use,
+            usd_price_feed,
+            max_usd,
+            auto_limit,
+            cosign_limit,
+            bump,
+            recipient_count,
+            recipient_slots,
+        }),
+        pod_account!(StandingOrder { owner, recipient, token_mint, amount, interval, next_due }),
+        pod_account!(CpiApproval { is_approved, program_id, max_amount, expiry }),
+        pod_account!(ExecutionReceipt { amount, executed_at }),
+        pod_account!(UsedPermit { used_at }),
+        pod_account!(PendingExecution { approval, owner, dapp, recipient, amount, expires_at }),
+    ];
+
+    let errors: Vec<Value> = WalletErrorCode::ALL
+        .into_iter()
+        .map(|error| json!({ "name": format!("{:?}", error), "msg": error.to_string(), "code": error.code() }))
+        .chain(
+            program_errors()
+                .into_iter()
+                .map(|error| json!({ "name": format!("{:?}", error), "msg": error.to_string(), "code": u64::from(error) })),
+        )
+        .collect();
+
+    let events: Vec<Value> = idl_variants::<WalletEvent>()
+        .into_iter()
+        .map(|(discriminant, name, fields)| json!({ "name": name, "discriminant": discriminant, "fields": fields }))
+        .collect();
+
+    json!({
+        "name": "smart_wallet",
+        "version": env!("CARGO_PKG_VERSION"),
+        "instructionVersion": INSTRUCTION_VERSION,
+        "instructions": instructions,
+        "accounts": accounts,
+        "types": [defined_type::<TransferType>(), defined_type::<UsdLimit>(), defined_type::<AccountKind>()],
+        "errors": errors,
+        "events": events,
+    })
+}
@@ -1,7 +1,17 @@
-use borsh::{BorshDeserialize, BorshSerialize};
-use solana_program::program_error::ProgramError;
+use borsh::{BorshDeserialize, BorshSchema, BorshSerialize};
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
 
-#[derive(BorshSerialize, BorshDeserialize)]
+use crate::state::{AccountKind, UsdLimit};
+
+/// Version of the instruction encoding, sent as the first byte of every instruction
+pub const INSTRUCTION_VERSION: u8 = 1;
+
+/// Instructions are encoded as `[INSTRUCTION_VERSION, tag, Borsh fields]`. Every variant has
+/// a fixed tag, so adding or reordering variants never changes the wire format; new variants
+/// take the next unused tag.
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema)]
+#[borsh(use_discriminant = true)]
+#[repr(u8)]
 pub enum WalletInstruction {
     /// Creates a new wallet
     /// 
@@ -9,30 +19,48 @@ pub enum WalletInstruction {
     /// 1. `[signer]` The account of the person initializing the wallet
     /// 2. `[writable]` The wallet to create
     /// 3. `[]` The system program
-    CreateWallet,
+    CreateWallet = 0,
 
-    /// Approves a dApp to interact with the wallet
+    /// Approves a dApp to interact with the wallet. Fails if the approval already exists; its
+    /// terms change with UpdateApproval
     /// 
     /// Accounts expected:
-    /// 1. `[signer]` The wallet owner
-    /// 2. `[writable]` The wallet account
+    /// 1. `[signer, writable]` The wallet owner
+    /// 2. `[]` The wallet account
     /// 3. `[]` The dApp to approve
+    /// 4. `[]` The token mint (`Pubkey::default()` for SOL)
+    /// 5. `[writable]` The approval account
+    /// 6. `[]` The system program
     ApproveDapp {
         max_amount: u64,
         expiry: i64,
-    },
+    } = 1,
 
-    /// Executes a transaction on behalf of the user
+    /// Executes a transaction on behalf of the user. For Token-2022 mints with a transfer fee,
+    /// `amount` is what the recipient receives; the wallet pays the fee on top and it counts
+    /// against the approval's `max_amount`. For native-mint approvals, any shortfall in the
+    /// wallet's wrapped SOL balance is wrapped from the wallet's lamports first
     /// 
     /// Accounts expected:
-    /// 1. `[writable]` The wallet account
-    /// 2. `[writable]` The recipient account
-    /// 3. `[signer]` The approved dApp's account
-    /// 4. `[]` The system program
+    /// 1. `[signer, writable]` The approved dApp's account
+    /// 2. `[writable]` The wallet owner (receives the rent of a closed single-use approval)
+    /// 3. `[writable]` The wallet account
+    /// 4. `[writable]` The approval account
+    /// 5. `[writable]` The recipient account
+    /// 6. `[]` The system program
+    ///
+    /// If the approval has a USD limit, its `[]` price account comes next
+    ///
+    /// For token transfers, additionally:
+    /// 7. `[]` The token mint
+    /// 8. `[writable]` The wallet's token account
+    /// 9. `[]` The token program
+    ///
+    /// Followed by any transfer-hook accounts the mint requires
     ExecuteTransaction {
         amount: u64,
         transfer_type: TransferType,
-    },
+    } = 2,
 
     /// Withdraws funds from the wallet
     /// 
@@ -41,22 +69,491 @@ pub enum WalletInstruction {
     /// 2. `[writable]` The wallet account
     /// 3. `[writable]` The recipient account (usually the owner's main account)
     /// 4. `[]` The system program
+    ///
+    /// For token withdrawals, additionally:
+    /// 5. `[]` The token mint
+    /// 6. `[writable]` The wallet's token account
+    /// 7. `[]` The token program
+    ///
+    /// Followed by any transfer-hook accounts the mint requires
     Withdraw {
         amount: u64,
         transfer_type: TransferType,
-    },
+    } = 3,
+
+    /// Creates a standing order paying a fixed amount to a recipient every `interval` seconds,
+    /// starting at `start_time`, which must not be in the past
+    ///
+    /// Accounts expected:
+    /// 1. `[signer, writable]` The wallet owner
+    /// 2. `[]` The wallet account
+    /// 3. `[]` The recipient account (the recipient's token account for token orders)
+    /// 4. `[]` The token mint (`Pubkey::default()` for SOL)
+    /// 5. `[writable]` The standing order account
+    /// 6. `[]` The system program
+    CreateStandingOrder {
+        order_id: u64,
+        amount: u64,
+        interval: i64,
+        start_time: i64,
+    } = 4,
+
+    /// Pays out a standing order that is due. Anyone can call this; the caller
+    /// is paid a fixed crank reward from the wallet. Each crank makes one payment:
+    /// intervals missed since the order fell due are skipped, not paid out
+    ///
+    /// Accounts expected:
+    /// 1. `[signer, writable]` The crank caller
+    /// 2. `[writable]` The wallet account
+    /// 3. `[writable]` The standing order account
+    /// 4. `[writable]` The recipient account
+    /// 5. `[]` The system program
+    ///
+    /// For token orders, additionally:
+    /// 6. `[]` The token mint
+    /// 7. `[writable]` The wallet's token account
+    /// 8. `[]` The token program
+    ///
+    /// Followed by any transfer-hook accounts the mint requires
+    CrankStandingOrder = 5,
+
+    /// Cancels a standing order and returns its rent to the owner
+    ///
+    /// Accounts expected:
+    /// 1. `[signer, writable]` The wallet owner
+    /// 2. `[]` The wallet account
+    /// 3. `[writable]` The standing order account
+    CancelStandingOrder = 6,
+
+    /// Executes several transfers on behalf of the user against a single approval.
+    /// Each transfer is `(index, amount)`, where `index` selects a recipient from the
+    /// trailing accounts. The approval's `max_amount` applies to the batch total
+    ///
+    /// Accounts expected:
+    /// 1. `[signer, writable]` The approved dApp's account
+    /// 2. `[writable]` The wallet owner (receives the rent of a closed single-use approval)
+    /// 3. `[writable]` The wallet account
+    /// 4. `[writable]` The approval account
+    /// 5. `[]` The system program
+    ///
+    /// If the approval has a USD limit, its `[]` price account comes next
+    ///
+    /// For token batches, additionally:
+    /// 6. `[]` The token mint
+    /// 7. `[writable]` The wallet's token account
+    /// 8. `[]` The token program
+    ///
+    /// Followed by the `[writable]` recipient accounts the transfers index, then any transfer-hook
+    /// accounts the mint requires
+    ExecuteBatch {
+        transfers: Vec<(u8, u64)>,
+        transfer_type: TransferType,
+    } = 7,
+
+    /// Allows a dApp to make the wallet sign instructions for one target program.
+    /// After each call, no wallet balance may have dropped by more than `max_amount`
+    ///
+    /// Accounts expected:
+    /// 1. `[signer, writable]` The wallet owner
+    /// 2. `[]` The wallet account
+    /// 3. `[]` The dApp to approve
+    /// 4. `[]` The target program
+    /// 5. `[writable]` The CPI approval account
+    /// 6. `[]` The system program
+    ApproveCpi {
+        max_amount: u64,
+        expiry: i64,
+    } = 8,
+
+    /// Invokes an arbitrary instruction on the target program with the wallet PDA as a signer.
+    /// The owner may call any program; a dApp needs a CPI approval for the target program
+    ///
+    /// Accounts expected:
+    /// 1. `[signer]` The wallet owner or an approved dApp
+    /// 2. `[]` The wallet owner
+    /// 3. `[writable]` The wallet account
+    /// 4. `[]` The CPI approval account (ignored when the owner signs)
+    /// 5. `[]` The target program
+    ///
+    /// Followed by the accounts of the target instruction, in order
+    ExecuteCpi {
+        instruction_data: Vec<u8>,
+    } = 9,
+
+    /// Wraps `amount` lamports from the wallet into its native-mint token account,
+    /// creating the token account if needed
+    ///
+    /// Accounts expected:
+    /// 1. `[signer, writable]` The wallet owner
+    /// 2. `[writable]` The wallet account
+    /// 3. `[writable]` The wallet's native-mint associated token account
+    /// 4. `[]` The native mint
+    /// 5. `[]` The token program
+    /// 6. `[]` The system program
+    /// 7. `[]` The associated token account program
+    WrapSol {
+        amount: u64,
+    } = 10,
+
+    /// Closes the wallet's native-mint token account, returning all of its lamports to the wallet
+    ///
+    /// Accounts expected:
+    /// 1. `[signer]` The wallet owner
+    /// 2. `[writable]` The wallet account
+    /// 3. `[writable]` The wallet's native-mint associated token account
+    /// 4. `[]` The token program
+    UnwrapSol = 11,
+
+    /// Same as `ExecuteTransaction`, but records `request_id` in a receipt account so that
+    /// the same request can only ever be executed once
+    ///
+    /// Accounts expected:
+    /// 1. `[signer, writable]` The approved dApp's account (pays the receipt's rent)
+    /// 2. `[writable]` The wallet owner (receives the rent of a closed single-use approval)
+    /// 3. `[writable]` The wallet account
+    /// 4. `[writable]` The approval account
+    /// 5. `[writable]` The recipient account
+    /// 6. `[]` The system program
+    /// 7. `[writable]` The receipt account for `request_id`
+    ///
+    /// Followed by the price account and token accounts of `ExecuteTransaction`, where needed,
+    /// then any transfer-hook accounts
+    ExecuteTransactionOnce {
+        amount: u64,
+        transfer_type: TransferType,
+        request_id: [u8; 32],
+    } = 12,
+
+    /// Approves a dApp using a permit the owner signed off-chain (see `permit::ApprovalPermit`).
+    /// Must directly follow an ed25519 program instruction verifying the owner's signature;
+    /// the dApp submits the transaction and pays for the accounts. Only creates approvals:
+    /// the owner changes an existing one with `UpdateApproval`, and cancels an unused permit
+    /// with `CancelPermit`
+    ///
+    /// Accounts expected:
+    /// 1. `[signer, writable]` The dApp being approved
+    /// 2. `[]` The wallet owner
+    /// 3. `[]` The wallet account
+    /// 4. `[]` The token mint (`Pubkey::default()` for SOL)
+    /// 5. `[writable]` The approval account
+    /// 6. `[writable]` The permit account for `nonce`
+    /// 7. `[]` The system program
+    /// 8. `[]` The instructions sysvar
+    ApproveDappWithPermit {
+        max_amount: u64,
+        expiry: i64,
+        nonce: u64,
+    } = 13,
+
+    /// Executes a transfer intent the owner signed off-chain (see `permit::TransferIntent`).
+    /// Must directly follow an ed25519 program instruction verifying the owner's signature.
+    /// `nonce` must equal the wallet's next intent nonce; like a permit's expiry, the intent
+    /// is rejected from `deadline` on. Any relayer can submit it and is reimbursed from the wallet
+    ///
+    /// Accounts expected:
+    /// 1. `[signer, writable]` The relayer
+    /// 2. `[]` The wallet owner
+    /// 3. `[writable]` The wallet account
+    /// 4. `[writable]` The recipient account
+    /// 5. `[]` The system program
+    /// 6. `[]` The instructions sysvar
+    ///
+    /// Followed by the token accounts of `ExecuteTransaction` for token transfers
+    ExecuteIntent {
+        amount: u64,
+        transfer_type: TransferType,
+        nonce: u64,
+        deadline: i64,
+    } = 14,
+
+    /// Changes an existing approval in place. Fields left as `None` keep their current
+    /// value, and the spent amount and execution count are preserved. `recipients`
+    /// replaces the recipient allowlist (empty allows any recipient); `is_approved`
+    /// pauses or resumes the approval; `valid_from` sets when the approval becomes usable
+    /// (0 for immediately); `max_executions` caps how many times it can be executed (0 for
+    /// no cap); `single_use` closes the approval after its next successful execution;
+    /// `usd_limit` sets (`Some(Some(_))`) or removes (`Some(None)`) a per-transaction USD cap;
+    /// `auto_limit` and `cosign_limit` configure co-signing (see `ProposeExecution`); with
+    /// co-signing enabled they must satisfy `auto_limit <= cosign_limit <= max_amount`
+    ///
+    /// Accounts expected:
+    /// 1. `[signer, writable]` The wallet owner
+    /// 2. `[]` The wallet account
+    /// 3. `[]` The approved dApp
+    /// 4. `[writable]` The approval account
+    /// 5. `[]` The system program
+    UpdateApproval {
+        max_amount: Option<u64>,
+        expiry: Option<i64>,
+        recipients: Option<Vec<Pubkey>>,
+        is_approved: Option<bool>,
+        valid_from: Option<i64>,
+        max_executions: Option<u64>,
+        single_use: Option<bool>,
+        usd_limit: Option<Option<UsdLimit>>,
+        auto_limit: Option<u64>,
+        cosign_limit: Option<u64>,
+    } = 15,
+
+    /// Upgrades a wallet or dApp approval of the first release to the current layout,
+    /// growing it and topping up its rent as needed. Accounts already on the current layout
+    /// are left unchanged; other kinds have no older layouts and are rejected
+    ///
+    /// Accounts expected:
+    /// 1. `[signer, writable]` The payer for any extra rent
+    /// 2. `[writable]` The account to migrate
+    /// 3. `[]` The system program
+    ///
+    /// Followed by, for `Wallet`, the wallet owner; for `DAppApproval`, the wallet account,
+    /// the approved dApp and the wallet owner
+    MigrateAccount {
+        kind: AccountKind,
+    } = 16,
+
+    /// Asks the owner to confirm a transfer above the approval's `auto_limit` and up to its
+    /// `cosign_limit`. Creates a pending execution for `request_id` that the owner confirms
+    /// with `ConfirmExecution` before it expires
+    ///
+    /// Accounts expected:
+    /// 1. `[signer, writable]` The approved dApp's account (pays the pending execution's rent)
+    /// 2. `[]` The wallet owner
+    /// 3. `[]` The wallet account
+    /// 4. `[]` The approval account
+    /// 5. `[]` The recipient account
+    /// 6. `[writable]` The pending execution account for `request_id`
+    /// 7. `[]` The system program
+    ProposeExecution {
+        amount: u64,
+        request_id: [u8; 32],
+    } = 17,
+
+    /// Confirms a pending execution and makes the transfer under the dApp's approval,
+    /// returning the pending execution's rent to the dApp
+    ///
+    /// Accounts expected:
+    /// 1. `[signer, writable]` The wallet owner
+    /// 2. `[writable]` The wallet account
+    /// 3. `[writable]` The approval account
+    /// 4. `[writable]` The pending execution account
+    /// 5. `[writable]` The dApp that proposed the execution
+    /// 6. `[writable]` The recipient account
+    /// 7. `[]` The system program
+    ///
+    /// Followed by the price account and token accounts of `ExecuteTransaction`, where needed
+    ConfirmExecution = 18,
+
+    /// Drops a pending execution, returning its rent to the dApp. Either the wallet owner
+    /// or the dApp can cancel
+    ///
+    /// Accounts expected:
+    /// 1. `[signer]` The wallet owner or the dApp
+    /// 2. `[writable]` The pending execution account
+    /// 3. `[writable]` The dApp that proposed the execution
+    CancelExecution = 19,
+
+    /// Moves `amount` lamports from the wallet into a new stake account for `stake_id`,
+    /// with the wallet as stake and withdraw authority. `amount` plus any lamports already
+    /// at the stake address must cover the stake account's rent-exempt reserve
+    ///
+    /// Accounts expected:
+    /// 1. `[signer]` The wallet owner
+    /// 2. `[writable]` The wallet account
+    /// 3. `[writable]` The stake account for `stake_id`
+    /// 4. `[]` The system program
+    /// 5. `[]` The stake program
+    /// 6. `[]` The rent sysvar
+    CreateStake {
+        stake_id: u64,
+        amount: u64,
+    } = 20,
+
+    /// Delegates one of the wallet's stake accounts to a vote account
+    ///
+    /// Accounts expected:
+    /// 1. `[signer]` The wallet owner
+    /// 2. `[]` The wallet account
+    /// 3. `[writable]` The stake account for `stake_id`
+    /// 4. `[]` The vote account
+    /// 5. `[]` The clock sysvar
+    /// 6. `[]` The stake history sysvar
+    /// 7. `[]` The stake config account
+    /// 8. `[]` The stake program
+    DelegateStake {
+        stake_id: u64,
+    } = 21,
+
+    /// Deactivates one of the wallet's stake accounts
+    ///
+    /// Accounts expected:
+    /// 1. `[signer]` The wallet owner
+    /// 2. `[]` The wallet account
+    /// 3. `[writable]` The stake account for `stake_id`
+    /// 4. `[]` The clock sysvar
+    /// 5. `[]` The stake program
+    DeactivateStake {
+        stake_id: u64,
+    } = 22,
+
+    /// Withdraws `amount` lamports from one of the wallet's stake accounts back to the wallet
+    ///
+    /// Accounts expected:
+    /// 1. `[signer]` The wallet owner
+    /// 2. `[writable]` The wallet account
+    /// 3. `[writable]` The stake account for `stake_id`
+    /// 4. `[]` The clock sysvar
+    /// 5. `[]` The stake history sysvar
+    /// 6. `[]` The stake program
+    WithdrawStake {
+        stake_id: u64,
+        amount: u64,
+    } = 23,
+
+    /// Burns a permit nonce, so that a permit the owner signed with it can no longer approve
+    /// anything
+    ///
+    /// Accounts expected:
+    /// 1. `[signer, writable]` The wallet owner (pays for the permit account)
+    /// 2. `[]` The wallet account
+    /// 3. `[writable]` The permit account for `nonce`
+    /// 4. `[]` The system program
+    CancelPermit {
+        nonce: u64,
+    } = 24,
 }
 
-#[derive(BorshSerialize, BorshDeserialize)]
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema)]
+#[borsh(use_discriminant = true)]
+#[repr(u8)]
 pub enum TransferType {
-    Sol,
-    Token,
+    Sol = 0,
+    Token = 1,
 }
 
 impl WalletInstruction {
     /// Unpacks a byte buffer into a WalletInstruction
     pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
-        WalletInstruction::try_from_slice(input)
+        let (&version, data) = input
+            .split_first()
+            .ok_or(ProgramError::InvalidInstructionData)?;
+        if version != INSTRUCTION_VERSION {
+            return Err(ProgramError::InvalidInstructionData);
+        }
+
+        WalletInstruction::try_from_slice(data)
             .map_err(|_| ProgramError::InvalidInstructionData)
     }
+
+    /// Packs a WalletInstruction into instruction data
+    pub fn pack(&self) -> Vec<u8> {
+        let mut data = vec![INSTRUCTION_VERSION];
+        // Writing to a Vec can't fail
+        self.serialize(&mut data).unwrap();
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Encoded bytes of every variant, as deployed clients send them. These must never
+    // change; a failure here means the wire format broke.
+    fn check(instruction: WalletInstruction, expected: &[&[u8]]) {
+        let expected = expected.concat();
+        assert_eq!(instruction.pack(), expected);
+        assert_eq!(WalletInstruction::unpack(&expected).unwrap().pack(), expected);
+    }
+
+    const ONE: [u8; 8] = [1, 0, 0, 0, 0, 0, 0, 0];
+    const TWO: [u8; 8] = [2, 0, 0, 0, 0, 0, 0, 0];
+    const MINUS_ONE: [u8; 8] = [0xff; 8];
+    const ID: [u8; 32] = [9; 32];
+    const KEY: [u8; 32] = [7; 32];
+
+    #[test]
+    fn encoding_is_stable() {
+        check(WalletInstruction::CreateWallet, &[&[1, 0]]);
+        check(WalletInstruction::ApproveDapp { max_amount: 1, expiry: -1 }, &[&[1, 1], &ONE, &MINUS_ONE]);
+        check(
+            WalletInstruction::ExecuteTransaction { amount: 1, transfer_type: TransferType::Token },
+            &[&[1, 2], &ONE, &[1]],
+        );
+        check(
+            WalletInstruction::Withdraw { amount: 1, transfer_type: TransferType::Sol },
+            &[&[1, 3], &ONE, &[0]],
+        );
+        check(
+            WalletInstruction::CreateStandingOrder { order_id: 1, amount: 2, interval: 1, start_time: -1 },
+            &[&[1, 4], &ONE, &TWO, &ONE, &MINUS_ONE],
+        );
+        check(WalletInstruction::CrankStandingOrder, &[&[1, 5]]);
+        check(WalletInstruction::CancelStandingOrder, &[&[1, 6]]);
+        check(
+            WalletInstruction::ExecuteBatch { transfers: vec![(3, 1)], transfer_type: TransferType::Sol },
+            &[&[1, 7], &[1, 0, 0, 0], &[3], &ONE, &[0]],
+        );
+        check(WalletInstruction::ApproveCpi { max_amount: 1, expiry: -1 }, &[&[1, 8], &ONE, &MINUS_ONE]);
+        check(
+            WalletInstruction::ExecuteCpi { instruction_data: vec![0xab, 0xcd] },
+            &[&[1, 9], &[2, 0, 0, 0], &[0xab, 0xcd]],
+        );
+        check(WalletInstruction::WrapSol { amount: 1 }, &[&[1, 10], &ONE]);
+        check(WalletInstruction::UnwrapSol, &[&[1, 11]]);
+        check(
+            WalletInstruction::ExecuteTransactionOnce { amount: 1, transfer_type: TransferType::Sol, request_id: ID },
+            &[&[1, 12], &ONE, &[0], &ID],
+        );
+        check(
+            WalletInstruction::ApproveDappWithPermit { max_amount: 1, expiry: -1, nonce: 2 },
+            &[&[1, 13], &ONE, &MINUS_ONE, &TWO],
+        );
+        check(
+            WalletInstruction::ExecuteIntent { amount: 1, transfer_type: TransferType::Token, nonce: 2, deadline: -1 },
+            &[&[1, 14], &ONE, &[1], &TWO, &MINUS_ONE],
+        );
+        check(
+            WalletInstruction::UpdateApproval {
+                max_amount: Some(1),
+                expiry: None,
+                recipients: Some(vec![Pubkey::new_from_array(KEY)]),
+                is_approved: Some(false),
+                valid_from: None,
+                max_executions: Some(2),
+                single_use: None,
+                usd_limit: Some(Some(UsdLimit { price_feed: Pubkey::new_from_array(KEY), max_usd: 1 })),
+                auto_limit: None,
+                cosign_limit: Some(2),
+            },
+            &[
+                &[1, 15],
+                &[1], &ONE,
+                &[0],
+                &[1], &[1, 0, 0, 0], &KEY,
+                &[1, 0],
+                &[0],
+                &[1], &TWO,
+                &[0],
+                &[1, 1], &KEY, &ONE,
+                &[0],
+                &[1], &TWO,
+            ],
+        );
+        check(WalletInstruction::MigrateAccount { kind: AccountKind::DAppApproval }, &[&[1, 16], &[2]]);
+        check(WalletInstruction::ProposeExecution { amount: 1, request_id: ID }, &[&[1, 17], &ONE, &ID]);
+        check(WalletInstruction::ConfirmExecution, &[&[1, 18]]);
+        check(WalletInstruction::CancelExecution, &[&[1, 19]]);
+        check(WalletInstruction::CreateStake { stake_id: 1, amount: 2 }, &[&[1, 20], &ONE, &TWO]);
+        check(WalletInstruction::DelegateStake { stake_id: 1 }, &[&[1, 21], &ONE]);
+        check(WalletInstruction::DeactivateStake { stake_id: 1 }, &[&[1, 22], &ONE]);
+        check(WalletInstruction::WithdrawStake { stake_id: 1, amount: 2 }, &[&[1, 23], &ONE, &TWO]);
+        check(WalletInstruction::CancelPermit { nonce: 2 }, &[&[1, 24], &TWO]);
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        assert!(WalletInstruction::unpack(&[]).is_err());
+        assert!(WalletInstruction::unpack(&[0, 0]).is_err());
+        assert!(WalletInstruction::unpack(&[2, 0]).is_err());
+        assert!(WalletInstruction::unpack(&[INSTRUCTION_VERSION, 0xff]).is_err());
+    }
 }
@@ -3,14 +3,35 @@ use solana_program::{
     entrypoint,
     entrypoint::ProgramResult,
     pubkey::Pubkey,
-    msg,
 };
 
+/// `msg!` for detailed diagnostics, compiled in only with the `debug-logs` feature.
+/// Production builds keep just the structured events and the reason an instruction failed.
+#[cfg(feature = "debug-logs")]
+macro_rules! debug_msg {
+    ($($arg:tt)*) => {
+        solana_program::msg!($($arg)*)
+    };
+}
+
+#[cfg(not(feature = "debug-logs"))]
+macro_rules! debug_msg {
+    ($($arg:tt)*) => {};
+}
+
+#[cfg(not(target_os = "solana"))]
+pub mod client;
+pub mod error;
+pub mod event;
+#[cfg(not(target_os = "solana"))]
+pub mod idl;
 pub mod instruction;
+pub mod permit;
+pub mod price;
 mod processor;
-mod state;
+pub mod state;
 use instruction::WalletInstruction;
-use processor::{approve_dapp, create_wallet, execute_transaction, withdraw};
+use processor::{approve_cpi, approve_dapp, approve_dapp_with_permit, cancel_execution, cancel_permit, cancel_standing_order, confirm_execution, crank_standing_order, create_stake, create_standing_order, create_wallet, deactivate_stake, delegate_stake, execute_batch, execute_cpi, execute_intent, execute_transaction, migrate_account, propose_execution, unwrap_sol, update_approval, withdraw, withdraw_stake, wrap_sol};
 
 
 entrypoint!(process_instruction);
@@ -25,21 +46,129 @@ pub fn process_instruction(
 
     match instruction {
         WalletInstruction::CreateWallet => {
-            msg!("Instruction: Create Wallet");
+            debug_msg!("Instruction: Create Wallet");
             create_wallet(program_id, accounts, instruction_data)?;
         }
         WalletInstruction::ApproveDapp { max_amount, expiry } => {
-            msg!("Instruction: Approve dApp with max_amount: {} and expiry: {}", max_amount, expiry);
+            debug_msg!("Instruction: Approve dApp with max_amount: {} and expiry: {}", max_amount, expiry);
             approve_dapp(program_id, accounts, max_amount, expiry)?;
         }
         WalletInstruction::ExecuteTransaction { amount, transfer_type } => {
-            msg!("Instruction: Execute Transaction with amount: {}", amount);
-            execute_transaction(program_id, accounts, amount, transfer_type)?;
+            debug_msg!("Instruction: Execute Transaction with amount: {}", amount);
+            execute_transaction(program_id, accounts, amount, transfer_type, None)?;
         }
         WalletInstruction::Withdraw { amount, transfer_type } => {
-            msg!("Instruction: Withdraw with amount: {}", amount);
+            debug_msg!("Instruction: Withdraw with amount: {}", amount);
             withdraw(program_id, accounts, amount, transfer_type)?;
         }
+        WalletInstruction::CreateStandingOrder { order_id, amount, interval, start_time } => {
+            debug_msg!("Instruction: Create Standing Order {} with amount: {} every {}s", order_id, amount, interval);
+            create_standing_order(program_id, accounts, order_id, amount, interval, start_time)?;
+        }
+        WalletInstruction::CrankStandingOrder => {
+            debug_msg!("Instruction: Crank Standing Order");
+            crank_standing_order(program_id, accounts)?;
+        }
+        WalletInstruction::CancelStandingOrder => {
+            debug_msg!("Instruction: Cancel Standing Order");
+            cancel_standing_order(program_id, accounts)?;
+        }
+        WalletInstruction::ExecuteBatch { transfers, transfer_type } => {
+            debug_msg!("Instruction: Execute Batch of {} transfers", transfers.len());
+            execute_batch(program_id, accounts, transfers, transfer_type)?;
+        }
+        WalletInstruction::ApproveCpi { max_amount, expiry } => {
+            debug_msg!("Instruction: Approve CPI with max_amount: {} and expiry: {}", max_amount, expiry);
+            approve_cpi(program_id, accounts, max_amount, expiry)?;
+        }
+        WalletInstruction::ExecuteCpi { instruction_data } => {
+            debug_msg!("Instruction: Execute CPI");
+            execute_cpi(program_id, accounts, instruction_data)?;
+        }
+        WalletInstruction::WrapSol { amount } => {
+            debug_msg!("Instruction: Wrap SOL with amount: {}", amount);
+            wrap_sol(program_id, accounts, amount)?;
+        }
+        WalletInstruction::UnwrapSol => {
+            debug_msg!("Instruction: Unwrap SOL");
+            unwrap_sol(program_id, accounts)?;
+        }
+        WalletInstruction::ExecuteTransactionOnce { amount, transfer_type, request_id } => {
+            debug_msg!("Instruction: Execute Transaction Once with amount: {}", amount);
+            execute_transaction(program_id, accounts, amount, transfer_type, Some(request_id))?;
+        }
+        WalletInstruction::ApproveDappWithPermit { max_amount, expiry, nonce } => {
+            debug_msg!("Instruction: Approve dApp with permit, max_amount: {} and expiry: {}", max_amount, expiry);
+            approve_dapp_with_permit(program_id, accounts, max_amount, expiry, nonce)?;
+        }
+        WalletInstruction::ExecuteIntent { amount, transfer_type, nonce, deadline } => {
+            debug_msg!("Instruction: Execute intent {}, amount: {}", nonce, amount);
+            execute_intent(program_id, accounts, amount, transfer_type, nonce, deadline)?;
+        }
+        WalletInstruction::UpdateApproval {
+            max_amount,
+            expiry,
+            recipients,
+            is_approved,
+            valid_from,
+            max_executions,
+            single_use,
+            usd_limit,
+            auto_limit,
+            cosign_limit,
+        } => {
+            debug_msg!("Instruction: Update approval");
+            update_approval(
+                program_id,
+                accounts,
+                max_amount,
+                expiry,
+                recipients,
+                is_approved,
+                valid_from,
+                max_executions,
+                single_use,
+                usd_limit,
+                auto_limit,
+                cosign_limit,
+            )?;
+        }
+        WalletInstruction::MigrateAccount { kind } => {
+            debug_msg!("Instruction: Migrate {:?} account", kind);
+            migrate_account(program_id, accounts, kind)?;
+        }
+        WalletInstruction::ProposeExecution { amount, request_id } => {
+            debug_msg!("Instruction: Propose execution, amount: {}", amount);
+            propose_execution(program_id, accounts, amount, request_id)?;
+        }
+        WalletInstruction::ConfirmExecution => {
+            debug_msg!("Instruction: Confirm execution");
+            confirm_execution(program_id, accounts)?;
+        }
+        WalletInstruction::CancelExecution => {
+            debug_msg!("Instruction: Cancel execution");
+            cancel_execution(program_id, accounts)?;
+        }
+        WalletInstruction::CreateStake { stake_id, amount } => {
+            debug_msg!("Instruction: Create stake {}, amount: {}", stake_id, amount);
+            create_stake(program_id, accounts, stake_id, amount)?;
+        }
+        WalletInstruction::DelegateStake { stake_id } => {
+            debug_msg!("Instruction: Delegate stake {}", stake_id);
+            delegate_stake(program_id, accounts, stake_id)?;
+        }
+        WalletInstruction::DeactivateStake { stake_id } => {
+            debug_msg!("Instruction: Deactivate stake {}", stake_id);
+            deactivate_stake(program_id, accounts, stake_id)?;
+        }
+        WalletInstruction::WithdrawStake { stake_id, amount } => {
+            debug_msg!("Instruction: Withdraw stake {}, amount: {}", stake_id, amount);
+            withdraw_stake(program_id, accounts, stake_id, amount)?;
+        }
+        WalletInstruction::CancelPermit { nonce } => {
+            debug_msg!("Instruction: Cancel permit {}", nonce);
+            cancel_permit(program_id, accounts, nonce)?;
+        }
     }
 
     Ok(())
@@ -0,0 +1,288 @@
+//! Owner-signed messages that are verified through the ed25519 program
+
+use solana_program::{
+    account_info::AccountInfo,
+    ed25519_program,
+    msg,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    sysvar::instructions::{load_current_index_checked, load_instruction_at_checked},
+};
+
+// Domain separators so a signature over one kind of message can't be replayed as another
+const APPROVAL_PERMIT_DOMAIN: &[u8] = b"smart-wallet:approval-permit";
+const TRANSFER_INTENT_DOMAIN: &[u8] = b"smart-wallet:transfer-intent";
+
+// Layout of the ed25519 program's instruction data
+const ED25519_OFFSETS_START: usize = 2;
+const ED25519_PUBKEY_SIZE: usize = 32;
+
+/// An owner's off-chain consent to approve a dApp, submitted by the dApp with
+/// `ApproveDappWithPermit`
+#[derive(Debug)]
+pub struct ApprovalPermit {
+    pub wallet: Pubkey,
+    pub dapp: Pubkey,
+    pub token_mint: Pubkey,
+    pub max_amount: u64,
+    pub expiry: i64,
+    pub nonce: u64,
+}
+
+impl ApprovalPermit {
+    /// The bytes the owner signs: the domain separator followed by each field,
+    /// integers little-endian
+    pub fn message(&self) -> Vec<u8> {
+        let mut message = APPROVAL_PERMIT_DOMAIN.to_vec();
+        message.extend_from_slice(self.wallet.as_ref());
+        message.extend_from_slice(self.dapp.as_ref());
+        message.extend_from_slice(self.token_mint.as_ref());
+        message.extend_from_slice(&self.max_amount.to_le_bytes());
+        message.extend_from_slice(&self.expiry.to_le_bytes());
+        message.extend_from_slice(&self.nonce.to_le_bytes());
+        message
+    }
+}
+
+/// An owner's off-chain instruction to move funds out of the wallet, submitted by any
+/// relayer with `ExecuteIntent`. `token_mint` is `Pubkey::default()` for SOL
+#[derive(Debug)]
+pub struct TransferIntent {
+    pub wallet: Pubkey,
+    pub recipient: Pubkey,
+    pub token_mint: Pubkey,
+    pub amount: u64,
+    pub nonce: u64,
+    pub deadline: i64,
+}
+
+impl TransferIntent {
+    /// The bytes the owner signs: the domain separator followed by each field,
+    /// integers little-endian
+    pub fn message(&self) -> Vec<u8> {
+        let mut message = TRANSFER_INTENT_DOMAIN.to_vec();
+        message.extend_from_slice(self.wallet.as_ref());
+        message.extend_from_slice(self.recipient.as_ref());
+        message.extend_from_slice(self.token_mint.as_ref());
+        message.extend_from_slice(&self.amount.to_le_bytes());
+        message.extend_from_slice(&self.nonce.to_le_bytes());
+        message.extend_from_slice(&self.deadline.to_le_bytes());
+        message
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16, ProgramError> {
+    data.get(offset..offset + 2)
+        .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+        .ok_or(ProgramError::InvalidInstructionData)
+}
+
+fn read_slice(data: &[u8], offset: u16, len: usize) -> Result<&[u8], ProgramError> {
+    data.get(offset as usize..offset as usize + len)
+        .ok_or(ProgramError::InvalidInstructionData)
+}
+
+/// Checks that the instruction right before the current one is an ed25519 program
+/// instruction verifying `signer`'s signature over `message`.
+pub fn verify_ed25519_signature(
+    instructions_sysvar: &AccountInfo,
+    signer: &Pubkey,
+    message: &[u8],
+) -> Result<(), ProgramError> {
+    if !solana_program::sysvar::instructions::check_id(instructions_sysvar.key) {
+        msg!("Instructions sysvar account does not match");
+        return Err(ProgramError::UnsupportedSysvar);
+    }
+
+    let current_index = load_current_index_checked(instructions_sysvar)?;
+    if current_index == 0 {
+        msg!("Missing ed25519 signature instruction");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let ed25519_ix = load_instruction_at_checked(current_index as usize - 1, instructions_sysvar)?;
+    if ed25519_ix.program_id != ed25519_program::id() {
+        msg!("Previous instruction is not an ed25519 signature instruction");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let data = &ed25519_ix.data;
+    if data.first() != Some(&1) {
+        msg!("Expected exactly one ed25519 signature");
+        return Err(ProgramError::InvalidInstructionData);
+    }
+
+    let offsets = ED25519_OFFSETS_START;
+    let signature_instruction_index = read_u16(data, offsets + 2)?;
+    let public_key_offset = read_u16(data, offsets + 4)?;
+    let public_key_instruction_index = read_u16(data, offsets + 6)?;
+    let message_data_offset = read_u16(data, offsets + 8)?;
+    let message_data_size = read_u16(data, offsets + 10)?;
+    let message_instruction_index = read_u16(data, offsets + 12)?;
+
+    // Everything must live in the ed25519 instruction itself, otherwise the verified
+    // bytes could be different from the ones checked here
+    if signature_instruction_index != u16::MAX
+        || public_key_instruction_index != u16::MAX
+        || message_instruction_index != u16::MAX
+    {
+        msg!("ed25519 signature must reference its own instruction data");
+        return Err(ProgramError::InvalidInstructionData);
+    }
+
+    if read_slice(data, public_key_offset, ED25519_PUBKEY_SIZE)? != signer.as_ref() {
+        msg!("ed25519 signature is not from the wallet owner");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    if read_slice(data, message_data_offset, message_data_size as usize)? != message {
+        msg!("ed25519 signature is for a different message");
+        return Err(ProgramError::InvalidInstructionData);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::ed25519_signature;
+    use solana_program::{
+        instruction::Instruction,
+        sysvar::{
+            self,
+            instructions::{construct_instructions_data, store_current_index, BorrowedAccountMeta, BorrowedInstruction},
+        },
+    };
+
+    const SIGNATURE: [u8; 64] = [7; 64];
+
+    /// The instructions sysvar of a transaction made of `instructions`, executing the one
+    /// at `current_index`
+    fn instructions_data(instructions: &[Instruction], current_index: u16) -> Vec<u8> {
+        let borrowed: Vec<_> = instructions
+            .iter()
+            .map(|instruction| BorrowedInstruction {
+                program_id: &instruction.program_id,
+                accounts: instruction
+                    .accounts
+                    .iter()
+                    .map(|meta| BorrowedAccountMeta { pubkey: &meta.pubkey, is_signer: meta.is_signer, is_writable: meta.is_writable })
+                    .collect(),
+                data: &instruction.data,
+            })
+            .collect();
+        let mut data = construct_instructions_data(&borrowed);
+        store_current_index(&mut data, current_index);
+        data
+    }
+
+    fn verify(instructions: &[Instruction], current_index: u16, signer: &Pubkey, message: &[u8]) -> Result<(), ProgramError> {
+        verify_with_key(&sysvar::instructions::ID, instructions, current_index, signer, message)
+    }
+
+    fn verify_with_key(
+        key: &Pubkey,
+        instructions: &[Instruction],
+        current_index: u16,
+        signer: &Pubkey,
+        message: &[u8],
+    ) -> Result<(), ProgramError> {
+        let mut data = instructions_data(instructions, current_index);
+        let mut lamports = 0;
+        let owner = sysvar::ID;
+        let account = AccountInfo::new(key, false, false, &mut lamports, &mut data, &owner, false, 0);
+        verify_ed25519_signature(&account, signer, message)
+    }
+
+    fn wallet_instruction() -> Instruction {
+        Instruction::new_with_bytes(Pubkey::new_unique(), &[1, 13], vec![])
+    }
+
+    #[test]
+    fn accepts_the_owners_signature_over_the_message() {
+        let owner = Pubkey::new_unique();
+        let transaction = [ed25519_signature(&owner, &SIGNATURE, b"permit"), wallet_instruction()];
+
+        assert_eq!(verify(&transaction, 1, &owner, b"permit"), Ok(()));
+    }
+
+    #[test]
+    fn rejects_a_signature_from_someone_else() {
+        let owner = Pubkey::new_unique();
+        let transaction = [ed25519_signature(&Pubkey::new_unique(), &SIGNATURE, b"permit"), wallet_instruction()];
+
+        assert_eq!(verify(&transaction, 1, &owner, b"permit"), Err(ProgramError::MissingRequiredSignature));
+    }
+
+    #[test]
+    fn rejects_a_signature_over_another_message() {
+        let owner = Pubkey::new_unique();
+        let transaction = [ed25519_signature(&owner, &SIGNATURE, b"permit"), wallet_instruction()];
+
+        assert_eq!(verify(&transaction, 1, &owner, b"other permit"), Err(ProgramError::InvalidInstructionData));
+        assert_eq!(verify(&transaction, 1, &owner, b"permi"), Err(ProgramError::InvalidInstructionData));
+    }
+
+    #[test]
+    fn rejects_offsets_into_other_instructions() {
+        let owner = Pubkey::new_unique();
+
+        // Each of the signature, public key and message instruction indices pointing at
+        // the wallet instruction instead of the ed25519 one
+        for index_offset in [4, 8, 14] {
+            let mut signature = ed25519_signature(&owner, &SIGNATURE, b"permit");
+            signature.data[index_offset..index_offset + 2].copy_from_slice(&1u16.to_le_bytes());
+            let transaction = [signature, wallet_instruction()];
+
+            assert_eq!(verify(&transaction, 1, &owner, b"permit"), Err(ProgramError::InvalidInstructionData));
+        }
+    }
+
+    #[test]
+    fn needs_the_signature_directly_before_the_current_instruction() {
+        let owner = Pubkey::new_unique();
+        let signature = ed25519_signature(&owner, &SIGNATURE, b"permit");
+
+        assert_eq!(
+            verify(&[wallet_instruction(), signature.clone()], 0, &owner, b"permit"),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+        assert_eq!(
+            verify(&[signature, wallet_instruction(), wallet_instruction()], 2, &owner, b"permit"),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+    }
+
+    #[test]
+    fn rejects_several_signatures_in_one_instruction() {
+        let owner = Pubkey::new_unique();
+        let mut signature = ed25519_signature(&owner, &SIGNATURE, b"permit");
+        signature.data[0] = 2;
+
+        assert_eq!(
+            verify(&[signature, wallet_instruction()], 1, &owner, b"permit"),
+            Err(ProgramError::InvalidInstructionData)
+        );
+    }
+
+    #[test]
+    fn reads_only_the_real_instructions_sysvar() {
+        let owner = Pubkey::new_unique();
+        let transaction = [ed25519_signature(&owner, &SIGNATURE, b"permit"), wallet_instruction()];
+
+        assert_eq!(
+            verify_with_key(&Pubkey::new_unique(), &transaction, 1, &owner, b"permit"),
+            Err(ProgramError::UnsupportedSysvar)
+        );
+    }
+
+    #[test]
+    fn messages_are_domain_separated() {
+        let key = Pubkey::new_unique();
+        let permit = ApprovalPermit { wallet: key, dapp: key, token_mint: key, max_amount: 1, expiry: 1, nonce: 1 };
+        let intent = TransferIntent { wallet: key, recipient: key, token_mint: key, amount: 1, nonce: 1, deadline: 1 };
+
+        assert_ne!(permit.message(), intent.message());
+    }
+}
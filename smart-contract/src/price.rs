@@ -0,0 +1,150 @@
+//! Reading prices from Pyth-format (v2) price accounts for USD-denominated approval limits.
+//! Any account with this layout works, so tests can use a locally written mock account.
+
+use solana_program::{account_info::AccountInfo, msg, program_error::ProgramError};
+
+/// USD amounts are fixed-point with this many decimals (micro-dollars)
+pub const USD_DECIMALS: u32 = 6;
+
+// Prices older than this many seconds are rejected
+const MAX_PRICE_AGE: i64 = 60;
+
+// Prices whose confidence interval is wider than this, in basis points of the price, are rejected
+const MAX_CONFIDENCE_BPS: u128 = 200;
+
+// Layout of a Pyth v2 price account
+const PYTH_MAGIC: u32 = 0xa1b2_c3d4;
+const PYTH_VERSION: u32 = 2;
+const PYTH_PRICE_ACCOUNT_TYPE: u32 = 3;
+const PYTH_STATUS_TRADING: u32 = 1;
+const MAGIC_OFFSET: usize = 0;
+const VERSION_OFFSET: usize = 4;
+const ACCOUNT_TYPE_OFFSET: usize = 8;
+const EXPO_OFFSET: usize = 20;
+const TIMESTAMP_OFFSET: usize = 96;
+const AGG_PRICE_OFFSET: usize = 208;
+const AGG_CONF_OFFSET: usize = 216;
+const AGG_STATUS_OFFSET: usize = 224;
+const PRICE_DATA_SIZE: usize = 240;
+
+/// An aggregate price: `price * 10^expo` USD per whole token, give or take `conf * 10^expo`
+#[derive(Debug, Clone, Copy)]
+pub struct PythPrice {
+    pub price: i64,
+    pub conf: u64,
+    pub expo: i32,
+    pub publish_time: i64,
+}
+
+fn read_bytes<const N: usize>(data: &[u8], offset: usize) -> Result<[u8; N], ProgramError> {
+    data.get(offset..offset + N)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or(ProgramError::InvalidAccountData)
+}
+
+/// Reads the aggregate price from `price_account`, rejecting prices that are not trading,
+/// older than `MAX_PRICE_AGE` at `now`, or less certain than `MAX_CONFIDENCE_BPS`.
+pub fn load_price(price_account: &AccountInfo, now: i64) -> Result<PythPrice, ProgramError> {
+    let data = price_account.try_borrow_data()?;
+
+    if u32::from_le_bytes(read_bytes(&data, MAGIC_OFFSET)?) != PYTH_MAGIC
+        || u32::from_le_bytes(read_bytes(&data, VERSION_OFFSET)?) != PYTH_VERSION
+        || u32::from_le_bytes(read_bytes(&data, ACCOUNT_TYPE_OFFSET)?) != PYTH_PRICE_ACCOUNT_TYPE
+    {
+        msg!("Not a Pyth price account");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if u32::from_le_bytes(read_bytes(&data, AGG_STATUS_OFFSET)?) != PYTH_STATUS_TRADING {
+        msg!("Price is not currently trading");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let price = PythPrice {
+        price: i64::from_le_bytes(read_bytes(&data, AGG_PRICE_OFFSET)?),
+        conf: u64::from_le_bytes(read_bytes(&data, AGG_CONF_OFFSET)?),
+        expo: i32::from_le_bytes(read_bytes(&data, EXPO_OFFSET)?),
+        publish_time: i64::from_le_bytes(read_bytes(&data, TIMESTAMP_OFFSET)?),
+    };
+
+    if price.price <= 0 {
+        msg!("Price is not positive");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if now.saturating_sub(price.publish_time) > MAX_PRICE_AGE {
+        msg!("Price is stale, published at {}", price.publish_time);
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if price.conf as u128 * 10_000 > price.price as u128 * MAX_CONFIDENCE_BPS {
+        msg!("Price confidence interval is too wide");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    Ok(price)
+}
+
+/// Value in `USD_DECIMALS` fixed-point of `amount` base units of a token with `decimals`.
+/// Uses the top of the confidence interval and rounds up, so limits are never under-counted.
+pub fn usd_value(amount: u64, decimals: u8, price: &PythPrice) -> Result<u64, ProgramError> {
+    let unit_price = (price.price as u128)
+        .checked_add(price.conf as u128)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    let raw_value = (amount as u128)
+        .checked_mul(unit_price)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+
+    let exponent = price
+        .expo
+        .checked_add(USD_DECIMALS as i32)
+        .and_then(|exponent| exponent.checked_sub(decimals as i32))
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    let scale = 10u128
+        .checked_pow(exponent.unsigned_abs())
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    let value = if exponent >= 0 {
+        raw_value.checked_mul(scale).ok_or(ProgramError::ArithmeticOverflow)?
+    } else {
+        raw_value.div_ceil(scale)
+    };
+
+    u64::try_from(value).map_err(|_| ProgramError::ArithmeticOverflow)
+}
+
+/// Data for a Pyth-format price account holding a trading aggregate price, for standing in
+/// for a real feed in tests and local clusters.
+#[cfg(not(target_os = "solana"))]
+pub fn mock_price_account_data(price: i64, conf: u64, expo: i32, publish_time: i64) -> Vec<u8> {
+    let mut data = vec![0; PRICE_DATA_SIZE];
+    data[MAGIC_OFFSET..MAGIC_OFFSET + 4].copy_from_slice(&PYTH_MAGIC.to_le_bytes());
+    data[VERSION_OFFSET..VERSION_OFFSET + 4].copy_from_slice(&PYTH_VERSION.to_le_bytes());
+    data[ACCOUNT_TYPE_OFFSET..ACCOUNT_TYPE_OFFSET + 4].copy_from_slice(&PYTH_PRICE_ACCOUNT_TYPE.to_le_bytes());
+    data[EXPO_OFFSET..EXPO_OFFSET + 4].copy_from_slice(&expo.to_le_bytes());
+    data[TIMESTAMP_OFFSET..TIMESTAMP_OFFSET + 8].copy_from_slice(&publish_time.to_le_bytes());
+    data[AGG_PRICE_OFFSET..AGG_PRICE_OFFSET + 8].copy_from_slice(&price.to_le_bytes());
+    data[AGG_CONF_OFFSET..AGG_CONF_OFFSET + 8].copy_from_slice(&conf.to_le_bytes());
+    data[AGG_STATUS_OFFSET..AGG_STATUS_OFFSET + 4].copy_from_slice(&PYTH_STATUS_TRADING.to_le_bytes());
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn price(expo: i32) -> PythPrice {
+        PythPrice { price: 150, conf: 0, expo, publish_time: 0 }
+    }
+
+    #[test]
+    fn usd_value_scales_by_the_exponent() {
+        // 1.5 SOL at $1.50
+        assert_eq!(usd_value(1_500_000_000, 9, &price(-2)), Ok(2_250_000));
+    }
+
+    #[test]
+    fn extreme_exponents_are_an_error() {
+        assert_eq!(usd_value(1, 9, &price(i32::MAX)), Err(ProgramError::ArithmeticOverflow));
+        assert_eq!(usd_value(1, 9, &price(i32::MIN)), Err(ProgramError::ArithmeticOverflow));
+    }
+}
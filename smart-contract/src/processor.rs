@@ -1,11 +1,25 @@
 use solana_program::{
-    account_info::{next_account_info, AccountInfo}, clock::Clock, entrypoint::ProgramResult, msg, program::invoke_signed, program_error::ProgramError, pubkey::Pubkey, rent::Rent, system_instruction, sysvar::Sysvar
+    account_info::{next_account_info, AccountInfo}, clock::Clock, entrypoint::ProgramResult, instruction::{AccountMeta, Instruction}, msg, program::{invoke, invoke_signed}, program_error::ProgramError, program_option::COption, pubkey::Pubkey, rent::Rent, system_instruction, sysvar::Sysvar
 };
-use crate::{instruction::TransferType, state::{derive_approval_address, derive_wallet_address, pack_approval_data, unpack_approval_data, DAppApproval, DAPP_APPROVAL_SIZE}};
+use crate::{error::WalletErrorCode, event::WalletEvent, instruction::TransferType, permit::{verify_ed25519_signature, ApprovalPermit, TransferIntent}, price::{load_price, usd_value, PythPrice}, state::{derive_approval_address, derive_cpi_approval_address, derive_pending_execution_address, derive_permit_address, derive_receipt_address, derive_stake_address, derive_standing_order_address, load_account_mut, pack_account, pack_approval_data, pack_cpi_approval_data, pack_pending_execution_data, pack_receipt_data, pack_standing_order_data, pack_used_permit_data, pack_wallet_state, unpack_approval_data, unpack_cpi_approval_data, unpack_pending_execution_data, unpack_standing_order_data, unpack_wallet_state, verify_program_address, AccountKind, CpiApproval, DAppApproval, ExecutionReceipt, PendingExecution, ProgramAccount, StandingOrder, UsdLimit, UsedPermit, WalletState, CPI_APPROVAL_SIZE, DAPP_APPROVAL_SIZE, EXECUTION_RECEIPT_SIZE, PENDING_EXECUTION_SIZE, STANDING_ORDER_SIZE, USED_PERMIT_SIZE, WALLET_STATE_SIZE}};
 use solana_program::program_pack::Pack;
+use solana_program::stake::{self, state::{Authorized, Lockup, StakeStateV2}};
+use spl_associated_token_account::{get_associated_token_address_with_program_id, instruction::create_associated_token_account_idempotent};
 use spl_token::state::Account as SplTokenAccount;
-use spl_token_2022::extension::StateWithExtensions;
+use spl_token_2022::extension::{transfer_fee::TransferFeeConfig, transfer_hook, BaseStateWithExtensions, StateWithExtensions};
+use spl_transfer_hook_interface::onchain::add_cpi_accounts_for_execute;
 
+// Base transaction fee is 5000 lamports per signature
+const TRANSACTION_FEE: u64 = 5000;
+
+// Lamports paid from the wallet to whoever cranks a due standing order
+const CRANK_REWARD: u64 = 5000;
+
+// SOL amounts are in lamports, 10^-9 SOL
+const SOL_DECIMALS: u8 = 9;
+
+// How long the owner has to confirm a pending execution, in seconds
+const PENDING_EXECUTION_TTL: i64 = 24 * 60 * 60;
 
 
 pub fn create_wallet(program_id: &Pubkey, accounts: &[AccountInfo], _instrcution_data: &[u8]) -> ProgramResult {
@@ -15,19 +29,23 @@ pub fn create_wallet(program_id: &Pubkey, accounts: &[AccountInfo], _instrcution
     let wallet_account = next_account_info(accounts_iter)?;
     let system_program = next_account_info(accounts_iter)?;
 
+    if !user_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
     // Derive the PDA and verify it
     let (expected_pda, bump_seed) = Pubkey::find_program_address(&[b"wallet", user_account.key.as_ref()], program_id);
     if *wallet_account.key != expected_pda {
-        return Err(ProgramError::InvalidAccountData.into());
+        return Err(ProgramError::InvalidAccountData);
     }
 
     // Create PDA using `invoke_signed`
-    let rent_exempt_balance = Rent::get()?.minimum_balance(0);
+    let rent_exempt_balance = Rent::get()?.minimum_balance(WALLET_STATE_SIZE);
     let create_account_ix = system_instruction::create_account(
         user_account.key,
         wallet_account.key,
         rent_exempt_balance,
-        0,
+        WALLET_STATE_SIZE as u64,
         program_id,
     );
     
@@ -37,60 +55,169 @@ pub fn create_wallet(program_id: &Pubkey, accounts: &[AccountInfo], _instrcution
         &[&[b"wallet", user_account.key.as_ref(), &[bump_seed]]],
     )?;
 
+    pack_wallet_state(&WalletState::new(*user_account.key, bump_seed), wallet_account)?;
+
     Ok(())
 }
 
-pub fn approve_dapp(program_id: &Pubkey, accounts: &[AccountInfo], max_amount: u64, expiry: i64) -> ProgramResult {
-    msg!("Approving dApp Request");
-
-    let account_info_iter = &mut accounts.iter();
+/// Checks that `wallet_account` is `owner`'s wallet PDA using the bump stored in its state,
+/// and returns the bump. Only wallets the program wrote on the current layout have one.
+fn verify_wallet_address(program_id: &Pubkey, wallet_account: &AccountInfo, owner: &Pubkey) -> Result<u8, ProgramError> {
+    let stored_bump = if wallet_account.owner == program_id && wallet_account.data_len() == WALLET_STATE_SIZE {
+        Some(unpack_wallet_state(wallet_account)?.bump)
+    } else {
+        None
+    };
 
-    let user_account = next_account_info(account_info_iter)?;
-    let wallet_account = next_account_info(account_info_iter)?; // user's pda smart wallet
-    let dapp_account = next_account_info(account_info_iter)?; // dapp's to approve for auto transaction
-    let token_mint_account = next_account_info(account_info_iter)?; // token to approve for spending 
-    let approval_account = next_account_info(account_info_iter)?; // approval account i.e pda storing approval details 
-    let system_program = next_account_info(account_info_iter)?; 
+    verify_program_address(wallet_account.key, &[b"wallet", owner.as_ref()], stored_bump, program_id)
+        .ok_or_else(|| {
+            msg!("Wallet account does not match");
+            ProgramError::InvalidAccountData
+        })
+}
 
+/// Reads the wallet's state, first upgrading wallets created with an older layout
+/// (or none at all) to the current one. The wallet pays the extra rent from its own balance.
+fn load_wallet_state(wallet_account: &AccountInfo, owner: &Pubkey, bump_seed: u8) -> Result<WalletState, ProgramError> {
+    let mut wallet_state = if wallet_account.data_is_empty() {
+        WalletState::new(*owner, bump_seed)
+    } else {
+        unpack_wallet_state(wallet_account)?
+    };
+    wallet_state.bump = bump_seed;
 
-    let (wallet_pda, _) = Pubkey::find_program_address(&[b"wallet", user_account.key.as_ref()], program_id);
-    
-    if wallet_pda != *wallet_account.key {
-        msg!("Wallet account does not match");
-        return Err(ProgramError::InvalidAccountData.into());
+    if wallet_state.owner != *owner {
+        msg!("Wallet state belongs to a different owner");
+        return Err(ProgramError::InvalidAccountData);
     }
 
+    if wallet_account.data_len() < WALLET_STATE_SIZE {
+        let rent = Rent::get()?;
+        if wallet_account.lamports() < rent.minimum_balance(WALLET_STATE_SIZE) {
+            msg!("Wallet cannot cover rent for its state");
+            return Err(ProgramError::InsufficientFunds);
+        }
+
+        wallet_account.realloc(WALLET_STATE_SIZE, true)?;
+        pack_wallet_state(&wallet_state, wallet_account)?;
+    }
 
-    let (approval_address, bump_seed) = derive_approval_address(wallet_account.key, dapp_account.key, token_mint_account.key, program_id);
+    Ok(wallet_state)
+}
 
-    if approval_address != *approval_account.key {
+/// Verifies the approval PDA for `(wallet, dapp, mint)` and creates it, with `payer_account`
+/// paying the rent. Fails if the approval already exists; its terms change with UpdateApproval.
+/// Returns the approval's bump seed.
+fn create_approval_account<'a>(
+    program_id: &Pubkey,
+    payer_account: &AccountInfo<'a>,
+    wallet_account: &AccountInfo<'a>,
+    dapp_account: &AccountInfo<'a>,
+    token_mint_account: &AccountInfo<'a>,
+    approval_account: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+) -> Result<u8, ProgramError> {
+    let bump_seed = verify_program_address(
+        approval_account.key,
+        &[b"approval", wallet_account.key.as_ref(), dapp_account.key.as_ref(), token_mint_account.key.as_ref()],
+        None,
+        program_id,
+    )
+    .ok_or_else(|| {
         msg!("Approval account does not match");
-        return Err(ProgramError::InvalidAccountData.into());
+        ProgramError::InvalidAccountData
+    })?;
+
+    if !approval_account.data_is_empty() {
+        msg!("Approval already exists; use UpdateApproval to change it");
+        return Err(ProgramError::AccountAlreadyInitialized);
     }
 
-    if approval_account.data_is_empty() {
-        let rent = Rent::get()?;
-        let space = DAPP_APPROVAL_SIZE as u64;
-        let lamports = rent.minimum_balance(space as usize);
+    let rent = Rent::get()?;
+    let space = DAPP_APPROVAL_SIZE as u64;
+    let lamports = rent.minimum_balance(space as usize);
 
-        invoke_signed(
-            &system_instruction::create_account(
-                user_account.key,
-                approval_account.key,
-                lamports,
-                DAPP_APPROVAL_SIZE as u64,
-                program_id,
-            ),
-            &[user_account.clone(), approval_account.clone(), system_program.clone()],
-            &[&[b"approval", wallet_account.key.as_ref(), dapp_account.key.as_ref(), token_mint_account.key.as_ref(), &[bump_seed]]],
+    invoke_signed(
+        &system_instruction::create_account(
+            payer_account.key,
+            approval_account.key,
+            lamports,
+            DAPP_APPROVAL_SIZE as u64,
+            program_id,
+        ),
+        &[payer_account.clone(), approval_account.clone(), system_program.clone()],
+        &[&[b"approval", wallet_account.key.as_ref(), dapp_account.key.as_ref(), token_mint_account.key.as_ref(), &[bump_seed]]],
+    )?;
+
+    Ok(bump_seed)
+}
+
+/// Grows an account created with an older, smaller layout to `size`, with
+/// `payer_account` topping up the rent.
+fn grow_account<'a>(
+    payer_account: &AccountInfo<'a>,
+    account: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    size: usize,
+) -> ProgramResult {
+    if account.data_len() >= size {
+        return Ok(());
+    }
+
+    let rent_shortfall = Rent::get()?
+        .minimum_balance(size)
+        .saturating_sub(account.lamports());
+    if rent_shortfall > 0 {
+        invoke(
+            &system_instruction::transfer(payer_account.key, account.key, rent_shortfall),
+            &[payer_account.clone(), account.clone(), system_program.clone()],
         )?;
+    }
+
+    account.realloc(size, true)
+}
+
+pub fn approve_dapp(program_id: &Pubkey, accounts: &[AccountInfo], max_amount: u64, expiry: i64) -> ProgramResult {
+    debug_msg!("Approving dApp Request");
+
+    let account_info_iter = &mut accounts.iter();
+
+    let user_account = next_account_info(account_info_iter)?;
+    let wallet_account = next_account_info(account_info_iter)?; // user's pda smart wallet
+    let dapp_account = next_account_info(account_info_iter)?; // dapp's to approve for auto transaction
+    let token_mint_account = next_account_info(account_info_iter)?; // token to approve for spending 
+    let approval_account = next_account_info(account_info_iter)?; // approval account i.e pda storing approval details 
+    let system_program = next_account_info(account_info_iter)?; 
 
+    if !user_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
     }
 
-    let approval_data = DAppApproval::new(max_amount, expiry, *token_mint_account.key);
+    verify_wallet_address(program_id, wallet_account, user_account.key)?;
+
+    let approval_bump = create_approval_account(
+        program_id,
+        user_account,
+        wallet_account,
+        dapp_account,
+        token_mint_account,
+        approval_account,
+        system_program,
+    )?;
+
+    let mut approval_data = DAppApproval::new(
+        *wallet_account.key,
+        *dapp_account.key,
+        *user_account.key,
+        Clock::get()?.unix_timestamp,
+        max_amount,
+        expiry,
+        *token_mint_account.key,
+    );
+    approval_data.bump = approval_bump;
     pack_approval_data(&approval_data, approval_account)?;
 
-    msg!("DApp approved successfully");
+    debug_msg!("DApp approved successfully");
 
     Ok(())
 }
@@ -111,6 +238,27 @@ fn get_token_balance(token_account: &AccountInfo, token_program: &Pubkey) -> Res
     }
 }
 
+/// Returns the fee a Token-2022 transfer-fee mint withholds when the recipient should receive
+/// `amount`, or `None` if the mint charges no transfer fees.
+fn get_transfer_fee(mint_account: &AccountInfo, token_program: &Pubkey, amount: u64) -> Result<Option<u64>, ProgramError> {
+    if token_program != &spl_token_2022::id() {
+        return Ok(None);
+    }
+
+    let data = mint_account.try_borrow_data()?;
+    let mint = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&data)?;
+    let Ok(transfer_fee_config) = mint.get_extension::<TransferFeeConfig>() else {
+        return Ok(None);
+    };
+
+    let epoch = Clock::get()?.epoch;
+    let fee = transfer_fee_config
+        .calculate_inverse_epoch_fee(epoch, amount)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    Ok(Some(fee))
+}
+
+#[allow(clippy::too_many_arguments)]
 fn create_transfer_instruction(
     token_program: &Pubkey,
     source: &Pubkey,
@@ -119,8 +267,21 @@ fn create_transfer_instruction(
     authority: &Pubkey,
     amount: u64,
     decimals: u8,
+    transfer_fee: Option<u64>,
 ) -> Result<solana_program::instruction::Instruction, ProgramError> {
-    if token_program == &spl_token::id() {
+    if let Some(fee) = transfer_fee {
+        Ok(spl_token_2022::extension::transfer_fee::instruction::transfer_checked_with_fee(
+            token_program,
+            source,
+            mint,
+            destination,
+            authority,
+            &[],
+            amount,
+            decimals,
+            fee,
+        )?)
+    } else if token_program == &spl_token::id() {
         Ok(spl_token::instruction::transfer_checked(
             token_program,
             source,
@@ -153,24 +314,24 @@ fn get_token_decimals(mint_account: &AccountInfo, token_program: &Pubkey) -> Res
         ProgramError::AccountBorrowFailed
     })?;
     
-    msg!("Attempting to get decimals for mint account: {:?}", mint_account.key);
+    debug_msg!("Attempting to get decimals for mint account: {:?}", mint_account.key);
     
     if token_program == &spl_token::id() {
-        msg!("Using SPL Token program");
+        debug_msg!("Using SPL Token program");
         let mint = spl_token::state::Mint::unpack(&data).map_err(|e| {
             msg!("Failed to unpack SPL Token mint data: {}", e);
             ProgramError::InvalidAccountData
         })?;
-        msg!("Successfully got decimals: {}", mint.decimals);
+        debug_msg!("Successfully got decimals: {}", mint.decimals);
         Ok(mint.decimals)
     } else if token_program == &spl_token_2022::id() {
-        msg!("Using Token-2022 program");
+        debug_msg!("Using Token-2022 program");
         let state = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&data).map_err(|e| {
             msg!("Failed to unpack Token-2022 mint data: {}", e);
             ProgramError::InvalidAccountData
         })?;
         let mint = state.base;
-        msg!("Successfully got decimals: {}", mint.decimals);
+        debug_msg!("Successfully got decimals: {}", mint.decimals);
         Ok(mint.decimals)
     } else {
         msg!("Error: Invalid token program. Expected SPL Token or Token-2022, got: {:?}", token_program);
@@ -188,292 +349,2144 @@ fn validate_token_accounts(
     if token_program.key != &spl_token::id() && 
        token_program.key != &spl_token_2022::id() {
         msg!("Invalid token program");
-        return Err(ProgramError::IncorrectProgramId.into());
+        return Err(ProgramError::IncorrectProgramId);
     }
 
     // Verify source account ownership
     if wallet_token_account.owner != token_program.key {
         msg!("Source token account not owned by token program");
-        return Err(ProgramError::InvalidAccountData.into());
+        return Err(ProgramError::InvalidAccountData);
     }
 
     // Verify mint account ownership
     if token_mint_account.owner != token_program.key {
         msg!("Mint account not owned by token program");
-        return Err(ProgramError::InvalidAccountData.into());
+        return Err(ProgramError::InvalidAccountData);
     }
 
     // Verify destination account ownership
     if recipient_account.owner != token_program.key {
         msg!("Destination token account not owned by token program");
-        return Err(ProgramError::InvalidAccountData.into());
+        return Err(ProgramError::InvalidAccountData);
     }
 
     Ok(())
 }
 
-pub fn execute_transaction(
-    program_id: &Pubkey,
-    accounts: &[AccountInfo],
+/// Returns the native mint of a token program.
+fn native_mint(token_program: &Pubkey) -> Result<Pubkey, ProgramError> {
+    if token_program == &spl_token::id() {
+        Ok(spl_token::native_mint::id())
+    } else if token_program == &spl_token_2022::id() {
+        Ok(spl_token_2022::native_mint::id())
+    } else {
+        Err(ProgramError::IncorrectProgramId)
+    }
+}
+
+/// Checks that `wallet_token_account` is the wallet's associated token account for the native
+/// mint of `token_program`, the only account the wallet wraps lamports into.
+fn verify_wallet_native_token_account(
+    wallet_account: &AccountInfo,
+    wallet_token_account: &AccountInfo,
+    token_program: &AccountInfo,
+) -> ProgramResult {
+    let wallet_token_address = get_associated_token_address_with_program_id(
+        wallet_account.key,
+        &native_mint(token_program.key)?,
+        token_program.key,
+    );
+    if wallet_token_address != *wallet_token_account.key {
+        msg!("Wallet native-mint token account does not match");
+        return Err(ProgramError::InvalidAccountData);
+    }
+    Ok(())
+}
+
+/// Moves `amount` lamports from the wallet into its native-mint token account and syncs the
+/// token balance. The wallet keeps enough lamports to stay rent exempt.
+fn wrap_lamports<'a>(
+    wallet_account: &AccountInfo<'a>,
+    wallet_token_account: &AccountInfo<'a>,
+    token_program: &AccountInfo<'a>,
     amount: u64,
-    transfer_type: TransferType,
 ) -> ProgramResult {
-    // Base transaction fee is 5000 lamports per signature
-    const TRANSACTION_FEE: u64 = 5000;
+    let rent_minimum = Rent::get()?.minimum_balance(wallet_account.data_len());
+    if wallet_account.lamports().saturating_sub(rent_minimum) < amount {
+        msg!("Wallet does not have enough lamports to wrap {}", amount);
+        return Err(ProgramError::InsufficientFunds);
+    }
 
-    msg!("execute_transaction: Beginning execution");
+    **wallet_account.try_borrow_mut_lamports()? -= amount;
+    **wallet_token_account.try_borrow_mut_lamports()? += amount;
 
-    let account_info_iter = &mut accounts.iter();
+    let sync_native_ix = if token_program.key == &spl_token::id() {
+        spl_token::instruction::sync_native(token_program.key, wallet_token_account.key)?
+    } else {
+        spl_token_2022::instruction::sync_native(token_program.key, wallet_token_account.key)?
+    };
+    invoke(&sync_native_ix, &[wallet_token_account.clone(), token_program.clone()])
+}
 
-    let dapp_account = next_account_info(account_info_iter)?;
-    let user_account = next_account_info(account_info_iter)?;
-    let wallet_account = next_account_info(account_info_iter)?;
-    let approval_account = next_account_info(account_info_iter)?;
-    let recipient_account = next_account_info(account_info_iter)?;
-    let system_program = next_account_info(account_info_iter)?;
+/// The accounts needed to move tokens out of the wallet's token account.
+struct TokenTransferAccounts<'a, 'b> {
+    token_mint: &'b AccountInfo<'a>,
+    wallet_token: &'b AccountInfo<'a>,
+    token_program: &'b AccountInfo<'a>,
+}
 
-    // Add detailed logging
-    msg!("Executing transaction");
-    msg!("DApp account: {:?}", dapp_account.key);
-    msg!("User account: {:?}", user_account.key);
-    msg!("Wallet account: {:?}", wallet_account.key);
-    msg!("Approval account: {:?}", approval_account.key);
-    msg!("Recipient account: {:?}", recipient_account.key);
-    msg!("Amount: {}", amount);
+impl<'a, 'b> TokenTransferAccounts<'a, 'b> {
+    /// Reads the trailing `[mint, wallet token account, token program]` accounts
+    /// that every token transfer instruction expects.
+    fn next<I: Iterator<Item = &'b AccountInfo<'a>>>(account_info_iter: &mut I) -> Result<Self, ProgramError> {
+        let token_mint = next_account_info(account_info_iter)?;
+        let wallet_token = next_account_info(account_info_iter)?;
+        let token_program = next_account_info(account_info_iter)?;
 
-    // Verify that the dApp is a signer
-    if !dapp_account.is_signer {
-        return Err(ProgramError::MissingRequiredSignature.into());
+        Ok(Self { token_mint, wallet_token, token_program })
     }
+}
 
-    // Get the wallet PDA
-    let (wallet_pda, bump_seed) = derive_wallet_address(user_account.key, program_id);
+/// Transfers tokens from the wallet's token account so that the recipient receives `amount`,
+/// signing as the wallet PDA. `transfer_fee` comes from `get_transfer_fee` and is paid by the
+/// wallet on top of `amount`.
+fn transfer_tokens_from_wallet<'a>(
+    token_accounts: &TokenTransferAccounts<'a, '_>,
+    recipient_account: &AccountInfo<'a>,
+    wallet_account: &AccountInfo<'a>,
+    extra_accounts: &[AccountInfo<'a>],
+    wallet_seeds: &[&[u8]],
+    amount: u64,
+    transfer_fee: Option<u64>,
+) -> ProgramResult {
+    let token_program = token_accounts.token_program;
 
-    // Verify that the provided wallet account is actually the PDA we expect
-    if wallet_pda != *wallet_account.key {
-        return Err(ProgramError::InvalidAccountData.into());
+    validate_token_accounts(
+        token_accounts.wallet_token,
+        token_accounts.token_mint,
+        recipient_account,
+        token_program,
+    )?;
+
+    let gross_amount = amount
+        .checked_add(transfer_fee.unwrap_or(0))
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+
+    // Check token account balance
+    let token_balance = get_token_balance(token_accounts.wallet_token, token_program.key)?;
+    if token_balance < gross_amount {
+        msg!("Insufficient token balance");
+        return Err(ProgramError::InsufficientFunds);
+    }
+
+    // Get token decimals
+    let decimals = get_token_decimals(token_accounts.token_mint, token_program.key)?;
+
+    // Create transfer instruction - SPL Token requires specific account order
+    let mut transfer_ix = create_transfer_instruction(
+        token_program.key,
+        token_accounts.wallet_token.key,    // source
+        recipient_account.key,              // destination
+        token_accounts.token_mint.key,      // mint
+        wallet_account.key,                 // authority
+        gross_amount,
+        decimals,
+        transfer_fee,
+    )?;
+
+    let mut accounts = vec![
+        token_accounts.wallet_token.clone(),    // source
+        token_accounts.token_mint.clone(),      // mint
+        recipient_account.clone(),              // destination
+        wallet_account.clone(),                 // authority
+        token_program.clone(),                  // program
+    ];
+
+    // Token-2022 mints with a transfer hook need the hook program, its validation account and
+    // the extra accounts it lists; the client passes these after the instruction's fixed accounts
+    if token_program.key == &spl_token_2022::id() {
+        let mint_data = token_accounts.token_mint.try_borrow_data()?;
+        let mint = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&mint_data)?;
+        if let Some(hook_program_id) = transfer_hook::get_program_id(&mint) {
+            add_cpi_accounts_for_execute(
+                &mut transfer_ix,
+                &mut accounts,
+                token_accounts.token_mint.key,
+                &hook_program_id,
+                extra_accounts,
+            )?;
+        }
+    }
+
+    invoke_signed(&transfer_ix, &accounts, &[wallet_seeds])
+}
+
+/// Checks that a dApp may spend from the wallet: the dApp signed, the wallet and approval
+/// accounts are the expected PDAs, and the approval is active. Returns the approval data
+/// and the wallet's bump seed.
+fn verify_dapp_approval(
+    program_id: &Pubkey,
+    dapp_account: &AccountInfo,
+    user_account: &AccountInfo,
+    wallet_account: &AccountInfo,
+    approval_account: &AccountInfo,
+) -> Result<(DAppApproval, u8), ProgramError> {
+    // Verify that the dApp is a signer
+    if !dapp_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
     }
 
+    load_active_approval(program_id, dapp_account, user_account, wallet_account, approval_account)
+}
+
+/// `verify_dapp_approval` without requiring the dApp's signature, for transfers the
+/// owner authorizes on the dApp's behalf.
+fn load_active_approval(
+    program_id: &Pubkey,
+    dapp_account: &AccountInfo,
+    user_account: &AccountInfo,
+    wallet_account: &AccountInfo,
+    approval_account: &AccountInfo,
+) -> Result<(DAppApproval, u8), ProgramError> {
     // Verify that the wallet account is owned by the program
     if wallet_account.owner != program_id {
         msg!("Wallet account is not owned by the program");
-        return Err(ProgramError::IllegalOwner.into());
+        return Err(ProgramError::IllegalOwner);
+    }
+
+    // Verify that the provided wallet account is actually the PDA we expect
+    let bump_seed = verify_wallet_address(program_id, wallet_account, user_account.key)?;
+
+    // Verify that the approval account is the PDA for this wallet, dApp and mint
+    if approval_account.owner != program_id {
+        msg!("Approval account is not owned by the program");
+        return Err(ProgramError::IllegalOwner);
     }
 
     // Unpack and verify approval data
-    let approval_data = unpack_approval_data(approval_account)?;
-    if !approval_data.is_approved {
+    let mut approval_data = unpack_approval_data(approval_account)?;
+    approval_data.bump = verify_program_address(
+        approval_account.key,
+        &[b"approval", wallet_account.key.as_ref(), dapp_account.key.as_ref(), approval_data.token_mint.as_ref()],
+        (approval_account.data_len() == DAPP_APPROVAL_SIZE).then_some(approval_data.bump),
+        program_id,
+    )
+    .ok_or_else(|| {
+        msg!("Approval account does not match");
+        ProgramError::InvalidAccountData
+    })?;
+    approval_data.set_keys(*wallet_account.key, *dapp_account.key, *user_account.key);
+
+    if !bool::from(approval_data.is_approved) {
         msg!("Dapp is not approved");
-        return Err(ProgramError::InvalidAccountData.into());
+        return Err(WalletErrorCode::ApprovalRevoked.into());
     }
 
     // Check approval expiry
     let clock = Clock::get()?;
-    if clock.unix_timestamp >= approval_data.expiry {
-        msg!("Approval has expired");
-        return Err(ProgramError::InvalidAccountData.into());
+    if clock.unix_timestamp >= i64::from(approval_data.expiry) {
+        msg!("Approval expired at {}", i64::from(approval_data.expiry));
+        return Err(WalletErrorCode::ApprovalExpired.into());
+    }
+
+    if clock.unix_timestamp < i64::from(approval_data.valid_from) {
+        msg!("Approval is not valid until {}", i64::from(approval_data.valid_from));
+        return Err(WalletErrorCode::ApprovalNotYetValid.into());
+    }
+
+    if u64::from(approval_data.max_executions) != 0 && u64::from(approval_data.execution_count) >= u64::from(approval_data.max_executions) {
+        msg!("Approval has been used {} times, its maximum", u64::from(approval_data.execution_count));
+        return Err(WalletErrorCode::ExecutionLimitReached.into());
+    }
+
+    Ok((approval_data, bump_seed))
+}
+
+/// Fails unless the approval allows sending to `recipient`.
+fn check_approved_recipient(approval_data: &DAppApproval, recipient: &Pubkey) -> ProgramResult {
+    if !approval_data.recipients().is_empty() && !approval_data.recipients().contains(recipient) {
+        msg!("Recipient {} is not allowed by the approval", recipient);
+        return Err(WalletErrorCode::RecipientNotAllowed.into());
+    }
+
+    Ok(())
+}
+
+/// Reads the approval's price account from the instruction accounts when the approval has a
+/// USD limit.
+fn load_usd_price<'a: 'b, 'b, I: Iterator<Item = &'b AccountInfo<'a>>>(
+    approval_data: &DAppApproval,
+    account_info_iter: &mut I,
+) -> Result<Option<PythPrice>, ProgramError> {
+    let Some(usd_limit) = approval_data.usd_limit() else {
+        return Ok(None);
+    };
+
+    let price_account = next_account_info(account_info_iter)?;
+    if *price_account.key != usd_limit.price_feed {
+        msg!("Price account does not match the approval's price feed");
+        return Err(ProgramError::InvalidAccountData);
     }
 
+    load_price(price_account, Clock::get()?.unix_timestamp).map(Some)
+}
+
+/// Fails if `amount` base units of a token with `decimals` are worth more than the
+/// approval's USD limit.
+fn check_usd_limit(approval_data: &DAppApproval, price: Option<&PythPrice>, amount: u64, decimals: u8) -> ProgramResult {
+    if let (Some(usd_limit), Some(price)) = (approval_data.usd_limit(), price) {
+        let value = usd_value(amount, decimals, price)?;
+        if value > usd_limit.max_usd {
+            msg!("Amount is worth {} USD micro-units, more than the limit of {}", value, usd_limit.max_usd);
+            return Err(WalletErrorCode::UsdLimitExceeded.into());
+        }
+    }
+
+    Ok(())
+}
+
+/// Lamports the wallet can pay out while staying rent exempt
+fn spendable_lamports(wallet_account: &AccountInfo) -> Result<u64, ProgramError> {
+    let rent_exempt_balance = Rent::get()?.minimum_balance(wallet_account.data_len());
+    Ok(wallet_account.lamports().saturating_sub(rent_exempt_balance))
+}
+
+/// Closes a program-owned account, moving its lamports to `destination_account`.
+fn close_program_account(account: &AccountInfo, destination_account: &AccountInfo) -> ProgramResult {
+    let account_lamports = account.lamports();
+    **account.try_borrow_mut_lamports()? -= account_lamports;
+    **destination_account.try_borrow_mut_lamports()? += account_lamports;
+    account.realloc(0, false)?;
+    account.assign(&solana_program::system_program::id());
+    Ok(())
+}
+
+/// Adds an execution that debited `amount` to the approval's usage counters and stores them.
+/// Single-use approvals are closed instead, returning their rent to the owner.
+fn record_approval_execution<'a>(
+    approval_data: &mut DAppApproval,
+    approval_account: &AccountInfo<'a>,
+    payer_account: &AccountInfo<'a>,
+    user_account: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    amount: u64,
+) -> ProgramResult {
+    if bool::from(approval_data.single_use) {
+        debug_msg!("Closing single-use approval");
+        return close_program_account(approval_account, user_account);
+    }
+
+    approval_data.spent_amount = u64::from(approval_data.spent_amount)
+        .checked_add(amount)
+        .ok_or(ProgramError::ArithmeticOverflow)?
+        .into();
+    approval_data.execution_count = u64::from(approval_data.execution_count)
+        .checked_add(1)
+        .ok_or(ProgramError::ArithmeticOverflow)?
+        .into();
+
+    // Approvals already in the current layout only have their counters updated in place
+    if let Ok(mut approval) = load_account_mut::<DAppApproval>(approval_account) {
+        approval.spent_amount = approval_data.spent_amount;
+        approval.execution_count = approval_data.execution_count;
+        approval.bump = approval_data.bump;
+        return Ok(());
+    }
+
+    grow_account(payer_account, approval_account, system_program, DAPP_APPROVAL_SIZE)?;
+    pack_approval_data(approval_data, approval_account)
+}
+
+/// Creates the receipt PDA for `request_id`, failing if the request was already executed.
+fn create_execution_receipt<'a>(
+    program_id: &Pubkey,
+    payer_account: &AccountInfo<'a>,
+    approval_account: &AccountInfo<'a>,
+    receipt_account: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    request_id: &[u8; 32],
+    amount: u64,
+) -> ProgramResult {
+    let (receipt_address, bump_seed) = derive_receipt_address(approval_account.key, request_id, program_id);
+    if receipt_address != *receipt_account.key {
+        msg!("Receipt account does not match");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if !receipt_account.data_is_empty() {
+        msg!("Request has already been executed");
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
+
+    let rent = Rent::get()?;
+    invoke_signed(
+        &system_instruction::create_account(
+            payer_account.key,
+            receipt_account.key,
+            rent.minimum_balance(EXECUTION_RECEIPT_SIZE),
+            EXECUTION_RECEIPT_SIZE as u64,
+            program_id,
+        ),
+        &[payer_account.clone(), receipt_account.clone(), system_program.clone()],
+        &[&[b"receipt", approval_account.key.as_ref(), request_id, &[bump_seed]]],
+    )?;
+
+    let receipt_data = ExecutionReceipt::new(amount, Clock::get()?.unix_timestamp);
+    pack_receipt_data(&receipt_data, receipt_account)
+}
+
+/// The accounts involved in a transfer a dApp makes under its approval. `payer` submitted
+/// the transaction: it is reimbursed the fee and pays for any account growth.
+struct ApprovalTransferAccounts<'a, 'b> {
+    payer: &'b AccountInfo<'a>,
+    user: &'b AccountInfo<'a>,
+    wallet: &'b AccountInfo<'a>,
+    approval: &'b AccountInfo<'a>,
+    recipient: &'b AccountInfo<'a>,
+    system_program: &'b AccountInfo<'a>,
+}
+
+/// Moves `amount` out of the wallet under an already verified approval: enforces the
+/// approval's limits, makes the transfer, reimburses the payer's fee and records the execution.
+/// The price account and token accounts, where needed, are read from `account_info_iter`.
+fn transfer_under_approval<'a, 'b>(
+    approval_data: &mut DAppApproval,
+    accounts: &ApprovalTransferAccounts<'a, 'b>,
+    account_info_iter: &mut std::slice::Iter<'b, AccountInfo<'a>>,
+    amount: u64,
+    transfer_type: TransferType,
+    bump_seed: u8,
+) -> ProgramResult {
     // Check transaction amount
-    if amount > approval_data.max_amount {
-        msg!("Amount is greater than max amount");
-        return Err(ProgramError::InvalidAccountData.into());
+    if amount > u64::from(approval_data.max_amount) {
+        msg!("Amount {} is greater than the max amount of {}", amount, u64::from(approval_data.max_amount));
+        return Err(WalletErrorCode::AllowanceExceeded.into());
     }
 
-    match transfer_type {
+    check_approved_recipient(approval_data, accounts.recipient.key)?;
+
+    let usd_price = load_usd_price(approval_data, account_info_iter)?;
+
+    let debited_amount = match transfer_type {
         TransferType::Sol => {
             // Verify the approval is for SOL 
             if approval_data.token_mint != Pubkey::default() {
                 msg!("Approval is not for SOL");
-                return Err(ProgramError::InvalidAccountData.into());
+                return Err(ProgramError::InvalidAccountData);
             }
 
-            msg!("Transferring SOL from wallet {:?} to recipient {:?}, amount: {:?}", wallet_account.key, recipient_account.key, amount);
-            msg!("Wallet account's balance: {:?}", wallet_account.lamports());
+            debug_msg!("Transferring SOL from wallet {:?} to recipient {:?}, amount: {:?}", accounts.wallet.key, accounts.recipient.key, amount);
+            debug_msg!("Wallet account's balance: {:?}", accounts.wallet.lamports());
 
             // Check if wallet has enough balance for transfer and fee
-            let wallet_balance = wallet_account.lamports();
-
-            if wallet_balance < amount + TRANSACTION_FEE {
+            let sol_outflow = amount.checked_add(TRANSACTION_FEE).ok_or(ProgramError::ArithmeticOverflow)?;
+            if spendable_lamports(accounts.wallet)? < sol_outflow {
                 msg!("Wallet does not have enough balance for transfer and fee");
-                return Err(ProgramError::InsufficientFunds.into());
+                return Err(ProgramError::InsufficientFunds);
             }
 
+            check_usd_limit(approval_data, usd_price.as_ref(), amount, SOL_DECIMALS)?;
+
             // Transfer amount to recipient
-            **wallet_account.try_borrow_mut_lamports()? -= amount;
-            **recipient_account.try_borrow_mut_lamports()? += amount;
+            **accounts.wallet.try_borrow_mut_lamports()? -= amount;
+            **accounts.recipient.try_borrow_mut_lamports()? += amount;
 
-            // Transfer fee to dApp
-            **wallet_account.try_borrow_mut_lamports()? -= TRANSACTION_FEE;
-            **dapp_account.try_borrow_mut_lamports()? += TRANSACTION_FEE;
+            // Reimburse the transaction fee
+            **accounts.wallet.try_borrow_mut_lamports()? -= TRANSACTION_FEE;
+            **accounts.payer.try_borrow_mut_lamports()? += TRANSACTION_FEE;
 
-            msg!("Transfer completed. New wallet balance: {}", wallet_account.lamports());
+            debug_msg!("Transfer completed. New wallet balance: {}", accounts.wallet.lamports());
+            amount
         },
         TransferType::Token => {
-            let token_mint_account = next_account_info(account_info_iter)?;
-            let wallet_token_account = next_account_info(account_info_iter)?;
-            let token_program = next_account_info(account_info_iter)?;
-
-            // Validate all token accounts
-            validate_token_accounts(
-                wallet_token_account,
-                token_mint_account,
-                recipient_account,
-                token_program,
-            )?;
+            let token_accounts = TokenTransferAccounts::next(account_info_iter)?;
+            if *token_accounts.token_mint.key != approval_data.token_mint {
+                msg!("Approval is not for this token");
+                return Err(ProgramError::InvalidAccountData);
+            }
 
-            // Check token account balance
-            let token_balance = get_token_balance(wallet_token_account, token_program.key)?;
-            if token_balance < amount {
-                msg!("Insufficient token balance");
-                return Err(ProgramError::InsufficientFunds.into());
+            // Transfer fees are paid by the wallet and count against the allowance
+            let transfer_fee = get_transfer_fee(token_accounts.token_mint, token_accounts.token_program.key, amount)?;
+            if let Some(fee) = transfer_fee {
+                if amount.saturating_add(fee) > u64::from(approval_data.max_amount) {
+                    msg!("Amount plus transfer fee is {}, greater than the max amount of {}", amount.saturating_add(fee), u64::from(approval_data.max_amount));
+                    return Err(WalletErrorCode::AllowanceExceeded.into());
+                }
             }
 
-            // Get token decimals
-            let decimals = get_token_decimals(token_mint_account, token_program.key)?;
+            if usd_price.is_some() {
+                let decimals = get_token_decimals(token_accounts.token_mint, token_accounts.token_program.key)?;
+                check_usd_limit(approval_data, usd_price.as_ref(), amount.saturating_add(transfer_fee.unwrap_or(0)), decimals)?;
+            }
 
-            // Create and execute the transfer instruction
-            let transfer_ix = create_transfer_instruction(
-                token_program.key,
-                wallet_token_account.key,
-                recipient_account.key,
-                token_mint_account.key,
-                wallet_account.key,
-                amount,
-                decimals,
-            )?;
+            // Native-mint approvals can be paid from the wallet's plain lamports
+            let shortfall = if *token_accounts.token_mint.key == native_mint(token_accounts.token_program.key)? {
+                let token_balance = get_token_balance(token_accounts.wallet_token, token_accounts.token_program.key)?;
+                amount.saturating_sub(token_balance)
+            } else {
+                0
+            };
 
-            // For Token-2022, we need to include any extra accounts that might be required
-            let mut accounts = vec![
-                wallet_token_account.clone(),
-                token_mint_account.clone(),
-                recipient_account.clone(),
-                wallet_account.clone(),
-                token_program.clone(),
-            ];
+            if spendable_lamports(accounts.wallet)? < shortfall.saturating_add(TRANSACTION_FEE) {
+                msg!("Wallet does not have enough balance for transfer and fee");
+                return Err(ProgramError::InsufficientFunds);
+            }
 
-            // Add memo program if using Token-2022 (optional but recommended)
-            if token_program.key == &spl_token_2022::id() {
-                if let Ok(memo_program) = next_account_info(account_info_iter) {
-                    accounts.push(memo_program.clone());
-                }
+            if shortfall > 0 {
+                // Only the wallet's own native-mint account may receive its lamports
+                verify_wallet_native_token_account(accounts.wallet, token_accounts.wallet_token, token_accounts.token_program)?;
+                debug_msg!("Wrapping {} lamports to cover the transfer", shortfall);
+                wrap_lamports(accounts.wallet, token_accounts.wallet_token, token_accounts.token_program, shortfall)?;
             }
 
-            invoke_signed(
-                &transfer_ix,
-                &accounts,
-                &[&[b"wallet", user_account.key.as_ref(), &[bump_seed]]],
+            transfer_tokens_from_wallet(
+                &token_accounts,
+                accounts.recipient,
+                accounts.wallet,
+                account_info_iter.as_slice(),
+                &[b"wallet", accounts.user.key.as_ref(), &[bump_seed]],
+                amount,
+                transfer_fee,
             )?;
 
-            // Transfer SOL fee to dApp
-            **wallet_account.try_borrow_mut_lamports()? -= TRANSACTION_FEE;
-            **dapp_account.try_borrow_mut_lamports()? += TRANSACTION_FEE;
+            // Reimburse the transaction fee in SOL
+            **accounts.wallet.try_borrow_mut_lamports()? -= TRANSACTION_FEE;
+            **accounts.payer.try_borrow_mut_lamports()? += TRANSACTION_FEE;
+            amount + transfer_fee.unwrap_or(0)
         }
-    }
+    };
 
-    msg!("Transaction executed successfully");
-    Ok(())
+    record_approval_execution(approval_data, accounts.approval, accounts.payer, accounts.user, accounts.system_program, debited_amount)?;
+
+    WalletEvent::TransferExecuted {
+        approval: *accounts.approval.key,
+        wallet: *accounts.wallet.key,
+        recipient: *accounts.recipient.key,
+        token_mint: approval_data.token_mint,
+        amount,
+        debited_amount,
+    }
+    .emit()
 }
 
-pub fn withdraw(program_id: &Pubkey, accounts: &[AccountInfo], amount: u64, transfer_type: TransferType) -> ProgramResult {
+pub fn execute_transaction(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+    transfer_type: TransferType,
+    request_id: Option<[u8; 32]>,
+) -> ProgramResult {
+    debug_msg!("execute_transaction: Beginning execution");
+
     let account_info_iter = &mut accounts.iter();
 
+    let dapp_account = next_account_info(account_info_iter)?;
     let user_account = next_account_info(account_info_iter)?;
     let wallet_account = next_account_info(account_info_iter)?;
+    let approval_account = next_account_info(account_info_iter)?;
     let recipient_account = next_account_info(account_info_iter)?;
     let system_program = next_account_info(account_info_iter)?;
+    let receipt_account = request_id.map(|_| next_account_info(account_info_iter)).transpose()?;
 
-    // check the signer
-    if !user_account.is_signer {
-        return Err(ProgramError::MissingRequiredSignature.into());
-    }
+    // Add detailed logging
+    debug_msg!("Executing transaction");
+    debug_msg!("DApp account: {:?}", dapp_account.key);
+    debug_msg!("User account: {:?}", user_account.key);
+    debug_msg!("Wallet account: {:?}", wallet_account.key);
+    debug_msg!("Approval account: {:?}", approval_account.key);
+    debug_msg!("Recipient account: {:?}", recipient_account.key);
+    debug_msg!("Amount: {}", amount);
+
+    let (mut approval_data, bump_seed) = verify_dapp_approval(
+        program_id,
+        dapp_account,
+        user_account,
+        wallet_account,
+        approval_account,
+    )?;
 
-    // Get the wallet PDA
-    let (wallet_pda, bump_seed) = derive_wallet_address(user_account.key, program_id);
+    // With co-signing enabled, larger amounts have to go through `ProposeExecution`
+    if u64::from(approval_data.cosign_limit) > 0 && amount > u64::from(approval_data.auto_limit) {
+        msg!("Amount is above the auto limit of {} and needs the owner's co-signature", u64::from(approval_data.auto_limit));
+        return Err(WalletErrorCode::CosignRequired.into());
+    }
 
-    // Verify that the provided wallet account is actually the PDA we expect
-    if wallet_pda != *wallet_account.key {
-        msg!("Wallet account does not match");
-        return Err(ProgramError::InvalidAccountData.into());
+    // Record the request id so a retried request cannot charge the wallet twice
+    if let (Some(request_id), Some(receipt_account)) = (request_id, receipt_account) {
+        create_execution_receipt(
+            program_id,
+            dapp_account,
+            approval_account,
+            receipt_account,
+            system_program,
+            &request_id,
+            amount,
+        )?;
     }
 
-    match transfer_type {
-        TransferType::Sol => {
-            msg!("Withdrawing SOL from wallet {:?} to recipient {:?}, amount: {:?}", wallet_account.key, recipient_account.key, amount);
-            msg!("Wallet account's balance: {:?}", wallet_account.lamports());
+    let transfer_accounts = ApprovalTransferAccounts {
+        payer: dapp_account,
+        user: user_account,
+        wallet: wallet_account,
+        approval: approval_account,
+        recipient: recipient_account,
+        system_program,
+    };
+    transfer_under_approval(&mut approval_data, &transfer_accounts, account_info_iter, amount, transfer_type, bump_seed)?;
+
+    debug_msg!("Transaction executed successfully");
+    Ok(())
+}
 
-            // Check if wallet has enough balance for withdrawal
-            let wallet_balance = wallet_account.lamports();
-    
-            if wallet_balance < amount {
-                msg!("Wallet does not have enough balance for withdrawal");
-                return Err(ProgramError::InsufficientFunds.into());
-            }
-    
-            // Withdraw amount from wallet
-            **wallet_account.try_borrow_mut_lamports()? -= amount;
-            **recipient_account.try_borrow_mut_lamports()? += amount;
-        }
-        TransferType::Token => {
-            msg!("Withdrawing token");
-            let token_mint_account = next_account_info(account_info_iter)?;
-            let wallet_token_account = next_account_info(account_info_iter)?;
-            let token_program = next_account_info(account_info_iter)?;
-
-            // Validate accounts
-            validate_token_accounts(
-                wallet_token_account,
-                token_mint_account,
-                recipient_account,
-                token_program,
-            )?;
+pub fn execute_batch(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    transfers: Vec<(u8, u64)>,
+    transfer_type: TransferType,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let dapp_account = next_account_info(account_info_iter)?;
+    let user_account = next_account_info(account_info_iter)?;
+    let wallet_account = next_account_info(account_info_iter)?;
+    let approval_account = next_account_info(account_info_iter)?;
+    let system_program = next_account_info(account_info_iter)?;
+
+    let (mut approval_data, bump_seed) = verify_dapp_approval(
+        program_id,
+        dapp_account,
+        user_account,
+        wallet_account,
+        approval_account,
+    )?;
+
+    if transfers.is_empty() {
+        msg!("Batch contains no transfers");
+        return Err(ProgramError::InvalidInstructionData);
+    }
 
-            msg!("Validated accounts");
+    // The allowance applies to the batch as a whole
+    let total_amount = transfers
+        .iter()
+        .try_fold(0u64, |total, (_, amount)| total.checked_add(*amount))
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    if total_amount > u64::from(approval_data.max_amount) {
+        msg!("Batch total {} is greater than the max amount of {}", total_amount, u64::from(approval_data.max_amount));
+        return Err(WalletErrorCode::AllowanceExceeded.into());
+    }
 
-            // Get token decimals
-            let decimals = get_token_decimals(token_mint_account, token_program.key)?;
+    if u64::from(approval_data.cosign_limit) > 0 && total_amount > u64::from(approval_data.auto_limit) {
+        msg!("Batch total {} is above the auto limit and needs the owner's co-signature", total_amount);
+        return Err(WalletErrorCode::CosignRequired.into());
+    }
 
-            msg!("Got token decimals: {}", decimals);
+    let wallet_seeds: &[&[u8]] = &[b"wallet", user_account.key.as_ref(), &[bump_seed]];
 
-            // Check balance
-            let token_balance = get_token_balance(wallet_token_account, token_program.key)?;
+    let usd_price = load_usd_price(&approval_data, account_info_iter)?;
 
-            msg!("Got token balance: {}", token_balance);
+    // The recipient and amount of each transfer made, for the event
+    let mut paid = Vec::with_capacity(transfers.len());
+    let debited_amount = match transfer_type {
+        TransferType::Sol => {
+            if approval_data.token_mint != Pubkey::default() {
+                msg!("Approval is not for SOL");
+                return Err(ProgramError::InvalidAccountData);
+            }
 
-            if token_balance < amount {
-                msg!("Insufficient token balance");
-                return Err(ProgramError::InsufficientFunds.into());
+            let sol_outflow = total_amount.checked_add(TRANSACTION_FEE).ok_or(ProgramError::ArithmeticOverflow)?;
+            if spendable_lamports(wallet_account)? < sol_outflow {
+                msg!("Wallet does not have enough balance for batch and fee");
+                return Err(ProgramError::InsufficientFunds);
             }
 
-            msg!("Withdrawing tokens from wallet_token_account {:?} to recipient_account {:?}, amount: {:?}", 
-                wallet_token_account.key, recipient_account.key, amount);
+            check_usd_limit(&approval_data, usd_price.as_ref(), total_amount, SOL_DECIMALS)?;
 
-            // Create transfer instruction - SPL Token requires specific account order
-            let transfer_ix = create_transfer_instruction(
-                token_program.key,
-                wallet_token_account.key,    // source
-                recipient_account.key,        // destination
-                token_mint_account.key,       // mint
-                wallet_account.key,           // authority
+            let recipients = account_info_iter.as_slice();
+            for (index, amount) in transfers.iter() {
+                let recipient_account = recipients
+                    .get(*index as usize)
+                    .ok_or(ProgramError::NotEnoughAccountKeys)?;
+                check_approved_recipient(&approval_data, recipient_account.key)?;
+
+                **wallet_account.try_borrow_mut_lamports()? -= amount;
+                **recipient_account.try_borrow_mut_lamports()? += amount;
+                paid.push((*recipient_account.key, *amount));
+            }
+            total_amount
+        }
+        TransferType::Token => {
+            let token_accounts = TokenTransferAccounts::next(account_info_iter)?;
+            if *token_accounts.token_mint.key != approval_data.token_mint {
+                msg!("Approval is not for this token");
+                return Err(ProgramError::InvalidAccountData);
+            }
+
+            // Transfer fees are paid by the wallet and count against the allowance
+            let mut transfer_fees = Vec::with_capacity(transfers.len());
+            let mut total_debit = total_amount;
+            for (_, amount) in transfers.iter() {
+                let transfer_fee = get_transfer_fee(token_accounts.token_mint, token_accounts.token_program.key, *amount)?;
+                total_debit = total_debit
+                    .checked_add(transfer_fee.unwrap_or(0))
+                    .ok_or(ProgramError::ArithmeticOverflow)?;
+                transfer_fees.push(transfer_fee);
+            }
+            if total_debit > u64::from(approval_data.max_amount) {
+                msg!("Batch total including transfer fees is {}, greater than the max amount of {}", total_debit, u64::from(approval_data.max_amount));
+                return Err(WalletErrorCode::AllowanceExceeded.into());
+            }
+
+            if usd_price.is_some() {
+                let decimals = get_token_decimals(token_accounts.token_mint, token_accounts.token_program.key)?;
+                check_usd_limit(&approval_data, usd_price.as_ref(), total_debit, decimals)?;
+            }
+
+            if spendable_lamports(wallet_account)? < TRANSACTION_FEE {
+                msg!("Wallet does not have enough balance for the fee");
+                return Err(ProgramError::InsufficientFunds);
+            }
+
+            // The transfers index the recipients; the transfer-hook accounts follow the last one
+            let recipient_count = transfers.iter().map(|(index, _)| *index as usize + 1).max().unwrap_or(0);
+            let trailing_accounts = account_info_iter.as_slice();
+            if trailing_accounts.len() < recipient_count {
+                return Err(ProgramError::NotEnoughAccountKeys);
+            }
+            let (recipients, hook_accounts) = trailing_accounts.split_at(recipient_count);
+            for ((index, amount), transfer_fee) in transfers.iter().zip(transfer_fees) {
+                let recipient_account = &recipients[*index as usize];
+                check_approved_recipient(&approval_data, recipient_account.key)?;
+
+                transfer_tokens_from_wallet(
+                    &token_accounts,
+                    recipient_account,
+                    wallet_account,
+                    hook_accounts,
+                    wallet_seeds,
+                    *amount,
+                    transfer_fee,
+                )?;
+                paid.push((*recipient_account.key, *amount));
+            }
+            total_debit
+        }
+    };
+
+    // The batch counts as a single execution of the approval
+    record_approval_execution(&mut approval_data, approval_account, dapp_account, user_account, system_program, debited_amount)?;
+
+    // A single fee reimbursement covers the whole batch
+    **wallet_account.try_borrow_mut_lamports()? -= TRANSACTION_FEE;
+    **dapp_account.try_borrow_mut_lamports()? += TRANSACTION_FEE;
+
+    WalletEvent::BatchExecuted {
+        approval: *approval_account.key,
+        wallet: *wallet_account.key,
+        token_mint: approval_data.token_mint,
+        transfers: paid,
+        debited_amount,
+    }
+    .emit()?;
+
+    debug_msg!("Batch of {} transfers executed, total: {}", transfers.len(), total_amount);
+    Ok(())
+}
+
+pub fn withdraw(program_id: &Pubkey, accounts: &[AccountInfo], amount: u64, transfer_type: TransferType) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let user_account = next_account_info(account_info_iter)?;
+    let wallet_account = next_account_info(account_info_iter)?;
+    let recipient_account = next_account_info(account_info_iter)?;
+    let _system_program = next_account_info(account_info_iter)?;
+
+    // check the signer
+    if !user_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    // Verify that the provided wallet account is actually the PDA we expect
+    let bump_seed = verify_wallet_address(program_id, wallet_account, user_account.key)?;
+
+    let token_mint = match transfer_type {
+        TransferType::Sol => {
+            debug_msg!("Withdrawing SOL from wallet {:?} to recipient {:?}, amount: {:?}", wallet_account.key, recipient_account.key, amount);
+            debug_msg!("Wallet account's balance: {:?}", wallet_account.lamports());
+
+            // Check if wallet has enough balance for withdrawal
+            if spendable_lamports(wallet_account)? < amount {
+                msg!("Wallet does not have enough balance for withdrawal");
+                return Err(ProgramError::InsufficientFunds);
+            }
+    
+            // Withdraw amount from wallet
+            **wallet_account.try_borrow_mut_lamports()? -= amount;
+            **recipient_account.try_borrow_mut_lamports()? += amount;
+            Pubkey::default()
+        }
+        TransferType::Token => {
+            debug_msg!("Withdrawing token");
+            let token_accounts = TokenTransferAccounts::next(account_info_iter)?;
+            let transfer_fee = get_transfer_fee(token_accounts.token_mint, token_accounts.token_program.key, amount)?;
+
+            transfer_tokens_from_wallet(
+                &token_accounts,
+                recipient_account,
+                wallet_account,
+                account_info_iter.as_slice(),
+                &[b"wallet", user_account.key.as_ref(), &[bump_seed]],
                 amount,
-                decimals,
+                transfer_fee,
             )?;
+            *token_accounts.token_mint.key
+        }
+    };
 
-            // Execute transfer with accounts in SPL Token's required order
-            invoke_signed(
-                &transfer_ix,
-                &[
-                    wallet_token_account.clone(),    // source
-                    token_mint_account.clone(),      // mint
-                    recipient_account.clone(),       // destination
-                    wallet_account.clone(),          // authority
-                    token_program.clone(),           // program
-                ],
-                &[&[b"wallet", user_account.key.as_ref(), &[bump_seed]]],
-            )?;
+    WalletEvent::Withdrawn {
+        wallet: *wallet_account.key,
+        recipient: *recipient_account.key,
+        token_mint,
+        amount,
+    }
+    .emit()?;
+
+    debug_msg!("Withdrawal completed successfully");
+    Ok(())
+}
+pub fn create_standing_order(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    order_id: u64,
+    amount: u64,
+    interval: i64,
+    start_time: i64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let user_account = next_account_info(account_info_iter)?;
+    let wallet_account = next_account_info(account_info_iter)?;
+    let recipient_account = next_account_info(account_info_iter)?;
+    let token_mint_account = next_account_info(account_info_iter)?;
+    let order_account = next_account_info(account_info_iter)?;
+    let system_program = next_account_info(account_info_iter)?;
+
+    if !user_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    verify_wallet_address(program_id, wallet_account, user_account.key)?;
+
+    if amount == 0 || interval <= 0 {
+        msg!("Standing order amount and interval must be positive");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    // Orders pay from their start time on; a start in the past would pay out at once
+    if start_time < Clock::get()?.unix_timestamp {
+        msg!("Standing order start time {} is in the past", start_time);
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let (order_address, bump_seed) = derive_standing_order_address(wallet_account.key, order_id, program_id);
+    if order_address != *order_account.key {
+        msg!("Standing order account does not match");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if !order_account.data_is_empty() {
+        msg!("Standing order already exists");
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
+
+    let rent = Rent::get()?;
+    invoke_signed(
+        &system_instruction::create_account(
+            user_account.key,
+            order_account.key,
+            rent.minimum_balance(STANDING_ORDER_SIZE),
+            STANDING_ORDER_SIZE as u64,
+            program_id,
+        ),
+        &[user_account.clone(), order_account.clone(), system_program.clone()],
+        &[&[b"standing_order", wallet_account.key.as_ref(), &order_id.to_le_bytes(), &[bump_seed]]],
+    )?;
+
+    let order_data = StandingOrder::new(
+        *user_account.key,
+        *recipient_account.key,
+        *token_mint_account.key,
+        amount,
+        interval,
+        start_time,
+    );
+    pack_standing_order_data(&order_data, order_account)?;
+
+    debug_msg!("Standing order created");
+    Ok(())
+}
+
+pub fn crank_standing_order(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let cranker_account = next_account_info(account_info_iter)?;
+    let wallet_account = next_account_info(account_info_iter)?;
+    let order_account = next_account_info(account_info_iter)?;
+    let recipient_account = next_account_info(account_info_iter)?;
+    let _system_program = next_account_info(account_info_iter)?;
+
+    if !cranker_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    if order_account.owner != program_id {
+        msg!("Standing order account is not owned by the program");
+        return Err(ProgramError::IllegalOwner);
+    }
+
+    let mut order_data = unpack_standing_order_data(order_account)?;
+
+    let bump_seed = verify_wallet_address(program_id, wallet_account, &order_data.owner)?;
+
+    if order_data.recipient != *recipient_account.key {
+        msg!("Recipient account does not match the standing order");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let clock = Clock::get()?;
+    if clock.unix_timestamp < i64::from(order_data.next_due) {
+        msg!("Standing order is not due until {}", i64::from(order_data.next_due));
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if order_data.token_mint == Pubkey::default() {
+        let sol_outflow = u64::from(order_data.amount).checked_add(CRANK_REWARD).ok_or(ProgramError::ArithmeticOverflow)?;
+        if spendable_lamports(wallet_account)? < sol_outflow {
+            msg!("Wallet does not have enough balance for payment and crank reward");
+            return Err(ProgramError::InsufficientFunds);
+        }
+
+        **wallet_account.try_borrow_mut_lamports()? -= u64::from(order_data.amount);
+        **recipient_account.try_borrow_mut_lamports()? += u64::from(order_data.amount);
+    } else {
+        let token_accounts = TokenTransferAccounts::next(account_info_iter)?;
+        if *token_accounts.token_mint.key != order_data.token_mint {
+            msg!("Token mint does not match the standing order");
+            return Err(ProgramError::InvalidAccountData);
         }
+
+        if spendable_lamports(wallet_account)? < CRANK_REWARD {
+            msg!("Wallet does not have enough balance for crank reward");
+            return Err(ProgramError::InsufficientFunds);
+        }
+
+        let transfer_fee = get_transfer_fee(token_accounts.token_mint, token_accounts.token_program.key, u64::from(order_data.amount))?;
+
+        transfer_tokens_from_wallet(
+            &token_accounts,
+            recipient_account,
+            wallet_account,
+            account_info_iter.as_slice(),
+            &[b"wallet", order_data.owner.as_ref(), &[bump_seed]],
+            u64::from(order_data.amount),
+            transfer_fee,
+        )?;
+    }
+
+    // Pay the crank reward
+    **wallet_account.try_borrow_mut_lamports()? -= CRANK_REWARD;
+    **cranker_account.try_borrow_mut_lamports()? += CRANK_REWARD;
+
+    // One payment per crank: intervals missed while nobody cranked are skipped, so the next
+    // payment falls due at the first interval boundary after now
+    let interval = i64::from(order_data.interval);
+    let intervals_due = (clock.unix_timestamp - i64::from(order_data.next_due)) / interval + 1;
+    order_data.next_due = intervals_due
+        .checked_mul(interval)
+        .and_then(|elapsed| i64::from(order_data.next_due).checked_add(elapsed))
+        .ok_or(ProgramError::ArithmeticOverflow)?
+        .into();
+    pack_standing_order_data(&order_data, order_account)?;
+
+    WalletEvent::StandingOrderPaid {
+        order: *order_account.key,
+        wallet: *wallet_account.key,
+        recipient: *recipient_account.key,
+        token_mint: order_data.token_mint,
+        amount: u64::from(order_data.amount),
+        next_due: i64::from(order_data.next_due),
+    }
+    .emit()?;
+
+    debug_msg!("Standing order paid, next due at {}", i64::from(order_data.next_due));
+    Ok(())
+}
+
+pub fn cancel_standing_order(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let user_account = next_account_info(account_info_iter)?;
+    let wallet_account = next_account_info(account_info_iter)?;
+    let order_account = next_account_info(account_info_iter)?;
+
+    if !user_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    verify_wallet_address(program_id, wallet_account, user_account.key)?;
+
+    if order_account.owner != program_id {
+        msg!("Standing order account is not owned by the program");
+        return Err(ProgramError::IllegalOwner);
+    }
+
+    let order_data = unpack_standing_order_data(order_account)?;
+    if order_data.owner != *user_account.key {
+        msg!("Standing order does not belong to this wallet");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    // Close the order account, returning its rent to the owner
+    close_program_account(order_account, user_account)?;
+
+    debug_msg!("Standing order cancelled");
+    Ok(())
+}
+
+pub fn approve_cpi(program_id: &Pubkey, accounts: &[AccountInfo], max_amount: u64, expiry: i64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let user_account = next_account_info(account_info_iter)?;
+    let wallet_account = next_account_info(account_info_iter)?;
+    let dapp_account = next_account_info(account_info_iter)?;
+    let target_program = next_account_info(account_info_iter)?;
+    let approval_account = next_account_info(account_info_iter)?;
+    let system_program = next_account_info(account_info_iter)?;
+
+    if !user_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    verify_wallet_address(program_id, wallet_account, user_account.key)?;
+
+    if target_program.key == program_id {
+        msg!("The wallet program cannot be a CPI target");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let (approval_address, bump_seed) = derive_cpi_approval_address(
+        wallet_account.key,
+        dapp_account.key,
+        target_program.key,
+        program_id,
+    );
+    if approval_address != *approval_account.key {
+        msg!("CPI approval account does not match");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if approval_account.data_is_empty() {
+        let rent = Rent::get()?;
+        invoke_signed(
+            &system_instruction::create_account(
+                user_account.key,
+                approval_account.key,
+                rent.minimum_balance(CPI_APPROVAL_SIZE),
+                CPI_APPROVAL_SIZE as u64,
+                program_id,
+            ),
+            &[user_account.clone(), approval_account.clone(), system_program.clone()],
+            &[&[b"cpi_approval", wallet_account.key.as_ref(), dapp_account.key.as_ref(), target_program.key.as_ref(), &[bump_seed]]],
+        )?;
     }
 
-    msg!("Withdrawal completed successfully");
+    let approval_data = CpiApproval::new(*target_program.key, max_amount, expiry);
+    pack_cpi_approval_data(&approval_data, approval_account)?;
+
+    debug_msg!("dApp approved for CPI into {:?}", target_program.key);
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Balance and authorities of a wallet-owned token account, captured before a CPI.
+struct TokenAccountSnapshot {
+    index: usize,
+    amount: u64,
+    delegate: COption<Pubkey>,
+    close_authority: COption<Pubkey>,
+}
+
+/// Balance and authorities of a stake account the wallet can withdraw, captured before a CPI.
+struct StakeAccountSnapshot {
+    index: usize,
+    lamports: u64,
+    authorized: Authorized,
+}
+
+/// Reads the authorities of a stake account if the wallet is its withdrawer.
+fn unpack_wallet_stake_authorities(account: &AccountInfo, wallet: &Pubkey) -> Result<Option<Authorized>, ProgramError> {
+    if account.owner != &stake::program::id() {
+        return Ok(None);
+    }
+
+    let data = account.try_borrow_data()?;
+    let authorized = solana_program::borsh1::try_from_slice_unchecked::<StakeStateV2>(&data)
+        .ok()
+        .and_then(|state| state.authorized());
+    Ok(authorized.filter(|authorized| authorized.withdrawer == *wallet))
+}
+
+/// Reads a token account from either token program if it is owned by the wallet.
+fn unpack_wallet_token_account(
+    account: &AccountInfo,
+    wallet: &Pubkey,
+) -> Result<Option<spl_token_2022::state::Account>, ProgramError> {
+    if account.owner != &spl_token::id() && account.owner != &spl_token_2022::id() {
+        return Ok(None);
+    }
+
+    let data = account.try_borrow_data()?;
+    match StateWithExtensions::<spl_token_2022::state::Account>::unpack(&data) {
+        Ok(state) if state.base.owner == *wallet => Ok(Some(state.base)),
+        _ => Ok(None),
+    }
+}
+
+pub fn execute_cpi(program_id: &Pubkey, accounts: &[AccountInfo], instruction_data: Vec<u8>) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let authority_account = next_account_info(account_info_iter)?;
+    let user_account = next_account_info(account_info_iter)?;
+    let wallet_account = next_account_info(account_info_iter)?;
+    let approval_account = next_account_info(account_info_iter)?;
+    let target_program = next_account_info(account_info_iter)?;
+    let cpi_accounts = account_info_iter.as_slice();
+
+    if !authority_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let bump_seed = verify_wallet_address(program_id, wallet_account, user_account.key)?;
+
+    if wallet_account.owner != program_id {
+        msg!("Wallet account is not owned by the program");
+        return Err(ProgramError::IllegalOwner);
+    }
+
+    if target_program.key == program_id {
+        msg!("The wallet program cannot be a CPI target");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    // The owner may call anything; a dApp is limited by its CPI approval for this program
+    let max_amount = if authority_account.key == user_account.key {
+        None
+    } else {
+        if approval_account.owner != program_id {
+            msg!("CPI approval account is not owned by the program");
+            return Err(ProgramError::IllegalOwner);
+        }
+
+        let (approval_address, _) = derive_cpi_approval_address(
+            wallet_account.key,
+            authority_account.key,
+            target_program.key,
+            program_id,
+        );
+        if approval_address != *approval_account.key {
+            msg!("CPI approval account does not match");
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let approval_data = unpack_cpi_approval_data(approval_account)?;
+        if !bool::from(approval_data.is_approved) {
+            msg!("Dapp is not approved for this program");
+            return Err(WalletErrorCode::ApprovalRevoked.into());
+        }
+
+        if Clock::get()?.unix_timestamp >= i64::from(approval_data.expiry) {
+            msg!("CPI approval expired at {}", i64::from(approval_data.expiry));
+            return Err(WalletErrorCode::ApprovalExpired.into());
+        }
+
+        Some(u64::from(approval_data.max_amount))
+    };
+
+    // Snapshot the wallet's balances so the dApp's limit can be enforced afterwards
+    let wallet_lamports_before = wallet_account.lamports();
+    let mut token_snapshots = vec![];
+    for (index, account) in cpi_accounts.iter().enumerate() {
+        if let Some(token_account) = unpack_wallet_token_account(account, wallet_account.key)? {
+            token_snapshots.push(TokenAccountSnapshot {
+                index,
+                amount: token_account.amount,
+                delegate: token_account.delegate,
+                close_authority: token_account.close_authority,
+            });
+        }
+    }
+
+    // The wallet's stake accounts hold its lamports too, behind the wallet's authority
+    let mut stake_snapshots = vec![];
+    for (index, account) in cpi_accounts.iter().enumerate() {
+        if let Some(authorized) = unpack_wallet_stake_authorities(account, wallet_account.key)? {
+            stake_snapshots.push(StakeAccountSnapshot { index, lamports: account.lamports(), authorized });
+        }
+    }
+
+    let instruction = Instruction {
+        program_id: *target_program.key,
+        accounts: cpi_accounts
+            .iter()
+            .map(|account| AccountMeta {
+                pubkey: *account.key,
+                is_signer: account.is_signer || account.key == wallet_account.key,
+                is_writable: account.is_writable,
+            })
+            .collect(),
+        data: instruction_data,
+    };
+
+    let mut account_infos = cpi_accounts.to_vec();
+    account_infos.push(target_program.clone());
+
+    invoke_signed(
+        &instruction,
+        &account_infos,
+        &[&[b"wallet", user_account.key.as_ref(), &[bump_seed]]],
+    )?;
+
+    if let Some(max_amount) = max_amount {
+        if wallet_lamports_before.saturating_sub(wallet_account.lamports()) > max_amount {
+            msg!("CPI moved more lamports out of the wallet than approved");
+            return Err(ProgramError::InsufficientFunds);
+        }
+
+        for snapshot in token_snapshots.iter() {
+            let account = &cpi_accounts[snapshot.index];
+            let token_account = unpack_wallet_token_account(account, wallet_account.key)?.ok_or_else(|| {
+                msg!("Wallet token account {:?} was closed or reassigned", account.key);
+                ProgramError::InvalidAccountData
+            })?;
+
+            if token_account.delegate != snapshot.delegate
+                || token_account.close_authority != snapshot.close_authority
+            {
+                msg!("CPI changed the authorities of wallet token account {:?}", account.key);
+                return Err(ProgramError::InvalidAccountData);
+            }
+
+            if snapshot.amount.saturating_sub(token_account.amount) > max_amount {
+                msg!("CPI moved more tokens out of {:?} than approved", account.key);
+                return Err(ProgramError::InsufficientFunds);
+            }
+        }
+
+        for snapshot in stake_snapshots.iter() {
+            let account = &cpi_accounts[snapshot.index];
+            let authorized = unpack_wallet_stake_authorities(account, wallet_account.key)?;
+            if authorized != Some(snapshot.authorized) || account.lamports() != snapshot.lamports {
+                msg!("CPI changed the balance or authorities of wallet stake account {:?}", account.key);
+                return Err(ProgramError::InvalidAccountData);
+            }
+        }
+    }
+
+    WalletEvent::CpiExecuted {
+        wallet: *wallet_account.key,
+        authority: *authority_account.key,
+        program_id: *target_program.key,
+    }
+    .emit()?;
+
+    debug_msg!("CPI executed successfully");
+    Ok(())
+}
+
+pub fn wrap_sol(program_id: &Pubkey, accounts: &[AccountInfo], amount: u64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let user_account = next_account_info(account_info_iter)?;
+    let wallet_account = next_account_info(account_info_iter)?;
+    let wallet_token_account = next_account_info(account_info_iter)?;
+    let native_mint_account = next_account_info(account_info_iter)?;
+    let token_program = next_account_info(account_info_iter)?;
+    let system_program = next_account_info(account_info_iter)?;
+    let associated_token_program = next_account_info(account_info_iter)?;
+
+    if !user_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    verify_wallet_address(program_id, wallet_account, user_account.key)?;
+
+    if *native_mint_account.key != native_mint(token_program.key)? {
+        msg!("Mint is not the native mint");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    verify_wallet_native_token_account(wallet_account, wallet_token_account, token_program)?;
+
+    if wallet_token_account.data_is_empty() {
+        invoke(
+            &create_associated_token_account_idempotent(
+                user_account.key,
+                wallet_account.key,
+                native_mint_account.key,
+                token_program.key,
+            ),
+            &[
+                user_account.clone(),
+                wallet_token_account.clone(),
+                wallet_account.clone(),
+                native_mint_account.clone(),
+                system_program.clone(),
+                token_program.clone(),
+                associated_token_program.clone(),
+            ],
+        )?;
+    }
+
+    wrap_lamports(wallet_account, wallet_token_account, token_program, amount)?;
+
+    debug_msg!("Wrapped {} lamports", amount);
+    Ok(())
+}
+
+pub fn unwrap_sol(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let user_account = next_account_info(account_info_iter)?;
+    let wallet_account = next_account_info(account_info_iter)?;
+    let wallet_token_account = next_account_info(account_info_iter)?;
+    let token_program = next_account_info(account_info_iter)?;
+
+    if !user_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let bump_seed = verify_wallet_address(program_id, wallet_account, user_account.key)?;
+
+    verify_wallet_native_token_account(wallet_account, wallet_token_account, token_program)?;
+
+    // Closing the native account sends both the wrapped SOL and its rent back to the wallet
+    let close_ix = if token_program.key == &spl_token::id() {
+        spl_token::instruction::close_account(
+            token_program.key,
+            wallet_token_account.key,
+            wallet_account.key,
+            wallet_account.key,
+            &[],
+        )?
+    } else {
+        spl_token_2022::instruction::close_account(
+            token_program.key,
+            wallet_token_account.key,
+            wallet_account.key,
+            wallet_account.key,
+            &[],
+        )?
+    };
+
+    invoke_signed(
+        &close_ix,
+        &[wallet_token_account.clone(), wallet_account.clone(), wallet_account.clone(), token_program.clone()],
+        &[&[b"wallet", user_account.key.as_ref(), &[bump_seed]]],
+    )?;
+
+    debug_msg!("Unwrapped SOL into the wallet");
+    Ok(())
+}
+
+/// Marks a permit nonce as used by creating its marker account, paid for by `payer_account`.
+/// Fails if the nonce was already used or cancelled.
+fn use_permit_nonce<'a>(
+    program_id: &Pubkey,
+    payer_account: &AccountInfo<'a>,
+    wallet_account: &AccountInfo<'a>,
+    permit_account: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    nonce: u64,
+) -> ProgramResult {
+    let (permit_address, permit_bump) = derive_permit_address(wallet_account.key, nonce, program_id);
+    if permit_address != *permit_account.key {
+        msg!("Permit account does not match");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if !permit_account.data_is_empty() {
+        msg!("Permit has already been used");
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
+
+    let rent = Rent::get()?;
+    invoke_signed(
+        &system_instruction::create_account(
+            payer_account.key,
+            permit_account.key,
+            rent.minimum_balance(USED_PERMIT_SIZE),
+            USED_PERMIT_SIZE as u64,
+            program_id,
+        ),
+        &[payer_account.clone(), permit_account.clone(), system_program.clone()],
+        &[&[b"permit", wallet_account.key.as_ref(), &nonce.to_le_bytes(), &[permit_bump]]],
+    )?;
+    pack_used_permit_data(&UsedPermit::new(Clock::get()?.unix_timestamp), permit_account)
+}
+
+pub fn approve_dapp_with_permit(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    max_amount: u64,
+    expiry: i64,
+    nonce: u64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let dapp_account = next_account_info(account_info_iter)?;
+    let user_account = next_account_info(account_info_iter)?;
+    let wallet_account = next_account_info(account_info_iter)?;
+    let token_mint_account = next_account_info(account_info_iter)?;
+    let approval_account = next_account_info(account_info_iter)?;
+    let permit_account = next_account_info(account_info_iter)?;
+    let system_program = next_account_info(account_info_iter)?;
+    let instructions_sysvar = next_account_info(account_info_iter)?;
+
+    // The dApp submits and pays for the permit; the owner only signed it off-chain
+    if !dapp_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    verify_wallet_address(program_id, wallet_account, user_account.key)?;
+
+    if Clock::get()?.unix_timestamp >= expiry {
+        msg!("Permit has expired");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let permit = ApprovalPermit {
+        wallet: *wallet_account.key,
+        dapp: *dapp_account.key,
+        token_mint: *token_mint_account.key,
+        max_amount,
+        expiry,
+        nonce,
+    };
+    verify_ed25519_signature(instructions_sysvar, user_account.key, &permit.message())?;
+
+    // A permit only grants new approvals; it doesn't commit to the terms of an existing one,
+    // which the owner changes with UpdateApproval
+    let approval_bump = create_approval_account(
+        program_id,
+        dapp_account,
+        wallet_account,
+        dapp_account,
+        token_mint_account,
+        approval_account,
+        system_program,
+    )?;
+
+    // Each permit nonce can be used once; its marker account blocks replays
+    use_permit_nonce(program_id, dapp_account, wallet_account, permit_account, system_program, nonce)?;
+
+    let mut approval_data = DAppApproval::new(
+        *wallet_account.key,
+        *dapp_account.key,
+        *user_account.key,
+        Clock::get()?.unix_timestamp,
+        max_amount,
+        expiry,
+        *token_mint_account.key,
+    );
+    approval_data.bump = approval_bump;
+    pack_approval_data(&approval_data, approval_account)?;
+
+    debug_msg!("DApp approved with owner permit");
+    Ok(())
+}
+
+/// Burns a permit nonce so that any permit the owner signed with it can no longer be used.
+pub fn cancel_permit(program_id: &Pubkey, accounts: &[AccountInfo], nonce: u64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let user_account = next_account_info(account_info_iter)?;
+    let wallet_account = next_account_info(account_info_iter)?;
+    let permit_account = next_account_info(account_info_iter)?;
+    let system_program = next_account_info(account_info_iter)?;
+
+    if !user_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    verify_wallet_address(program_id, wallet_account, user_account.key)?;
+
+    use_permit_nonce(program_id, user_account, wallet_account, permit_account, system_program, nonce)?;
+
+    debug_msg!("Permit nonce {} cancelled", nonce);
+    Ok(())
+}
+
+pub fn execute_intent(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+    transfer_type: TransferType,
+    nonce: u64,
+    deadline: i64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let relayer_account = next_account_info(account_info_iter)?;
+    let user_account = next_account_info(account_info_iter)?;
+    let wallet_account = next_account_info(account_info_iter)?;
+    let recipient_account = next_account_info(account_info_iter)?;
+    let _system_program = next_account_info(account_info_iter)?;
+    let instructions_sysvar = next_account_info(account_info_iter)?;
+
+    // Anyone may relay the intent; the owner's authority comes from the ed25519 signature
+    if !relayer_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let bump_seed = verify_wallet_address(program_id, wallet_account, user_account.key)?;
+
+    if Clock::get()?.unix_timestamp >= deadline {
+        msg!("Intent expired at {}", deadline);
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let token_accounts = match transfer_type {
+        TransferType::Sol => None,
+        TransferType::Token => Some(TokenTransferAccounts::next(account_info_iter)?),
+    };
+    let token_mint = token_accounts
+        .as_ref()
+        .map_or(Pubkey::default(), |token_accounts| *token_accounts.token_mint.key);
+
+    let intent = TransferIntent {
+        wallet: *wallet_account.key,
+        recipient: *recipient_account.key,
+        token_mint,
+        amount,
+        nonce,
+        deadline,
+    };
+    verify_ed25519_signature(instructions_sysvar, user_account.key, &intent.message())?;
+
+    // Intents execute strictly in nonce order, each exactly once
+    let mut wallet_state = load_wallet_state(wallet_account, user_account.key, bump_seed)?;
+    if nonce != u64::from(wallet_state.intent_nonce) {
+        msg!("Intent nonce {} does not match wallet nonce {}", nonce, u64::from(wallet_state.intent_nonce));
+        return Err(ProgramError::InvalidArgument);
+    }
+    wallet_state.intent_nonce = u64::from(wallet_state.intent_nonce)
+        .checked_add(1)
+        .ok_or(ProgramError::ArithmeticOverflow)?
+        .into();
+    pack_wallet_state(&wallet_state, wallet_account)?;
+
+    let sol_outflow = match &token_accounts {
+        None => amount.checked_add(TRANSACTION_FEE).ok_or(ProgramError::ArithmeticOverflow)?,
+        Some(_) => TRANSACTION_FEE,
+    };
+    let rent_exempt_balance = Rent::get()?.minimum_balance(wallet_account.data_len());
+    if wallet_account.lamports().saturating_sub(rent_exempt_balance) < sol_outflow {
+        msg!("Wallet does not have enough SOL for the intent and relayer fee");
+        return Err(ProgramError::InsufficientFunds);
+    }
+
+    match token_accounts {
+        None => {
+            **wallet_account.try_borrow_mut_lamports()? -= amount;
+            **recipient_account.try_borrow_mut_lamports()? += amount;
+        }
+        Some(token_accounts) => {
+            let transfer_fee = get_transfer_fee(token_accounts.token_mint, token_accounts.token_program.key, amount)?;
+
+            transfer_tokens_from_wallet(
+                &token_accounts,
+                recipient_account,
+                wallet_account,
+                account_info_iter.as_slice(),
+                &[b"wallet", user_account.key.as_ref(), &[bump_seed]],
+                amount,
+                transfer_fee,
+            )?;
+        }
+    }
+
+    // Reimburse the relayer for submitting the transaction
+    **wallet_account.try_borrow_mut_lamports()? -= TRANSACTION_FEE;
+    **relayer_account.try_borrow_mut_lamports()? += TRANSACTION_FEE;
+
+    WalletEvent::IntentExecuted {
+        wallet: *wallet_account.key,
+        recipient: *recipient_account.key,
+        token_mint,
+        amount,
+        nonce,
+    }
+    .emit()?;
+
+    debug_msg!("Intent {} executed", nonce);
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn update_approval(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    max_amount: Option<u64>,
+    expiry: Option<i64>,
+    recipients: Option<Vec<Pubkey>>,
+    is_approved: Option<bool>,
+    valid_from: Option<i64>,
+    max_executions: Option<u64>,
+    single_use: Option<bool>,
+    usd_limit: Option<Option<UsdLimit>>,
+    auto_limit: Option<u64>,
+    cosign_limit: Option<u64>,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let user_account = next_account_info(account_info_iter)?;
+    let wallet_account = next_account_info(account_info_iter)?;
+    let dapp_account = next_account_info(account_info_iter)?;
+    let approval_account = next_account_info(account_info_iter)?;
+    let system_program = next_account_info(account_info_iter)?;
+
+    if !user_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    verify_wallet_address(program_id, wallet_account, user_account.key)?;
+
+    if approval_account.owner != program_id {
+        msg!("Approval account is not owned by the program");
+        return Err(ProgramError::IllegalOwner);
+    }
+
+    let mut approval_data = unpack_approval_data(approval_account)?;
+    approval_data.bump = verify_program_address(
+        approval_account.key,
+        &[b"approval", wallet_account.key.as_ref(), dapp_account.key.as_ref(), approval_data.token_mint.as_ref()],
+        (approval_account.data_len() == DAPP_APPROVAL_SIZE).then_some(approval_data.bump),
+        program_id,
+    )
+    .ok_or_else(|| {
+        msg!("Approval account does not match");
+        ProgramError::InvalidAccountData
+    })?;
+    approval_data.set_keys(*wallet_account.key, *dapp_account.key, *user_account.key);
+
+    // Only the given fields change; usage counters are always carried over
+    if let Some(max_amount) = max_amount {
+        approval_data.max_amount = max_amount.into();
+    }
+    if let Some(expiry) = expiry {
+        approval_data.expiry = expiry.into();
+    }
+    if let Some(recipients) = recipients {
+        approval_data.set_recipients(&recipients)?;
+    }
+    if let Some(is_approved) = is_approved {
+        approval_data.is_approved = is_approved.into();
+    }
+    if let Some(valid_from) = valid_from {
+        approval_data.valid_from = valid_from.into();
+    }
+    if let Some(max_executions) = max_executions {
+        approval_data.max_executions = max_executions.into();
+    }
+    if let Some(single_use) = single_use {
+        approval_data.single_use = single_use.into();
+    }
+    if let Some(usd_limit) = usd_limit {
+        approval_data.set_usd_limit(usd_limit);
+    }
+    if let Some(auto_limit) = auto_limit {
+        approval_data.auto_limit = auto_limit.into();
+    }
+    if let Some(cosign_limit) = cosign_limit {
+        approval_data.cosign_limit = cosign_limit.into();
+    }
+
+    // Co-signed amounts are still charged against the allowance, so the limits must nest
+    let cosign_limit = u64::from(approval_data.cosign_limit);
+    if cosign_limit > 0
+        && (u64::from(approval_data.auto_limit) > cosign_limit || cosign_limit > u64::from(approval_data.max_amount))
+    {
+        msg!("Co-signing limits must satisfy auto limit <= co-sign limit <= max amount");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    grow_account(user_account, approval_account, system_program, DAPP_APPROVAL_SIZE)?;
+    pack_approval_data(&approval_data, approval_account)?;
+
+    WalletEvent::ApprovalUpdated {
+        approval: *approval_account.key,
+        is_approved: bool::from(approval_data.is_approved),
+        max_amount: u64::from(approval_data.max_amount),
+        expiry: i64::from(approval_data.expiry),
+        valid_from: i64::from(approval_data.valid_from),
+        max_executions: u64::from(approval_data.max_executions),
+        single_use: bool::from(approval_data.single_use),
+        usd_limit: approval_data.usd_limit(),
+        auto_limit: u64::from(approval_data.auto_limit),
+        cosign_limit: u64::from(approval_data.cosign_limit),
+        recipients: approval_data.recipients().to_vec(),
+        spent_amount: u64::from(approval_data.spent_amount),
+        execution_count: u64::from(approval_data.execution_count),
+    }
+    .emit()?;
+
+    debug_msg!("Approval updated");
+    Ok(())
+}
+
+/// Rewrites an account in the current layout of `T`, growing it first if needed.
+fn migrate_to_current<'a, T: ProgramAccount>(
+    payer_account: &AccountInfo<'a>,
+    account: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    account_data: &T,
+) -> ProgramResult {
+    grow_account(payer_account, account, system_program, T::SIZE)?;
+    pack_account(account_data, account)
+}
+
+pub fn migrate_account(program_id: &Pubkey, accounts: &[AccountInfo], kind: AccountKind) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let payer_account = next_account_info(account_info_iter)?;
+    let account = next_account_info(account_info_iter)?;
+    let system_program = next_account_info(account_info_iter)?;
+
+    if !payer_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    if account.owner != program_id {
+        msg!("Account is not owned by the program");
+        return Err(ProgramError::IllegalOwner);
+    }
+
+    // Migration only re-encodes existing data, so anyone may pay for it. Headerless accounts
+    // don't record their kind, so the PDA is re-derived to make sure `kind` is right.
+    match kind {
+        AccountKind::Wallet => {
+            let user_account = next_account_info(account_info_iter)?;
+            let bump_seed = verify_wallet_address(program_id, account, user_account.key)?;
+
+            let mut wallet_state = if account.data_is_empty() {
+                WalletState::new(*user_account.key, bump_seed)
+            } else {
+                unpack_wallet_state(account)?
+            };
+            wallet_state.bump = bump_seed;
+            migrate_to_current(payer_account, account, system_program, &wallet_state)?;
+        }
+        AccountKind::DAppApproval => {
+            let wallet_account = next_account_info(account_info_iter)?;
+            let dapp_account = next_account_info(account_info_iter)?;
+            let user_account = next_account_info(account_info_iter)?;
+
+            verify_wallet_address(program_id, wallet_account, user_account.key)?;
+
+            let mut approval_data = unpack_approval_data(account)?;
+            let (approval_address, approval_bump) = derive_approval_address(
+                wallet_account.key,
+                dapp_account.key,
+                &approval_data.token_mint,
+                program_id,
+            );
+            if approval_address != *account.key {
+                msg!("Approval account does not match");
+                return Err(ProgramError::InvalidAccountData);
+            }
+            approval_data.bump = approval_bump;
+            approval_data.set_keys(*wallet_account.key, *dapp_account.key, *user_account.key);
+            migrate_to_current(payer_account, account, system_program, &approval_data)?;
+        }
+        _ => {
+            msg!("{:?} accounts have no older layouts", kind);
+            return Err(ProgramError::InvalidArgument);
+        }
+    }
+
+    debug_msg!("Migrated {:?} account to version {}", kind, account.data.borrow()[1]);
+    Ok(())
+}
+
+pub fn propose_execution(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+    request_id: [u8; 32],
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let dapp_account = next_account_info(account_info_iter)?;
+    let user_account = next_account_info(account_info_iter)?;
+    let wallet_account = next_account_info(account_info_iter)?;
+    let approval_account = next_account_info(account_info_iter)?;
+    let recipient_account = next_account_info(account_info_iter)?;
+    let pending_account = next_account_info(account_info_iter)?;
+    let system_program = next_account_info(account_info_iter)?;
+
+    let (approval_data, _) = verify_dapp_approval(
+        program_id,
+        dapp_account,
+        user_account,
+        wallet_account,
+        approval_account,
+    )?;
+
+    if u64::from(approval_data.cosign_limit) == 0 {
+        msg!("Approval does not allow co-signed executions");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if amount <= u64::from(approval_data.auto_limit) || amount > u64::from(approval_data.cosign_limit) {
+        msg!("Amount must be above the auto limit of {} and at most the co-sign limit of {}", u64::from(approval_data.auto_limit), u64::from(approval_data.cosign_limit));
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    check_approved_recipient(&approval_data, recipient_account.key)?;
+
+    let (pending_address, bump_seed) = derive_pending_execution_address(approval_account.key, &request_id, program_id);
+    if pending_address != *pending_account.key {
+        msg!("Pending execution account does not match");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if !pending_account.data_is_empty() {
+        msg!("Execution has already been proposed");
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
+
+    let rent = Rent::get()?;
+    invoke_signed(
+        &system_instruction::create_account(
+            dapp_account.key,
+            pending_account.key,
+            rent.minimum_balance(PENDING_EXECUTION_SIZE),
+            PENDING_EXECUTION_SIZE as u64,
+            program_id,
+        ),
+        &[dapp_account.clone(), pending_account.clone(), system_program.clone()],
+        &[&[b"pending", approval_account.key.as_ref(), &request_id, &[bump_seed]]],
+    )?;
+
+    let expires_at = Clock::get()?
+        .unix_timestamp
+        .checked_add(PENDING_EXECUTION_TTL)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    let pending_data = PendingExecution::new(
+        *approval_account.key,
+        *user_account.key,
+        *dapp_account.key,
+        *recipient_account.key,
+        amount,
+        expires_at,
+    );
+    pack_pending_execution_data(&pending_data, pending_account)?;
+
+    debug_msg!("Execution of {} proposed, awaiting owner confirmation until {}", amount, expires_at);
+    Ok(())
+}
+
+pub fn confirm_execution(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let user_account = next_account_info(account_info_iter)?;
+    let wallet_account = next_account_info(account_info_iter)?;
+    let approval_account = next_account_info(account_info_iter)?;
+    let pending_account = next_account_info(account_info_iter)?;
+    let dapp_account = next_account_info(account_info_iter)?;
+    let recipient_account = next_account_info(account_info_iter)?;
+    let system_program = next_account_info(account_info_iter)?;
+
+    // The owner's signature is the co-signature
+    if !user_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    if pending_account.owner != program_id {
+        msg!("Pending execution account is not owned by the program");
+        return Err(ProgramError::IllegalOwner);
+    }
+
+    let pending_data = unpack_pending_execution_data(pending_account)?;
+    if pending_data.approval != *approval_account.key
+        || pending_data.owner != *user_account.key
+        || pending_data.dapp != *dapp_account.key
+        || pending_data.recipient != *recipient_account.key
+    {
+        msg!("Accounts do not match the pending execution");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if Clock::get()?.unix_timestamp >= i64::from(pending_data.expires_at) {
+        msg!("Pending execution expired at {}", i64::from(pending_data.expires_at));
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let (mut approval_data, bump_seed) = load_active_approval(
+        program_id,
+        dapp_account,
+        user_account,
+        wallet_account,
+        approval_account,
+    )?;
+
+    // The approval may have changed since the execution was proposed
+    if u64::from(pending_data.amount) > u64::from(approval_data.cosign_limit) {
+        msg!("Amount is above the approval's co-sign limit");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let transfer_type = if approval_data.token_mint == Pubkey::default() {
+        TransferType::Sol
+    } else {
+        TransferType::Token
+    };
+
+    // Close the pending execution first, returning its rent to the dApp that proposed it
+    close_program_account(pending_account, dapp_account)?;
+
+    let transfer_accounts = ApprovalTransferAccounts {
+        payer: user_account,
+        user: user_account,
+        wallet: wallet_account,
+        approval: approval_account,
+        recipient: recipient_account,
+        system_program,
+    };
+    transfer_under_approval(
+        &mut approval_data,
+        &transfer_accounts,
+        account_info_iter,
+        u64::from(pending_data.amount),
+        transfer_type,
+        bump_seed,
+    )?;
+
+    debug_msg!("Pending execution confirmed");
+    Ok(())
+}
+
+pub fn cancel_execution(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let signer_account = next_account_info(account_info_iter)?;
+    let pending_account = next_account_info(account_info_iter)?;
+    let dapp_account = next_account_info(account_info_iter)?;
+
+    if !signer_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    if pending_account.owner != program_id {
+        msg!("Pending execution account is not owned by the program");
+        return Err(ProgramError::IllegalOwner);
+    }
+
+    let pending_data = unpack_pending_execution_data(pending_account)?;
+    if pending_data.dapp != *dapp_account.key {
+        msg!("dApp account does not match the pending execution");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    // Either side can drop the request at any time
+    if *signer_account.key != pending_data.owner && *signer_account.key != pending_data.dapp {
+        msg!("Only the wallet owner or the dApp can cancel a pending execution");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    close_program_account(pending_account, dapp_account)?;
+
+    debug_msg!("Pending execution cancelled");
+    Ok(())
+}
+
+pub fn create_stake(program_id: &Pubkey, accounts: &[AccountInfo], stake_id: u64, amount: u64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let user_account = next_account_info(account_info_iter)?;
+    let wallet_account = next_account_info(account_info_iter)?;
+    let stake_account = next_account_info(account_info_iter)?;
+    let system_program = next_account_info(account_info_iter)?;
+    let stake_program = next_account_info(account_info_iter)?;
+    let rent_sysvar = next_account_info(account_info_iter)?;
+
+    if !user_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    verify_wallet_address(program_id, wallet_account, user_account.key)?;
+
+    let (stake_address, stake_bump) = derive_stake_address(wallet_account.key, stake_id, program_id);
+    if stake_address != *stake_account.key {
+        msg!("Stake account does not match");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    // Anyone can send lamports to the stake address before it's created, so a funded but
+    // unallocated system account is taken over rather than rejected
+    if !stake_account.data_is_empty() || *stake_account.owner != solana_program::system_program::id() {
+        msg!("Stake account already exists");
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
+
+    let rent = Rent::get()?;
+    let stake_space = StakeStateV2::size_of();
+    if stake_account.lamports().saturating_add(amount) < rent.minimum_balance(stake_space) {
+        msg!("Amount does not cover the stake account's rent-exempt reserve");
+        return Err(ProgramError::InsufficientFunds);
+    }
+
+    // The wallet keeps enough to stay rent exempt
+    let available = wallet_account
+        .lamports()
+        .saturating_sub(rent.minimum_balance(wallet_account.data_len()));
+    if available < amount {
+        msg!("Wallet does not have enough balance to stake");
+        return Err(ProgramError::InsufficientFunds);
+    }
+
+    // The wallet is program-owned, so it can't pay through the system program. Top up the
+    // stake address directly, then allocate it and hand it to the stake program.
+    **wallet_account.try_borrow_mut_lamports()? -= amount;
+    **stake_account.try_borrow_mut_lamports()? += amount;
+
+    let stake_seeds: &[&[u8]] = &[b"stake", wallet_account.key.as_ref(), &stake_id.to_le_bytes(), &[stake_bump]];
+    invoke_signed(
+        &system_instruction::allocate(stake_account.key, stake_space as u64),
+        &[stake_account.clone(), system_program.clone()],
+        &[stake_seeds],
+    )?;
+    invoke_signed(
+        &system_instruction::assign(stake_account.key, &stake::program::id()),
+        &[stake_account.clone(), system_program.clone()],
+        &[stake_seeds],
+    )?;
+
+    // The wallet PDA is both stake and withdraw authority
+    let authorized = Authorized {
+        staker: *wallet_account.key,
+        withdrawer: *wallet_account.key,
+    };
+    invoke(
+        &stake::instruction::initialize(stake_account.key, &authorized, &Lockup::default()),
+        &[stake_account.clone(), rent_sysvar.clone(), stake_program.clone()],
+    )?;
+
+    debug_msg!("Stake account created with {} lamports", amount);
+    Ok(())
+}
+
+pub fn delegate_stake(program_id: &Pubkey, accounts: &[AccountInfo], stake_id: u64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let user_account = next_account_info(account_info_iter)?;
+    let wallet_account = next_account_info(account_info_iter)?;
+    let stake_account = next_account_info(account_info_iter)?;
+    let vote_account = next_account_info(account_info_iter)?;
+    let clock_sysvar = next_account_info(account_info_iter)?;
+    let stake_history_sysvar = next_account_info(account_info_iter)?;
+    let stake_config = next_account_info(account_info_iter)?;
+    let stake_program = next_account_info(account_info_iter)?;
+
+    let wallet_bump = verify_wallet_stake(program_id, user_account, wallet_account, stake_account, stake_id)?;
+
+    invoke_signed(
+        &stake::instruction::delegate_stake(stake_account.key, wallet_account.key, vote_account.key),
+        &[
+            stake_account.clone(),
+            vote_account.clone(),
+            clock_sysvar.clone(),
+            stake_history_sysvar.clone(),
+            stake_config.clone(),
+            wallet_account.clone(),
+            stake_program.clone(),
+        ],
+        &[&[b"wallet", user_account.key.as_ref(), &[wallet_bump]]],
+    )?;
+
+    debug_msg!("Stake delegated to {:?}", vote_account.key);
+    Ok(())
+}
+
+pub fn deactivate_stake(program_id: &Pubkey, accounts: &[AccountInfo], stake_id: u64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let user_account = next_account_info(account_info_iter)?;
+    let wallet_account = next_account_info(account_info_iter)?;
+    let stake_account = next_account_info(account_info_iter)?;
+    let clock_sysvar = next_account_info(account_info_iter)?;
+    let stake_program = next_account_info(account_info_iter)?;
+
+    let wallet_bump = verify_wallet_stake(program_id, user_account, wallet_account, stake_account, stake_id)?;
+
+    invoke_signed(
+        &stake::instruction::deactivate_stake(stake_account.key, wallet_account.key),
+        &[stake_account.clone(), clock_sysvar.clone(), wallet_account.clone(), stake_program.clone()],
+        &[&[b"wallet", user_account.key.as_ref(), &[wallet_bump]]],
+    )?;
+
+    debug_msg!("Stake deactivated");
+    Ok(())
+}
+
+pub fn withdraw_stake(program_id: &Pubkey, accounts: &[AccountInfo], stake_id: u64, amount: u64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let user_account = next_account_info(account_info_iter)?;
+    let wallet_account = next_account_info(account_info_iter)?;
+    let stake_account = next_account_info(account_info_iter)?;
+    let clock_sysvar = next_account_info(account_info_iter)?;
+    let stake_history_sysvar = next_account_info(account_info_iter)?;
+    let stake_program = next_account_info(account_info_iter)?;
+
+    let wallet_bump = verify_wallet_stake(program_id, user_account, wallet_account, stake_account, stake_id)?;
+
+    // Withdrawn lamports always go back to the wallet
+    invoke_signed(
+        &stake::instruction::withdraw(stake_account.key, wallet_account.key, wallet_account.key, amount, None),
+        &[
+            stake_account.clone(),
+            wallet_account.clone(),
+            clock_sysvar.clone(),
+            stake_history_sysvar.clone(),
+            wallet_account.clone(),
+            stake_program.clone(),
+        ],
+        &[&[b"wallet", user_account.key.as_ref(), &[wallet_bump]]],
+    )?;
+
+    debug_msg!("Withdrew {} lamports of stake to the wallet", amount);
+    Ok(())
+}
+
+/// Checks that the owner signed and that the wallet and stake accounts are the owner's PDAs.
+/// Returns the wallet's bump seed.
+fn verify_wallet_stake(
+    program_id: &Pubkey,
+    user_account: &AccountInfo,
+    wallet_account: &AccountInfo,
+    stake_account: &AccountInfo,
+    stake_id: u64,
+) -> Result<u8, ProgramError> {
+    if !user_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let wallet_bump = verify_wallet_address(program_id, wallet_account, user_account.key)?;
+
+    let (stake_address, _) = derive_stake_address(wallet_account.key, stake_id, program_id);
+    if stake_address != *stake_account.key {
+        msg!("Stake account does not match");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    Ok(wallet_bump)
+}
@@ -1,31 +1,380 @@
-use borsh::{BorshSerialize, BorshDeserialize};
+use std::{cell::RefMut, mem::size_of};
+
+use borsh::{BorshSerialize, BorshDeserialize, BorshSchema};
+use bytemuck::{Pod, Zeroable};
 use solana_program::{
     pubkey::Pubkey,
     program_error::ProgramError,
     account_info::AccountInfo,
 };
+use spl_pod::primitives::{PodBool, PodI64, PodU64};
+
+/// Identifies the type of a program account. Stored as the first byte of every account
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Clone, Copy, Debug, PartialEq, Eq)]
+#[borsh(use_discriminant = true)]
+#[repr(u8)]
+pub enum AccountKind {
+    Wallet = 1,
+    DAppApproval = 2,
+    StandingOrder = 3,
+    CpiApproval = 4,
+    ExecutionReceipt = 5,
+    UsedPermit = 6,
+    PendingExecution = 7,
+}
+
+/// A program account laid out as `[discriminator, version, body]`. The body of the current
+/// layout is the fixed-size `Pod` struct itself, so it is read and written without
+/// deserializing and can be borrowed in place with `load_account_mut`.
+pub trait ProgramAccount: Pod {
+    const KIND: AccountKind;
+    /// Current layout version
+    const VERSION: u8;
+    /// Account size for the current layout, including the header
+    const SIZE: usize;
+    /// Sizes of the headerless layouts used before versioning, read as version 0
+    const LEGACY_SIZES: &'static [usize] = &[];
+
+    /// Reads the body of an older layout. Version 0 is the whole headerless account
+    fn unpack_older(_version: u8, _data: &[u8]) -> Result<Self, ProgramError> {
+        Err(ProgramError::InvalidAccountData)
+    }
+}
+
+/// Copies a current-layout body out of `data`, which may hold unused trailing space
+fn read_body<T: ProgramAccount>(data: &[u8]) -> Result<T, ProgramError> {
+    data.get(..size_of::<T>())
+        .and_then(|body| bytemuck::try_pod_read_unaligned(body).ok())
+        .ok_or(ProgramError::InvalidAccountData)
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+pub struct WalletState {
+    pub owner: Pubkey,
+    pub intent_nonce: PodU64,
+    /// Canonical bump of the wallet PDA, so it can be checked without a bump search
+    pub bump: u8,
+}
 
-#[derive(BorshSerialize, BorshDeserialize, Debug)]
+impl WalletState {
+    pub fn new(owner: Pubkey, bump: u8) -> Self {
+        Self {
+            owner,
+            intent_nonce: PodU64::from(0),
+            bump,
+        }
+    }
+}
+
+/// A per-transaction limit in USD, valued with the price from `price_feed`
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, Clone, PartialEq, Eq)]
+pub struct UsdLimit {
+    /// Pyth-format price account for the approved token, in USD
+    pub price_feed: Pubkey,
+    /// Maximum value per transaction, fixed-point with `price::USD_DECIMALS` decimals
+    pub max_usd: u64,
+}
+
+/// The wallet, dApp, owner and creation time come first so they sit at fixed offsets
+/// (`APPROVAL_*_OFFSET`) that `getProgramAccounts` memcmp filters can match on
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
 pub struct DAppApproval {
-    pub is_approved: bool,
-    pub max_amount: u64,
-    pub expiry: i64,
+    pub wallet: Pubkey,
+    pub dapp: Pubkey,
+    pub owner: Pubkey,
+    pub created_at: PodI64,
+    pub is_approved: PodBool,
+    pub max_amount: PodU64,
+    pub expiry: PodI64,
     pub token_mint: Pubkey,
+    pub spent_amount: PodU64,
+    pub execution_count: PodU64,
+    /// The approval can't be used before this time; 0 makes it usable immediately
+    pub valid_from: PodI64,
+    /// Maximum number of executions; 0 allows any number
+    pub max_executions: PodU64,
+    /// Close the approval, returning its rent to the owner, after one successful execution
+    pub single_use: PodBool,
+    /// Price account of the optional USD cap applied on top of `max_amount`; unset when
+    /// there is no cap. Read through `usd_limit`
+    pub usd_price_feed: Pubkey,
+    pub max_usd: PodU64,
+    /// With `cosign_limit` set, amounts up to `auto_limit` execute directly
+    pub auto_limit: PodU64,
+    /// Amounts above `auto_limit` and up to this need the owner to confirm a pending
+    /// execution; 0 turns co-signing off
+    pub cosign_limit: PodU64,
+    /// Canonical bump of the approval PDA, so it can be checked without a bump search
+    pub bump: u8,
+    pub recipient_count: u8,
+    /// Accounts the dApp may send to, the first `recipient_count` of them. Read through
+    /// `recipients`; an empty list allows any recipient
+    pub recipient_slots: [Pubkey; MAX_APPROVAL_RECIPIENTS],
 }
 
 impl DAppApproval {
-    pub fn new(max_amount: u64, expiry: i64, token_mint: Pubkey) -> Self {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        wallet: Pubkey,
+        dapp: Pubkey,
+        owner: Pubkey,
+        created_at: i64,
+        max_amount: u64,
+        expiry: i64,
+        token_mint: Pubkey,
+    ) -> Self {
+        Self {
+            wallet,
+            dapp,
+            owner,
+            created_at: created_at.into(),
+            is_approved: true.into(),
+            max_amount: max_amount.into(),
+            expiry: expiry.into(),
+            token_mint,
+            ..Self::zeroed()
+        }
+    }
+
+    /// Records the keys behind the approval's PDA. Only needed for approvals read from
+    /// an older layout, where they are unset
+    pub fn set_keys(&mut self, wallet: Pubkey, dapp: Pubkey, owner: Pubkey) {
+        self.wallet = wallet;
+        self.dapp = dapp;
+        self.owner = owner;
+    }
+
+    pub fn usd_limit(&self) -> Option<UsdLimit> {
+        (self.usd_price_feed != Pubkey::default()).then(|| UsdLimit {
+            price_feed: self.usd_price_feed,
+            max_usd: self.max_usd.into(),
+        })
+    }
+
+    pub fn set_usd_limit(&mut self, usd_limit: Option<UsdLimit>) {
+        let usd_limit = usd_limit.unwrap_or(UsdLimit {
+            price_feed: Pubkey::default(),
+            max_usd: 0,
+        });
+        self.usd_price_feed = usd_limit.price_feed;
+        self.max_usd = usd_limit.max_usd.into();
+    }
+
+    pub fn recipients(&self) -> &[Pubkey] {
+        &self.recipient_slots[..(self.recipient_count as usize).min(MAX_APPROVAL_RECIPIENTS)]
+    }
+
+    /// Fails if there are more than `MAX_APPROVAL_RECIPIENTS` recipients
+    pub fn set_recipients(&mut self, recipients: &[Pubkey]) -> Result<(), ProgramError> {
+        if recipients.len() > MAX_APPROVAL_RECIPIENTS {
+            return Err(ProgramError::InvalidArgument);
+        }
+
+        self.recipient_slots = [Pubkey::default(); MAX_APPROVAL_RECIPIENTS];
+        self.recipient_slots[..recipients.len()].copy_from_slice(recipients);
+        self.recipient_count = recipients.len() as u8;
+        Ok(())
+    }
+}
+
+/// Layout of approvals created before usage counters and recipients were added
+#[derive(BorshDeserialize)]
+struct LegacyDAppApproval {
+    is_approved: bool,
+    max_amount: u64,
+    expiry: i64,
+    token_mint: Pubkey,
+}
+
+impl From<LegacyDAppApproval> for DAppApproval {
+    fn from(legacy: LegacyDAppApproval) -> Self {
+        Self {
+            is_approved: legacy.is_approved.into(),
+            ..Self::new(
+                Pubkey::default(),
+                Pubkey::default(),
+                Pubkey::default(),
+                0,
+                legacy.max_amount,
+                legacy.expiry,
+                legacy.token_mint,
+            )
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+pub struct StandingOrder {
+    pub owner: Pubkey,
+    pub recipient: Pubkey,
+    pub token_mint: Pubkey,
+    pub amount: PodU64,
+    pub interval: PodI64,
+    pub next_due: PodI64,
+}
+
+impl StandingOrder {
+    pub fn new(owner: Pubkey, recipient: Pubkey, token_mint: Pubkey, amount: u64, interval: i64, next_due: i64) -> Self {
         Self {
-            is_approved: true,
-            max_amount,
-            expiry,
+            owner,
+            recipient,
             token_mint,
+            amount: amount.into(),
+            interval: interval.into(),
+            next_due: next_due.into(),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+pub struct CpiApproval {
+    pub is_approved: PodBool,
+    pub program_id: Pubkey,
+    pub max_amount: PodU64,
+    pub expiry: PodI64,
+}
+
+impl CpiApproval {
+    pub fn new(program_id: Pubkey, max_amount: u64, expiry: i64) -> Self {
+        Self {
+            is_approved: true.into(),
+            program_id,
+            max_amount: max_amount.into(),
+            expiry: expiry.into(),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+pub struct ExecutionReceipt {
+    pub amount: PodU64,
+    pub executed_at: PodI64,
+}
+
+impl ExecutionReceipt {
+    pub fn new(amount: u64, executed_at: i64) -> Self {
+        Self {
+            amount: amount.into(),
+            executed_at: executed_at.into(),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+pub struct UsedPermit {
+    pub used_at: PodI64,
+}
+
+impl UsedPermit {
+    pub fn new(used_at: i64) -> Self {
+        Self { used_at: used_at.into() }
+    }
+}
+
+/// A transfer above an approval's `auto_limit`, waiting for the owner to confirm it
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+pub struct PendingExecution {
+    pub approval: Pubkey,
+    pub owner: Pubkey,
+    pub dapp: Pubkey,
+    pub recipient: Pubkey,
+    pub amount: PodU64,
+    pub expires_at: PodI64,
+}
+
+impl PendingExecution {
+    pub fn new(approval: Pubkey, owner: Pubkey, dapp: Pubkey, recipient: Pubkey, amount: u64, expires_at: i64) -> Self {
+        Self {
+            approval,
+            owner,
+            dapp,
+            recipient,
+            amount: amount.into(),
+            expires_at: expires_at.into(),
+        }
+    }
+}
+
+impl ProgramAccount for WalletState {
+    const KIND: AccountKind = AccountKind::Wallet;
+    const VERSION: u8 = 1;
+    const SIZE: usize = WALLET_STATE_SIZE;
+}
+
+/// The headerless approvals of the first release only store `is_approved`, `max_amount`,
+/// `expiry` and `token_mint`. The other fields are read as zero; the wallet, dApp, owner and
+/// bump are filled in from the verified accounts on the next write.
+impl ProgramAccount for DAppApproval {
+    const KIND: AccountKind = AccountKind::DAppApproval;
+    const VERSION: u8 = 1;
+    const SIZE: usize = DAPP_APPROVAL_SIZE;
+    const LEGACY_SIZES: &'static [usize] = &[LEGACY_DAPP_APPROVAL_SIZE];
+
+    fn unpack_older(version: u8, data: &[u8]) -> Result<Self, ProgramError> {
+        match version {
+            0 => LegacyDAppApproval::try_from_slice(data)
+                .map(DAppApproval::from)
+                .map_err(|_| ProgramError::InvalidAccountData),
+            _ => Err(ProgramError::InvalidAccountData),
         }
     }
 }
 
+impl ProgramAccount for StandingOrder {
+    const KIND: AccountKind = AccountKind::StandingOrder;
+    const VERSION: u8 = 1;
+    const SIZE: usize = STANDING_ORDER_SIZE;
+}
+
+impl ProgramAccount for CpiApproval {
+    const KIND: AccountKind = AccountKind::CpiApproval;
+    const VERSION: u8 = 1;
+    const SIZE: usize = CPI_APPROVAL_SIZE;
+}
+
+impl ProgramAccount for ExecutionReceipt {
+    const KIND: AccountKind = AccountKind::ExecutionReceipt;
+    const VERSION: u8 = 1;
+    const SIZE: usize = EXECUTION_RECEIPT_SIZE;
+}
+
+impl ProgramAccount for UsedPermit {
+    const KIND: AccountKind = AccountKind::UsedPermit;
+    const VERSION: u8 = 1;
+    const SIZE: usize = USED_PERMIT_SIZE;
+}
+
+impl ProgramAccount for PendingExecution {
+    const KIND: AccountKind = AccountKind::PendingExecution;
+    const VERSION: u8 = 1;
+    const SIZE: usize = PENDING_EXECUTION_SIZE;
+}
+
 // Constants for space calculation
-pub const DAPP_APPROVAL_SIZE: usize = 1 + 8 + 8 + 32; // is_approved + max_amount + expiry + token_mint
+pub const ACCOUNT_HEADER_SIZE: usize = 1 + 1; // discriminator + version
+pub const WALLET_STATE_SIZE: usize = ACCOUNT_HEADER_SIZE + size_of::<WalletState>();
+pub const MAX_APPROVAL_RECIPIENTS: usize = 4;
+pub const DAPP_APPROVAL_SIZE: usize = ACCOUNT_HEADER_SIZE + size_of::<DAppApproval>();
+pub const APPROVAL_WALLET_OFFSET: usize = ACCOUNT_HEADER_SIZE;
+pub const APPROVAL_DAPP_OFFSET: usize = APPROVAL_WALLET_OFFSET + 32;
+pub const APPROVAL_OWNER_OFFSET: usize = APPROVAL_DAPP_OFFSET + 32;
+pub const APPROVAL_CREATED_AT_OFFSET: usize = APPROVAL_OWNER_OFFSET + 32;
+pub const STANDING_ORDER_SIZE: usize = ACCOUNT_HEADER_SIZE + size_of::<StandingOrder>();
+pub const CPI_APPROVAL_SIZE: usize = ACCOUNT_HEADER_SIZE + size_of::<CpiApproval>();
+pub const EXECUTION_RECEIPT_SIZE: usize = ACCOUNT_HEADER_SIZE + size_of::<ExecutionReceipt>();
+pub const USED_PERMIT_SIZE: usize = ACCOUNT_HEADER_SIZE + size_of::<UsedPermit>();
+pub const PENDING_EXECUTION_SIZE: usize = ACCOUNT_HEADER_SIZE + size_of::<PendingExecution>();
+
+// Size of approvals written before accounts had a header
+const LEGACY_DAPP_APPROVAL_SIZE: usize = 1 + 8 + 8 + 32; // is_approved + max_amount + expiry + token_mint
+
 
 pub fn derive_wallet_address(owner: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
     Pubkey::find_program_address(&[b"wallet", owner.as_ref()], program_id)
@@ -43,13 +392,206 @@ pub fn derive_approval_address(
     )
 }
 
+pub fn derive_standing_order_address(wallet: &Pubkey, order_id: u64, program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[b"standing_order", wallet.as_ref(), &order_id.to_le_bytes()],
+        program_id
+    )
+}
+
+pub fn derive_cpi_approval_address(
+    wallet: &Pubkey,
+    dapp: &Pubkey,
+    target_program: &Pubkey,
+    program_id: &Pubkey
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[b"cpi_approval", wallet.as_ref(), dapp.as_ref(), target_program.as_ref()],
+        program_id
+    )
+}
+
+pub fn derive_receipt_address(approval: &Pubkey, request_id: &[u8; 32], program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[b"receipt", approval.as_ref(), request_id],
+        program_id
+    )
+}
+
+pub fn derive_pending_execution_address(approval: &Pubkey, request_id: &[u8; 32], program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[b"pending", approval.as_ref(), request_id],
+        program_id
+    )
+}
+
+pub fn derive_stake_address(wallet: &Pubkey, stake_id: u64, program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[b"stake", wallet.as_ref(), &stake_id.to_le_bytes()],
+        program_id
+    )
+}
+
+pub fn derive_permit_address(wallet: &Pubkey, nonce: u64, program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[b"permit", wallet.as_ref(), &nonce.to_le_bytes()],
+        program_id
+    )
+}
+
+/// Checks that `address` is the PDA for `seeds`, trying the stored `bump` first so the usual
+/// case costs one `create_program_address` instead of a bump search. Pass `None` when no bump
+/// was stored (accounts that don't exist yet, or were written before bumps were stored): a
+/// valid but non-canonical bump must never be accepted, so those fall back to the search.
+/// Returns the canonical bump.
+pub fn verify_program_address(address: &Pubkey, seeds: &[&[u8]], bump: Option<u8>, program_id: &Pubkey) -> Option<u8> {
+    if let Some(bump) = bump {
+        let bump_seed = [bump];
+        let mut seeds_with_bump = seeds.to_vec();
+        seeds_with_bump.push(&bump_seed);
+        if Pubkey::create_program_address(&seeds_with_bump, program_id).ok() == Some(*address) {
+            return Some(bump);
+        }
+    }
+
+    let (expected_address, canonical_bump) = Pubkey::find_program_address(seeds, program_id);
+    (expected_address == *address).then_some(canonical_bump)
+}
+
+/// Reads a program account of type `T` in its current layout, or in the headerless layout
+/// of the first release, which is recognised by its exact size.
+pub fn unpack_account<T: ProgramAccount>(account: &AccountInfo) -> Result<T, ProgramError> {
+    unpack_account_data(&account.data.borrow())
+}
+
+/// Same as `unpack_account`, for raw account data such as fetched over RPC
+pub fn unpack_account_data<T: ProgramAccount>(data: &[u8]) -> Result<T, ProgramError> {
+    if T::LEGACY_SIZES.contains(&data.len()) {
+        return T::unpack_older(0, data);
+    }
+
+    match data {
+        [discriminator, version, body @ ..] if *discriminator == T::KIND as u8 => {
+            if *version == T::VERSION {
+                read_body(body)
+            } else {
+                T::unpack_older(*version, body)
+            }
+        }
+        _ => Err(ProgramError::InvalidAccountData),
+    }
+}
+
+/// Writes `T` with the current header. Accounts still on an older, smaller layout
+/// have to be migrated first.
+pub fn pack_account<T: ProgramAccount>(account_data: &T, account: &AccountInfo) -> Result<(), ProgramError> {
+    let mut data = account.data.borrow_mut();
+    if data.len() < T::SIZE {
+        return Err(ProgramError::AccountDataTooSmall);
+    }
+
+    data[0] = T::KIND as u8;
+    data[1] = T::VERSION;
+    data[ACCOUNT_HEADER_SIZE..T::SIZE].copy_from_slice(bytemuck::bytes_of(account_data));
+    Ok(())
+}
+
+/// Borrows an account already in the current layout of `T` in place, so fields can be read
+/// and updated without copying the whole account. Older layouts have to be migrated first.
+pub fn load_account_mut<'b, T: ProgramAccount>(account: &'b AccountInfo) -> Result<RefMut<'b, T>, ProgramError> {
+    let data = account.try_borrow_mut_data()?;
+    if data.len() < T::SIZE || data[0] != T::KIND as u8 || data[1] != T::VERSION {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    Ok(RefMut::map(data, |data| bytemuck::from_bytes_mut(&mut data[ACCOUNT_HEADER_SIZE..T::SIZE])))
+}
+
+pub fn unpack_wallet_state(wallet_account: &AccountInfo) -> Result<WalletState, ProgramError> {
+    unpack_account(wallet_account)
+}
+
+pub fn pack_wallet_state(wallet_state: &WalletState, wallet_account: &AccountInfo) -> Result<(), ProgramError> {
+    pack_account(wallet_state, wallet_account)
+}
 
 pub fn unpack_approval_data(approval_account: &AccountInfo) -> Result<DAppApproval, ProgramError> {
-    DAppApproval::try_from_slice(&approval_account.data.borrow())
-        .map_err(|_| ProgramError::InvalidAccountData)
+    unpack_account(approval_account)
 }
 
 pub fn pack_approval_data(approval_data: &DAppApproval, approval_account: &AccountInfo) -> Result<(), ProgramError> {
-    approval_data.serialize(&mut &mut approval_account.data.borrow_mut()[..])
-        .map_err(|_| ProgramError::AccountDataTooSmall)
+    pack_account(approval_data, approval_account)
+}
+
+pub fn unpack_standing_order_data(order_account: &AccountInfo) -> Result<StandingOrder, ProgramError> {
+    unpack_account(order_account)
+}
+
+pub fn pack_standing_order_data(order_data: &StandingOrder, order_account: &AccountInfo) -> Result<(), ProgramError> {
+    pack_account(order_data, order_account)
+}
+
+pub fn unpack_cpi_approval_data(approval_account: &AccountInfo) -> Result<CpiApproval, ProgramError> {
+    unpack_account(approval_account)
+}
+
+pub fn pack_cpi_approval_data(approval_data: &CpiApproval, approval_account: &AccountInfo) -> Result<(), ProgramError> {
+    pack_account(approval_data, approval_account)
+}
+
+pub fn pack_receipt_data(receipt_data: &ExecutionReceipt, receipt_account: &AccountInfo) -> Result<(), ProgramError> {
+    pack_account(receipt_data, receipt_account)
+}
+
+pub fn pack_used_permit_data(permit_data: &UsedPermit, permit_account: &AccountInfo) -> Result<(), ProgramError> {
+    pack_account(permit_data, permit_account)
+}
+
+pub fn unpack_pending_execution_data(pending_account: &AccountInfo) -> Result<PendingExecution, ProgramError> {
+    unpack_account(pending_account)
+}
+
+pub fn pack_pending_execution_data(pending_data: &PendingExecution, pending_account: &AccountInfo) -> Result<(), ProgramError> {
+    pack_account(pending_data, pending_account)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stored_bump_verifies_the_address() {
+        let program_id = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let (wallet, bump) = derive_wallet_address(&owner, &program_id);
+
+        assert_eq!(verify_program_address(&wallet, &[b"wallet", owner.as_ref()], Some(bump), &program_id), Some(bump));
+        assert_eq!(verify_program_address(&Pubkey::new_unique(), &[b"wallet", owner.as_ref()], Some(bump), &program_id), None);
+    }
+
+    #[test]
+    fn missing_bump_falls_back_to_search() {
+        let program_id = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let (wallet, bump) = derive_wallet_address(&owner, &program_id);
+        let wrong_bump = bump.wrapping_sub(1);
+
+        assert_eq!(verify_program_address(&wallet, &[b"wallet", owner.as_ref()], Some(wrong_bump), &program_id), Some(bump));
+        assert_eq!(verify_program_address(&wallet, &[b"wallet", owner.as_ref()], None, &program_id), Some(bump));
+    }
+
+    #[test]
+    fn non_canonical_addresses_are_rejected_without_a_stored_bump() {
+        let program_id = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let (_, bump) = derive_wallet_address(&owner, &program_id);
+        let seeds: &[&[u8]] = &[b"wallet", owner.as_ref()];
+
+        // Some lower bump also lands off the curve and is a valid, non-canonical PDA
+        let non_canonical = (0..bump)
+            .rev()
+            .find_map(|other| Pubkey::create_program_address(&[seeds[0], seeds[1], &[other]], &program_id).ok())
+            .unwrap();
+        assert_eq!(verify_program_address(&non_canonical, seeds, None, &program_id), None);
+    }
 }
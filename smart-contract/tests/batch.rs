@@ -0,0 +1,151 @@
+//! `ExecuteBatch`: several transfers under one approval, for SOL and tokens
+
+mod common;
+
+use smart_contract::{
+    error::WalletErrorCode,
+    event::WalletEvent,
+    instruction::{TransferType, WalletInstruction},
+    state::DAppApproval,
+};
+use solana_program::{
+    instruction::AccountMeta, native_token::LAMPORTS_PER_SOL, program_error::ProgramError, pubkey::Pubkey,
+    system_program,
+};
+
+use crate::common::{take_events, Runtime, TRANSACTION_FEE};
+
+const MAX_AMOUNT: u64 = LAMPORTS_PER_SOL;
+
+struct Batch {
+    owner: Pubkey,
+    wallet: Pubkey,
+    dapp: Pubkey,
+    approval: Pubkey,
+    recipients: Vec<Pubkey>,
+    token_accounts: Vec<AccountMeta>,
+}
+
+impl Batch {
+    fn sol(runtime: &mut Runtime) -> Self {
+        let (owner, wallet) = runtime.create_wallet(2 * LAMPORTS_PER_SOL);
+        let dapp = Pubkey::new_unique();
+        let approval = runtime.approve(&owner, &dapp, &Pubkey::default(), MAX_AMOUNT);
+        let recipients = vec![Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique()];
+        Batch { owner, wallet, dapp, approval, recipients, token_accounts: vec![] }
+    }
+
+    /// A batch of tokens from a wallet holding `balance` tokens and only its rent in lamports
+    fn token(runtime: &mut Runtime, balance: u64) -> (Self, Pubkey) {
+        let (owner, wallet) = runtime.create_wallet(0);
+        let mint = runtime.add_mint(&spl_token::id(), 6);
+        let wallet_token = runtime.add_token_account(&spl_token::id(), &mint, &wallet, balance);
+        let dapp = Pubkey::new_unique();
+        let approval = runtime.approve(&owner, &dapp, &mint, MAX_AMOUNT);
+        let recipients = (0..3)
+            .map(|_| runtime.add_token_account(&spl_token::id(), &mint, &Pubkey::new_unique(), 0))
+            .collect();
+        let token_accounts = vec![
+            AccountMeta::new_readonly(mint, false),
+            AccountMeta::new(wallet_token, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+        ];
+        (Batch { owner, wallet, dapp, approval, recipients, token_accounts }, wallet_token)
+    }
+
+    fn execute(&self, runtime: &mut Runtime, transfers: &[(u8, u64)], signed: bool) -> Result<(), ProgramError> {
+        let mut metas = vec![
+            AccountMeta::new(self.dapp, signed),
+            AccountMeta::new(self.owner, false),
+            AccountMeta::new(self.wallet, false),
+            AccountMeta::new(self.approval, false),
+            AccountMeta::new_readonly(system_program::ID, false),
+        ];
+        metas.extend_from_slice(&self.token_accounts);
+        metas.extend(self.recipients.iter().map(|recipient| AccountMeta::new(*recipient, false)));
+
+        let transfer_type = if self.token_accounts.is_empty() { TransferType::Sol } else { TransferType::Token };
+        let instruction = WalletInstruction::ExecuteBatch { transfers: transfers.to_vec(), transfer_type };
+        runtime.process(&metas, &instruction.pack())
+    }
+}
+
+#[test]
+fn sol_batches_pay_every_recipient_for_one_fee() {
+    let mut runtime = Runtime::new();
+    let batch = Batch::sol(&mut runtime);
+    let wallet_before = runtime.lamports(&batch.wallet);
+    let dapp_before = runtime.lamports(&batch.dapp);
+
+    batch.execute(&mut runtime, &[(0, 100), (2, 300), (0, 50)], true).unwrap();
+    assert_eq!(
+        take_events(),
+        [WalletEvent::BatchExecuted {
+            approval: batch.approval,
+            wallet: batch.wallet,
+            token_mint: Pubkey::default(),
+            transfers: vec![(batch.recipients[0], 100), (batch.recipients[2], 300), (batch.recipients[0], 50)],
+            debited_amount: 450,
+        }]
+    );
+    assert_eq!(runtime.lamports(&batch.recipients[0]), 150);
+    assert_eq!(runtime.lamports(&batch.recipients[1]), 0);
+    assert_eq!(runtime.lamports(&batch.recipients[2]), 300);
+    assert_eq!(runtime.lamports(&batch.wallet), wallet_before - 450 - TRANSACTION_FEE);
+    assert_eq!(runtime.lamports(&batch.dapp), dapp_before + TRANSACTION_FEE);
+
+    // The batch is one execution of the approval
+    let approval: DAppApproval = runtime.state(&batch.approval);
+    assert_eq!(u64::from(approval.spent_amount), 450);
+    assert_eq!(u64::from(approval.execution_count), 1);
+}
+
+#[test]
+fn the_allowance_applies_to_the_batch_total() {
+    let mut runtime = Runtime::new();
+    let batch = Batch::sol(&mut runtime);
+
+    assert_eq!(
+        batch.execute(&mut runtime, &[(0, MAX_AMOUNT / 2), (1, MAX_AMOUNT / 2 + 1)], true),
+        Err(WalletErrorCode::AllowanceExceeded.into())
+    );
+    assert_eq!(runtime.lamports(&batch.recipients[0]), 0);
+    batch.execute(&mut runtime, &[(0, MAX_AMOUNT / 2), (1, MAX_AMOUNT / 2)], true).unwrap();
+}
+
+#[test]
+fn malformed_batches_are_rejected() {
+    let mut runtime = Runtime::new();
+    let batch = Batch::sol(&mut runtime);
+
+    assert_eq!(batch.execute(&mut runtime, &[], true), Err(ProgramError::InvalidInstructionData));
+    assert_eq!(batch.execute(&mut runtime, &[(3, 1)], true), Err(ProgramError::NotEnoughAccountKeys));
+    assert_eq!(batch.execute(&mut runtime, &[(0, u64::MAX), (1, 1)], true), Err(ProgramError::ArithmeticOverflow));
+    assert_eq!(batch.execute(&mut runtime, &[(0, 1)], false), Err(ProgramError::MissingRequiredSignature));
+}
+
+#[test]
+fn token_batches_pay_every_recipient() {
+    let mut runtime = Runtime::new();
+    let (batch, wallet_token) = Batch::token(&mut runtime, 1000);
+    runtime.fund(&batch.wallet, TRANSACTION_FEE);
+
+    batch.execute(&mut runtime, &[(0, 100), (1, 200)], true).unwrap();
+    assert_eq!(runtime.token_balance(&batch.recipients[0]), 100);
+    assert_eq!(runtime.token_balance(&batch.recipients[1]), 200);
+    assert_eq!(runtime.token_balance(&wallet_token), 700);
+
+    // More than the wallet holds fails without paying anyone
+    assert_eq!(batch.execute(&mut runtime, &[(2, 500), (0, 500)], true), Err(ProgramError::InsufficientFunds));
+    assert_eq!(runtime.token_balance(&batch.recipients[2]), 0);
+}
+
+#[test]
+fn token_batches_need_lamports_for_the_fee() {
+    let mut runtime = Runtime::new();
+    let (batch, wallet_token) = Batch::token(&mut runtime, 1000);
+
+    // The wallet holds only its rent, so reimbursing the fee would leave it below rent exemption
+    assert_eq!(batch.execute(&mut runtime, &[(0, 100)], true), Err(ProgramError::InsufficientFunds));
+    assert_eq!(runtime.token_balance(&wallet_token), 1000);
+}
@@ -0,0 +1,696 @@
+//! In-memory runtime shared by the integration tests. The wallet program runs natively over
+//! accounts laid out the way the BPF loader passes them, and its cross-program invocations are
+//! carried out here: the system program, both token programs and the associated token account
+//! program run their real processors, the stake program is a small model of the real one, and
+//! tests can register mock programs of their own.
+
+#![allow(dead_code)]
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::BTreeMap,
+    mem::size_of,
+    sync::Once,
+};
+
+use borsh::BorshDeserialize;
+use smart_contract::{
+    client,
+    event::WalletEvent,
+    price::mock_price_account_data,
+    process_instruction,
+    state::{derive_approval_address, derive_wallet_address, unpack_account_data, ProgramAccount},
+};
+use solana_program::{
+    account_info::AccountInfo,
+    clock::Clock,
+    entrypoint::{deserialize, ProgramResult, MAX_PERMITTED_DATA_INCREASE, NON_DUP_MARKER},
+    instruction::{AccountMeta, Instruction},
+    native_token::LAMPORTS_PER_SOL,
+    program_error::ProgramError,
+    program_option::COption,
+    program_pack::Pack,
+    program_stubs::{set_syscall_stubs, SyscallStubs},
+    pubkey::Pubkey,
+    rent::Rent,
+    stake::{
+        self,
+        instruction::StakeInstruction,
+        stake_flags::StakeFlags,
+        state::{Authorized, Delegation, Lockup, Meta, StakeAuthorize, Stake, StakeStateV2},
+    },
+    system_instruction::SystemInstruction,
+    system_program,
+    sysvar::{
+        self,
+        instructions::{construct_instructions_data, store_current_index, BorrowedAccountMeta, BorrowedInstruction},
+    },
+};
+use spl_associated_token_account::get_associated_token_address_with_program_id;
+use spl_token_2022::extension::{BaseStateWithExtensions, ExtensionType, StateWithExtensions, StateWithExtensionsMut};
+
+pub const NOW: i64 = 1_700_000_000;
+pub const TRANSACTION_FEE: u64 = 5000;
+
+/// A program the tests provide, invoked natively like the wallet program
+pub type MockProgram = fn(&Pubkey, &[AccountInfo], &[u8]) -> ProgramResult;
+
+thread_local! {
+    static UNIX_TIMESTAMP: Cell<i64> = const { Cell::new(NOW) };
+    /// The programs currently executing, innermost last
+    static CALL_STACK: RefCell<Vec<Pubkey>> = const { RefCell::new(Vec::new()) };
+    static MOCK_PROGRAMS: RefCell<BTreeMap<Pubkey, MockProgram>> = const { RefCell::new(BTreeMap::new()) };
+    static RETURN_DATA: RefCell<Option<(Pubkey, Vec<u8>)>> = const { RefCell::new(None) };
+    static LOGS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+    static EVENTS: RefCell<Vec<Vec<u8>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Sets the cluster time the programs see on this thread
+pub fn set_time(unix_timestamp: i64) {
+    UNIX_TIMESTAMP.with(|time| time.set(unix_timestamp));
+}
+
+pub fn register_program(program_id: Pubkey, program: MockProgram) {
+    MOCK_PROGRAMS.with(|programs| programs.borrow_mut().insert(program_id, program));
+}
+
+/// Messages logged since the last call
+pub fn take_logs() -> Vec<String> {
+    LOGS.with(RefCell::take)
+}
+
+/// Events emitted since the last call
+pub fn take_events() -> Vec<WalletEvent> {
+    EVENTS
+        .with(RefCell::take)
+        .iter()
+        .map(|data| WalletEvent::try_from_slice(data).expect("undecodable event"))
+        .collect()
+}
+
+/// Syscalls of the runtime. All state lives in thread locals, so tests can run in parallel
+/// with a single set of stubs installed for the whole process.
+struct RuntimeStubs;
+
+impl SyscallStubs for RuntimeStubs {
+    fn sol_log(&self, message: &str) {
+        LOGS.with(|logs| logs.borrow_mut().push(message.to_string()));
+    }
+
+    fn sol_log_data(&self, fields: &[&[u8]]) {
+        EVENTS.with(|events| events.borrow_mut().push(fields.concat()));
+    }
+
+    fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+        let clock = Clock {
+            unix_timestamp: UNIX_TIMESTAMP.with(Cell::get),
+            ..Clock::default()
+        };
+        unsafe { *(var_addr as *mut Clock) = clock };
+        0
+    }
+
+    fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+        unsafe { *(var_addr as *mut Rent) = Rent::default() };
+        0
+    }
+
+    fn sol_get_return_data(&self) -> Option<(Pubkey, Vec<u8>)> {
+        RETURN_DATA.with(|data| data.borrow().clone())
+    }
+
+    fn sol_set_return_data(&self, data: &[u8]) {
+        let program_id = current_program();
+        RETURN_DATA.with(|return_data| *return_data.borrow_mut() = Some((program_id, data.to_vec())));
+    }
+
+    fn sol_get_stack_height(&self) -> u64 {
+        CALL_STACK.with(|stack| stack.borrow().len() as u64)
+    }
+
+    fn sol_invoke_signed(&self, instruction: &Instruction, account_infos: &[AccountInfo], signers_seeds: &[&[&[u8]]]) -> ProgramResult {
+        let caller = current_program();
+        let signers = signers_seeds
+            .iter()
+            .map(|seeds| Pubkey::create_program_address(seeds, &caller))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| ProgramError::InvalidSeeds)?;
+
+        // The callee sees the instruction's accounts in order, with no more privileges than
+        // the caller has or can sign for
+        let mut callee_accounts = Vec::with_capacity(instruction.accounts.len());
+        for meta in &instruction.accounts {
+            let account = account_infos
+                .iter()
+                .find(|account| *account.key == meta.pubkey)
+                .ok_or(ProgramError::NotEnoughAccountKeys)?;
+            if meta.is_signer && !account.is_signer && !signers.contains(account.key) {
+                return Err(ProgramError::MissingRequiredSignature);
+            }
+            if meta.is_writable && !account.is_writable {
+                return Err(ProgramError::InvalidArgument);
+            }
+            let mut account = account.clone();
+            account.is_signer = meta.is_signer;
+            account.is_writable = meta.is_writable;
+            callee_accounts.push(account);
+        }
+
+        CALL_STACK.with(|stack| stack.borrow_mut().push(instruction.program_id));
+        let result = dispatch(&instruction.program_id, &callee_accounts, &instruction.data);
+        CALL_STACK.with(|stack| stack.borrow_mut().pop());
+        result
+    }
+}
+
+fn current_program() -> Pubkey {
+    CALL_STACK.with(|stack| *stack.borrow().last().expect("no program is executing"))
+}
+
+fn dispatch(program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+    if *program_id == system_program::ID {
+        system_processor(accounts, data)
+    } else if *program_id == spl_token::id() {
+        spl_token::processor::Processor::process(program_id, accounts, data)
+    } else if *program_id == spl_token_2022::id() {
+        spl_token_2022::processor::Processor::process(program_id, accounts, data)
+    } else if *program_id == spl_associated_token_account::id() {
+        spl_associated_token_account::processor::process_instruction(program_id, accounts, data)
+    } else if *program_id == stake::program::id() {
+        stake_processor(accounts, data)
+    } else if let Some(program) = MOCK_PROGRAMS.with(|programs| programs.borrow().get(program_id).copied()) {
+        program(program_id, accounts, data)
+    } else {
+        Err(ProgramError::IncorrectProgramId)
+    }
+}
+
+fn signed_account<'a, 'b>(accounts: &'b [AccountInfo<'a>], index: usize) -> Result<&'b AccountInfo<'a>, ProgramError> {
+    let account = accounts.get(index).ok_or(ProgramError::NotEnoughAccountKeys)?;
+    if !account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    Ok(account)
+}
+
+fn move_lamports(from: &AccountInfo, to: &AccountInfo, lamports: u64) -> ProgramResult {
+    if from.lamports() < lamports {
+        return Err(ProgramError::InsufficientFunds);
+    }
+    **from.try_borrow_mut_lamports()? -= lamports;
+    **to.try_borrow_mut_lamports()? += lamports;
+    Ok(())
+}
+
+/// The system program instructions the wallet uses, with the real program's checks
+fn system_processor(accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+    let unallocated = |account: &AccountInfo| *account.owner == system_program::ID && account.data_is_empty();
+
+    match bincode::deserialize(data).map_err(|_| ProgramError::InvalidInstructionData)? {
+        SystemInstruction::CreateAccount { lamports, space, owner } => {
+            let (from, to) = (signed_account(accounts, 0)?, signed_account(accounts, 1)?);
+            if to.lamports() > 0 || !unallocated(to) {
+                return Err(ProgramError::AccountAlreadyInitialized);
+            }
+            if !unallocated(from) {
+                return Err(ProgramError::InvalidArgument);
+            }
+            move_lamports(from, to, lamports)?;
+            to.realloc(space as usize, true)?;
+            to.assign(&owner);
+            Ok(())
+        }
+        SystemInstruction::Transfer { lamports } => {
+            let from = signed_account(accounts, 0)?;
+            if !unallocated(from) {
+                return Err(ProgramError::InvalidArgument);
+            }
+            move_lamports(from, accounts.get(1).ok_or(ProgramError::NotEnoughAccountKeys)?, lamports)
+        }
+        SystemInstruction::Allocate { space } => {
+            let account = signed_account(accounts, 0)?;
+            if !unallocated(account) {
+                return Err(ProgramError::AccountAlreadyInitialized);
+            }
+            account.realloc(space as usize, true)
+        }
+        SystemInstruction::Assign { owner } => {
+            let account = signed_account(accounts, 0)?;
+            if *account.owner != system_program::ID {
+                return Err(ProgramError::IllegalOwner);
+            }
+            account.assign(&owner);
+            Ok(())
+        }
+        _ => Err(ProgramError::InvalidInstructionData),
+    }
+}
+
+fn stake_state(account: &AccountInfo) -> Result<StakeStateV2, ProgramError> {
+    bincode::deserialize(&account.data.borrow()).map_err(|_| ProgramError::InvalidAccountData)
+}
+
+fn set_stake_state(account: &AccountInfo, state: &StakeStateV2) -> ProgramResult {
+    bincode::serialize_into(&mut account.data.borrow_mut()[..], state).map_err(|_| ProgramError::AccountDataTooSmall)
+}
+
+/// Model of the stake program: accounts go from initialized to delegated to deactivated, their
+/// authorities can be handed over, and delegated lamports can't be withdrawn until the stake is
+/// deactivated
+fn stake_processor(accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+    let stake_account = accounts.first().ok_or(ProgramError::NotEnoughAccountKeys)?;
+    if *stake_account.owner != stake::program::id() || stake_account.data_len() != StakeStateV2::size_of() {
+        return Err(ProgramError::InvalidAccountOwner);
+    }
+    let check_authority = |index: usize, authority: &Pubkey| {
+        let signer = signed_account(accounts, index)?;
+        if signer.key != authority {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        Ok(())
+    };
+
+    match bincode::deserialize(data).map_err(|_| ProgramError::InvalidInstructionData)? {
+        StakeInstruction::Initialize(authorized, lockup) => {
+            if stake_state(stake_account)? != StakeStateV2::Uninitialized {
+                return Err(ProgramError::InvalidAccountData);
+            }
+            let rent_exempt_reserve = Rent::default().minimum_balance(StakeStateV2::size_of());
+            if stake_account.lamports() < rent_exempt_reserve {
+                return Err(ProgramError::InsufficientFunds);
+            }
+            set_stake_state(stake_account, &StakeStateV2::Initialized(Meta { rent_exempt_reserve, authorized, lockup }))
+        }
+        StakeInstruction::DelegateStake => {
+            let vote_account = accounts.get(1).ok_or(ProgramError::NotEnoughAccountKeys)?;
+            let meta = match stake_state(stake_account)? {
+                StakeStateV2::Initialized(meta) => meta,
+                _ => return Err(ProgramError::InvalidAccountData),
+            };
+            check_authority(5, &meta.authorized.staker)?;
+            let delegation = Delegation::new(vote_account.key, stake_account.lamports() - meta.rent_exempt_reserve, 0);
+            let stake = Stake { delegation, credits_observed: 0 };
+            set_stake_state(stake_account, &StakeStateV2::Stake(meta, stake, StakeFlags::empty()))
+        }
+        StakeInstruction::Deactivate => {
+            let (meta, mut stake, flags) = match stake_state(stake_account)? {
+                StakeStateV2::Stake(meta, stake, flags) => (meta, stake, flags),
+                _ => return Err(ProgramError::InvalidAccountData),
+            };
+            check_authority(2, &meta.authorized.staker)?;
+            if stake.delegation.deactivation_epoch != u64::MAX {
+                return Err(ProgramError::InvalidAccountData);
+            }
+            stake.delegation.deactivation_epoch = 0;
+            set_stake_state(stake_account, &StakeStateV2::Stake(meta, stake, flags))
+        }
+        StakeInstruction::Authorize(new_authority, stake_authorize) => {
+            let mut meta = stake_state(stake_account)?.meta().ok_or(ProgramError::InvalidAccountData)?;
+            let authority = match stake_authorize {
+                StakeAuthorize::Staker => &mut meta.authorized.staker,
+                StakeAuthorize::Withdrawer => &mut meta.authorized.withdrawer,
+            };
+            check_authority(2, authority)?;
+            *authority = new_authority;
+            let state = match stake_state(stake_account)? {
+                StakeStateV2::Stake(_, stake, flags) => StakeStateV2::Stake(meta, stake, flags),
+                _ => StakeStateV2::Initialized(meta),
+            };
+            set_stake_state(stake_account, &state)
+        }
+        StakeInstruction::Withdraw(lamports) => {
+            let recipient = accounts.get(1).ok_or(ProgramError::NotEnoughAccountKeys)?;
+            let (meta, locked) = match stake_state(stake_account)? {
+                StakeStateV2::Initialized(meta) => (meta, 0),
+                StakeStateV2::Stake(meta, stake, _) if stake.delegation.deactivation_epoch == u64::MAX => {
+                    (meta, stake.delegation.stake)
+                }
+                StakeStateV2::Stake(meta, _, _) => (meta, 0),
+                _ => return Err(ProgramError::InvalidAccountData),
+            };
+            check_authority(4, &meta.authorized.withdrawer)?;
+
+            // Emptying the account takes the reserve with it; otherwise the reserve stays
+            let remaining = stake_account.lamports().checked_sub(lamports).ok_or(ProgramError::InsufficientFunds)?;
+            let required = if locked > 0 { locked + meta.rent_exempt_reserve } else { meta.rent_exempt_reserve };
+            if remaining < required && (locked > 0 || remaining != 0) {
+                return Err(ProgramError::InsufficientFunds);
+            }
+            move_lamports(stake_account, recipient, lamports)?;
+            if remaining == 0 {
+                set_stake_state(stake_account, &StakeStateV2::Uninitialized)?;
+            }
+            Ok(())
+        }
+        _ => Err(ProgramError::InvalidInstructionData),
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Account {
+    pub lamports: u64,
+    pub data: Vec<u8>,
+    pub owner: Pubkey,
+    pub executable: bool,
+}
+
+impl Account {
+    pub fn system(lamports: u64) -> Self {
+        Account {
+            lamports,
+            data: vec![],
+            owner: system_program::ID,
+            executable: false,
+        }
+    }
+
+    /// A rent-exempt account of `owner` holding `data`
+    pub fn with_data(owner: Pubkey, data: Vec<u8>) -> Self {
+        Account {
+            lamports: Rent::default().minimum_balance(data.len()),
+            data,
+            owner,
+            executable: false,
+        }
+    }
+
+    fn program() -> Self {
+        Account {
+            lamports: 1,
+            data: vec![],
+            owner: solana_program::bpf_loader::ID,
+            executable: true,
+        }
+    }
+}
+
+/// Runs the wallet program over a set of accounts, like a one-instruction transaction:
+/// account changes are kept only when the instruction succeeds
+pub struct Runtime {
+    pub program_id: Pubkey,
+    pub accounts: BTreeMap<Pubkey, Account>,
+}
+
+impl Default for Runtime {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Runtime {
+    pub fn new() -> Self {
+        static INSTALL_STUBS: Once = Once::new();
+        INSTALL_STUBS.call_once(|| {
+            set_syscall_stubs(Box::new(RuntimeStubs));
+        });
+        set_time(NOW);
+
+        let mut accounts = BTreeMap::new();
+        for program in [
+            system_program::ID,
+            spl_token::id(),
+            spl_token_2022::id(),
+            spl_associated_token_account::id(),
+            stake::program::id(),
+        ] {
+            accounts.insert(program, Account::program());
+        }
+        for sysvar in [sysvar::clock::ID, sysvar::rent::ID, sysvar::stake_history::ID] {
+            accounts.insert(sysvar, Account::with_data(sysvar::ID, vec![]));
+        }
+
+        Runtime {
+            program_id: Pubkey::new_unique(),
+            accounts,
+        }
+    }
+
+    /// Lays the accounts out the way the BPF loader passes them to a program, so the program
+    /// can resize and reassign them as it would on-chain
+    fn serialize(&self, metas: &[AccountMeta]) -> Vec<u64> {
+        let mut input = Vec::new();
+        input.extend_from_slice(&(metas.len() as u64).to_le_bytes());
+        for (index, meta) in metas.iter().enumerate() {
+            if let Some(original) = metas[..index].iter().position(|other| other.pubkey == meta.pubkey) {
+                input.push(original as u8);
+                input.extend_from_slice(&[0; 7]);
+                continue;
+            }
+            let account = &self.accounts[&meta.pubkey];
+            input.extend_from_slice(&[NON_DUP_MARKER, meta.is_signer as u8, meta.is_writable as u8, account.executable as u8]);
+            input.extend_from_slice(&[0; 4]);
+            input.extend_from_slice(meta.pubkey.as_ref());
+            input.extend_from_slice(account.owner.as_ref());
+            input.extend_from_slice(&account.lamports.to_le_bytes());
+            input.extend_from_slice(&(account.data.len() as u64).to_le_bytes());
+            input.extend_from_slice(&account.data);
+            input.resize(input.len() + MAX_PERMITTED_DATA_INCREASE, 0);
+            input.resize(input.len().next_multiple_of(size_of::<u64>()), 0);
+            input.extend_from_slice(&0u64.to_le_bytes());
+        }
+        input.extend_from_slice(&0u64.to_le_bytes());
+        input.extend_from_slice(self.program_id.as_ref());
+
+        // Copied into `u64`s so the buffer is aligned like the loader's
+        input
+            .chunks(size_of::<u64>())
+            .map(|chunk| {
+                let mut word = [0; 8];
+                word[..chunk.len()].copy_from_slice(chunk);
+                u64::from_ne_bytes(word)
+            })
+            .collect()
+    }
+
+    /// Runs the wallet program. Accounts the runtime doesn't know yet start out as empty
+    /// system accounts
+    pub fn process(&mut self, metas: &[AccountMeta], instruction_data: &[u8]) -> ProgramResult {
+        for meta in metas {
+            self.accounts.entry(meta.pubkey).or_insert_with(|| Account::system(0));
+        }
+        take_logs();
+        take_events();
+
+        let mut input = self.serialize(metas);
+        let (_, accounts, _) = unsafe { deserialize(input.as_mut_ptr() as *mut u8) };
+        CALL_STACK.with(|stack| stack.replace(vec![self.program_id]));
+        let result = process_instruction(&self.program_id, &accounts, instruction_data);
+        CALL_STACK.with(|stack| stack.borrow_mut().clear());
+        result?;
+
+        let mut changed = BTreeMap::new();
+        for (account, meta) in accounts.iter().zip(metas) {
+            let after = Account {
+                lamports: account.lamports(),
+                data: account.data.borrow().to_vec(),
+                owner: *account.owner,
+                executable: account.executable,
+            };
+            changed.insert(meta.pubkey, (after, meta.is_writable));
+        }
+        drop(accounts);
+
+        let lamports_before: u128 = changed.keys().map(|key| self.accounts[key].lamports as u128).sum();
+        let lamports_after: u128 = changed.values().map(|(account, _)| account.lamports as u128).sum();
+        assert_eq!(lamports_before, lamports_after, "instruction created or destroyed lamports");
+
+        for (key, (account, writable)) in changed {
+            if !writable {
+                assert_eq!(self.accounts[&key], account, "read-only account {} was modified", key);
+            }
+            self.accounts.insert(key, account);
+        }
+        Ok(())
+    }
+
+    /// Fills in the instructions sysvar as if the wallet instruction ran at `current_index`
+    /// of a transaction made of `instructions`
+    pub fn set_instructions(&mut self, instructions: &[Instruction], current_index: u16) {
+        let borrowed: Vec<_> = instructions
+            .iter()
+            .map(|instruction| BorrowedInstruction {
+                program_id: &instruction.program_id,
+                accounts: instruction
+                    .accounts
+                    .iter()
+                    .map(|meta| BorrowedAccountMeta { pubkey: &meta.pubkey, is_signer: meta.is_signer, is_writable: meta.is_writable })
+                    .collect(),
+                data: &instruction.data,
+            })
+            .collect();
+        let mut data = construct_instructions_data(&borrowed);
+        store_current_index(&mut data, current_index);
+        self.accounts.insert(sysvar::instructions::ID, Account::with_data(sysvar::ID, data));
+    }
+
+    /// Runs a wallet instruction, such as one from the client builders
+    pub fn execute(&mut self, instruction: &Instruction) -> ProgramResult {
+        assert_eq!(instruction.program_id, self.program_id);
+        self.process(&instruction.accounts, &instruction.data)
+    }
+
+    pub fn lamports(&self, key: &Pubkey) -> u64 {
+        self.accounts.get(key).map_or(0, |account| account.lamports)
+    }
+
+    pub fn fund(&mut self, key: &Pubkey, lamports: u64) {
+        self.accounts.entry(*key).or_insert_with(|| Account::system(0)).lamports += lamports;
+    }
+
+    pub fn exists(&self, key: &Pubkey) -> bool {
+        self.lamports(key) > 0
+    }
+
+    /// The program account at `key`, read with any of its layouts
+    pub fn state<T: ProgramAccount>(&self, key: &Pubkey) -> T {
+        unpack_account_data(&self.accounts[key].data).unwrap()
+    }
+
+    pub fn spendable(&self, key: &Pubkey) -> u64 {
+        let account = &self.accounts[key];
+        account.lamports.saturating_sub(Rent::default().minimum_balance(account.data.len()))
+    }
+
+    /// Creates a wallet for a new owner, holding `lamports` on top of its rent. Returns the
+    /// owner and the wallet
+    pub fn create_wallet(&mut self, lamports: u64) -> (Pubkey, Pubkey) {
+        let owner = Pubkey::new_unique();
+        let (wallet, _) = derive_wallet_address(&owner, &self.program_id);
+        self.fund(&owner, 10 * LAMPORTS_PER_SOL);
+        self.execute(&client::create_wallet(&self.program_id, &owner)).unwrap();
+        self.fund(&wallet, lamports);
+        (owner, wallet)
+    }
+
+    /// Approves `dapp` to spend up to `max_amount` of `mint` from `owner`'s wallet for a day.
+    /// Returns the approval account
+    pub fn approve(&mut self, owner: &Pubkey, dapp: &Pubkey, mint: &Pubkey, max_amount: u64) -> Pubkey {
+        let (wallet, _) = derive_wallet_address(owner, &self.program_id);
+        self.execute(&client::approve_dapp(&self.program_id, owner, dapp, mint, max_amount, NOW + 86_400)).unwrap();
+        self.fund(dapp, LAMPORTS_PER_SOL);
+        derive_approval_address(&wallet, dapp, mint, &self.program_id).0
+    }
+
+    pub fn add_mint(&mut self, token_program: &Pubkey, decimals: u8) -> Pubkey {
+        let mint = Pubkey::new_unique();
+        let mut data = vec![0; spl_token::state::Mint::LEN];
+        spl_token::state::Mint {
+            mint_authority: COption::Some(Pubkey::new_unique()),
+            supply: u64::MAX / 2,
+            decimals,
+            is_initialized: true,
+            freeze_authority: COption::None,
+        }
+        .pack_into_slice(&mut data);
+        self.accounts.insert(mint, Account::with_data(*token_program, data));
+        mint
+    }
+
+    /// Creates a Token-2022 mint with `extensions`, which `init` fills in
+    pub fn add_token_2022_mint(
+        &mut self,
+        decimals: u8,
+        extensions: &[ExtensionType],
+        init: impl FnOnce(&mut StateWithExtensionsMut<spl_token_2022::state::Mint>),
+    ) -> Pubkey {
+        let mint = Pubkey::new_unique();
+        let mut data = vec![0; ExtensionType::try_calculate_account_len::<spl_token_2022::state::Mint>(extensions).unwrap()];
+        let mut state = StateWithExtensionsMut::<spl_token_2022::state::Mint>::unpack_uninitialized(&mut data).unwrap();
+        init(&mut state);
+        state.base = spl_token_2022::state::Mint {
+            mint_authority: COption::Some(Pubkey::new_unique()),
+            supply: u64::MAX / 2,
+            decimals,
+            is_initialized: true,
+            freeze_authority: COption::None,
+        };
+        state.pack_base();
+        state.init_account_type().unwrap();
+        self.accounts.insert(mint, Account::with_data(spl_token_2022::id(), data));
+        mint
+    }
+
+    /// Adds the native mint of `token_program`, which every cluster has at a fixed address
+    pub fn add_native_mint(&mut self, token_program: &Pubkey) -> Pubkey {
+        let mint = if *token_program == spl_token::id() { spl_token::native_mint::id() } else { spl_token_2022::native_mint::id() };
+        let mut data = vec![0; spl_token::state::Mint::LEN];
+        spl_token::state::Mint {
+            mint_authority: COption::None,
+            supply: 0,
+            decimals: spl_token::native_mint::DECIMALS,
+            is_initialized: true,
+            freeze_authority: COption::None,
+        }
+        .pack_into_slice(&mut data);
+        self.accounts.insert(mint, Account::with_data(*token_program, data));
+        mint
+    }
+
+    /// Adds a Pyth-format price account quoting `price * 10^expo` USD, published now
+    pub fn add_price_feed(&mut self, price: i64, expo: i32) -> Pubkey {
+        let feed = Pubkey::new_unique();
+        let data = mock_price_account_data(price, 0, expo, UNIX_TIMESTAMP.with(Cell::get));
+        self.accounts.insert(feed, Account::with_data(Pubkey::new_unique(), data));
+        feed
+    }
+
+    /// Creates an initialized stake account holding `lamports`, with `authority` as its staker
+    /// and withdrawer
+    pub fn add_stake_account(&mut self, authority: &Pubkey, lamports: u64) -> Pubkey {
+        let address = Pubkey::new_unique();
+        let meta = Meta {
+            rent_exempt_reserve: Rent::default().minimum_balance(StakeStateV2::size_of()),
+            authorized: Authorized { staker: *authority, withdrawer: *authority },
+            lockup: Lockup::default(),
+        };
+        let mut data = vec![0; StakeStateV2::size_of()];
+        bincode::serialize_into(&mut data[..], &StakeStateV2::Initialized(meta)).unwrap();
+        let account = Account { lamports, ..Account::with_data(stake::program::id(), data) };
+        self.accounts.insert(address, account);
+        address
+    }
+
+    /// Creates `owner`'s associated token account for `mint` holding `amount`, with the
+    /// account extensions the mint requires
+    pub fn add_token_account(&mut self, token_program: &Pubkey, mint: &Pubkey, owner: &Pubkey, amount: u64) -> Pubkey {
+        let address = get_associated_token_address_with_program_id(owner, mint, token_program);
+        let extensions = match self.accounts.get(mint) {
+            Some(mint) if mint.data.len() > spl_token::state::Mint::LEN => {
+                let mint = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&mint.data).unwrap();
+                ExtensionType::get_required_init_account_extensions(&mint.get_extension_types().unwrap())
+            }
+            _ => vec![],
+        };
+        let mut data = vec![0; ExtensionType::try_calculate_account_len::<spl_token_2022::state::Account>(&extensions).unwrap()];
+        let rent_reserve = Rent::default().minimum_balance(data.len());
+        let is_native = *mint == spl_token::native_mint::id() || *mint == spl_token_2022::native_mint::id();
+        let mut state = StateWithExtensionsMut::<spl_token_2022::state::Account>::unpack_uninitialized(&mut data).unwrap();
+        for extension in extensions {
+            state.init_account_extension_from_type(extension).unwrap();
+        }
+        state.base = spl_token_2022::state::Account {
+            mint: *mint,
+            owner: *owner,
+            amount,
+            delegate: COption::None,
+            state: spl_token_2022::state::AccountState::Initialized,
+            is_native: if is_native { COption::Some(rent_reserve) } else { COption::None },
+            delegated_amount: 0,
+            close_authority: COption::None,
+        };
+        state.pack_base();
+        state.init_account_type().unwrap();
+        let mut account = Account::with_data(*token_program, data);
+        if is_native {
+            account.lamports += amount;
+        }
+        self.accounts.insert(address, account);
+        address
+    }
+
+    pub fn token_balance(&self, key: &Pubkey) -> u64 {
+        spl_token::state::Account::unpack_from_slice(&self.accounts[key].data[..spl_token::state::Account::LEN])
+            .unwrap()
+            .amount
+    }
+}
@@ -0,0 +1,204 @@
+//! Co-signing: transfers between an approval's auto limit and co-sign limit wait for the owner
+
+mod common;
+
+use smart_contract::{
+    client,
+    error::WalletErrorCode,
+    event::WalletEvent,
+    instruction::WalletInstruction,
+    state::{derive_pending_execution_address, DAppApproval},
+};
+use solana_program::{
+    instruction::AccountMeta, native_token::LAMPORTS_PER_SOL, program_error::ProgramError, pubkey::Pubkey,
+    system_program,
+};
+
+use crate::common::{set_time, take_events, Runtime, NOW};
+
+const MAX_AMOUNT: u64 = LAMPORTS_PER_SOL;
+const AUTO_LIMIT: u64 = 1000;
+const COSIGN_LIMIT: u64 = 100_000;
+const TTL: i64 = 24 * 60 * 60;
+
+struct Setup {
+    owner: Pubkey,
+    wallet: Pubkey,
+    dapp: Pubkey,
+    approval: Pubkey,
+    recipient: Pubkey,
+}
+
+/// A SOL approval with co-signing enabled
+fn setup(runtime: &mut Runtime) -> Setup {
+    let (owner, wallet) = runtime.create_wallet(LAMPORTS_PER_SOL);
+    let dapp = Pubkey::new_unique();
+    let approval = runtime.approve(&owner, &dapp, &Pubkey::default(), MAX_AMOUNT);
+    let setup = Setup { owner, wallet, dapp, approval, recipient: Pubkey::new_unique() };
+    set_limits(runtime, &setup, AUTO_LIMIT, COSIGN_LIMIT).unwrap();
+    setup
+}
+
+fn set_limits(runtime: &mut Runtime, setup: &Setup, auto_limit: u64, cosign_limit: u64) -> Result<(), ProgramError> {
+    let metas = [
+        AccountMeta::new(setup.owner, true),
+        AccountMeta::new_readonly(setup.wallet, false),
+        AccountMeta::new_readonly(setup.dapp, false),
+        AccountMeta::new(setup.approval, false),
+        AccountMeta::new_readonly(system_program::ID, false),
+    ];
+    let instruction = WalletInstruction::UpdateApproval {
+        max_amount: None,
+        expiry: None,
+        recipients: None,
+        is_approved: None,
+        valid_from: None,
+        max_executions: None,
+        single_use: None,
+        usd_limit: None,
+        auto_limit: Some(auto_limit),
+        cosign_limit: Some(cosign_limit),
+    };
+    runtime.process(&metas, &instruction.pack())
+}
+
+fn propose(runtime: &mut Runtime, setup: &Setup, amount: u64, request_id: [u8; 32]) -> Result<Pubkey, ProgramError> {
+    let (pending, _) = derive_pending_execution_address(&setup.approval, &request_id, &runtime.program_id);
+    let metas = [
+        AccountMeta::new(setup.dapp, true),
+        AccountMeta::new_readonly(setup.owner, false),
+        AccountMeta::new_readonly(setup.wallet, false),
+        AccountMeta::new_readonly(setup.approval, false),
+        AccountMeta::new_readonly(setup.recipient, false),
+        AccountMeta::new(pending, false),
+        AccountMeta::new_readonly(system_program::ID, false),
+    ];
+    runtime.process(&metas, &WalletInstruction::ProposeExecution { amount, request_id }.pack()).map(|()| pending)
+}
+
+fn confirm(runtime: &mut Runtime, setup: &Setup, owner: &Pubkey, pending: &Pubkey) -> Result<(), ProgramError> {
+    let metas = [
+        AccountMeta::new(*owner, true),
+        AccountMeta::new(setup.wallet, false),
+        AccountMeta::new(setup.approval, false),
+        AccountMeta::new(*pending, false),
+        AccountMeta::new(setup.dapp, false),
+        AccountMeta::new(setup.recipient, false),
+        AccountMeta::new_readonly(system_program::ID, false),
+    ];
+    runtime.process(&metas, &WalletInstruction::ConfirmExecution.pack())
+}
+
+fn cancel(runtime: &mut Runtime, setup: &Setup, signer: &Pubkey, pending: &Pubkey) -> Result<(), ProgramError> {
+    let metas = [
+        AccountMeta::new_readonly(*signer, true),
+        AccountMeta::new(*pending, false),
+        AccountMeta::new(setup.dapp, false),
+    ];
+    runtime.process(&metas, &WalletInstruction::CancelExecution.pack())
+}
+
+fn pay(runtime: &mut Runtime, setup: &Setup, amount: u64) -> Result<(), ProgramError> {
+    let program_id = runtime.program_id;
+    runtime.execute(&client::execute_transaction(&program_id, &setup.dapp, &setup.owner, &setup.recipient, amount, None, None))
+}
+
+#[test]
+fn amounts_above_the_auto_limit_wait_for_the_owner() {
+    let mut runtime = Runtime::new();
+    let setup = setup(&mut runtime);
+
+    pay(&mut runtime, &setup, AUTO_LIMIT).unwrap();
+    assert_eq!(pay(&mut runtime, &setup, AUTO_LIMIT + 1), Err(WalletErrorCode::CosignRequired.into()));
+
+    let dapp_before = runtime.lamports(&setup.dapp);
+    let pending = propose(&mut runtime, &setup, COSIGN_LIMIT, [1; 32]).unwrap();
+    assert_eq!(runtime.lamports(&setup.recipient), AUTO_LIMIT);
+
+    // Confirming pays the recipient and refunds the dApp the pending execution's rent
+    confirm(&mut runtime, &setup, &setup.owner, &pending).unwrap();
+    assert_eq!(
+        take_events(),
+        [WalletEvent::TransferExecuted {
+            approval: setup.approval,
+            wallet: setup.wallet,
+            recipient: setup.recipient,
+            token_mint: Pubkey::default(),
+            amount: COSIGN_LIMIT,
+            debited_amount: COSIGN_LIMIT,
+        }]
+    );
+    assert_eq!(runtime.lamports(&setup.recipient), AUTO_LIMIT + COSIGN_LIMIT);
+    assert!(!runtime.exists(&pending));
+    assert_eq!(runtime.lamports(&setup.dapp), dapp_before);
+    let approval: DAppApproval = runtime.state(&setup.approval);
+    assert_eq!(u64::from(approval.spent_amount), AUTO_LIMIT + COSIGN_LIMIT);
+
+    assert!(confirm(&mut runtime, &setup, &setup.owner, &pending).is_err());
+}
+
+#[test]
+fn proposals_must_need_a_co_signature() {
+    let mut runtime = Runtime::new();
+    let setup = setup(&mut runtime);
+
+    assert_eq!(propose(&mut runtime, &setup, AUTO_LIMIT, [1; 32]), Err(ProgramError::InvalidArgument));
+    assert_eq!(propose(&mut runtime, &setup, COSIGN_LIMIT + 1, [1; 32]), Err(ProgramError::InvalidArgument));
+
+    propose(&mut runtime, &setup, AUTO_LIMIT + 1, [1; 32]).unwrap();
+    assert_eq!(propose(&mut runtime, &setup, AUTO_LIMIT + 1, [1; 32]), Err(ProgramError::AccountAlreadyInitialized));
+
+    set_limits(&mut runtime, &setup, 0, 0).unwrap();
+    assert_eq!(propose(&mut runtime, &setup, AUTO_LIMIT + 1, [2; 32]), Err(ProgramError::InvalidAccountData));
+}
+
+#[test]
+fn only_the_owner_confirms_in_time() {
+    let mut runtime = Runtime::new();
+    let setup = setup(&mut runtime);
+    let pending = propose(&mut runtime, &setup, COSIGN_LIMIT, [1; 32]).unwrap();
+    let setup_recipient = setup.recipient;
+
+    let (other_owner, _) = runtime.create_wallet(0);
+    assert_eq!(confirm(&mut runtime, &setup, &other_owner, &pending), Err(ProgramError::InvalidAccountData));
+    let other_recipient = Setup { recipient: Pubkey::new_unique(), ..setup };
+    assert_eq!(
+        confirm(&mut runtime, &other_recipient, &other_recipient.owner, &pending),
+        Err(ProgramError::InvalidAccountData)
+    );
+
+    let setup = Setup { recipient: setup_recipient, ..other_recipient };
+
+    // The pending execution expires a day after it was proposed
+    set_time(NOW + TTL);
+    assert_eq!(confirm(&mut runtime, &setup, &setup.owner, &pending), Err(ProgramError::InvalidArgument));
+    assert_eq!(runtime.lamports(&setup.recipient), 0);
+}
+
+#[test]
+fn confirmations_respect_the_current_limits() {
+    let mut runtime = Runtime::new();
+    let setup = setup(&mut runtime);
+    let pending = propose(&mut runtime, &setup, COSIGN_LIMIT, [1; 32]).unwrap();
+
+    set_limits(&mut runtime, &setup, AUTO_LIMIT, COSIGN_LIMIT - 1).unwrap();
+    assert_eq!(confirm(&mut runtime, &setup, &setup.owner, &pending), Err(ProgramError::InvalidAccountData));
+}
+
+#[test]
+fn the_owner_or_the_dapp_can_cancel() {
+    let mut runtime = Runtime::new();
+    let setup = setup(&mut runtime);
+    let dapp_before = runtime.lamports(&setup.dapp);
+
+    let pending = propose(&mut runtime, &setup, COSIGN_LIMIT, [1; 32]).unwrap();
+    assert_eq!(cancel(&mut runtime, &setup, &Pubkey::new_unique(), &pending), Err(ProgramError::MissingRequiredSignature));
+    cancel(&mut runtime, &setup, &setup.owner, &pending).unwrap();
+    assert!(!runtime.exists(&pending));
+    assert_eq!(runtime.lamports(&setup.dapp), dapp_before);
+
+    let pending = propose(&mut runtime, &setup, COSIGN_LIMIT, [2; 32]).unwrap();
+    cancel(&mut runtime, &setup, &setup.dapp, &pending).unwrap();
+    assert_eq!(confirm(&mut runtime, &setup, &setup.owner, &pending), Err(ProgramError::IllegalOwner));
+    assert_eq!(runtime.lamports(&setup.recipient), 0);
+}
@@ -0,0 +1,186 @@
+//! `ApproveCpi` and `ExecuteCpi`: calls into other programs with the wallet as a signer
+
+mod common;
+
+use smart_contract::{
+    error::WalletErrorCode,
+    event::WalletEvent,
+    instruction::WalletInstruction,
+    state::{derive_cpi_approval_address, derive_wallet_address},
+};
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    native_token::LAMPORTS_PER_SOL,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    stake::{self, state::StakeAuthorize},
+    system_program,
+};
+use spl_token::instruction::AuthorityType;
+
+use crate::common::{set_time, take_events, Runtime, NOW};
+
+const MAX_AMOUNT: u64 = 500;
+
+struct Setup {
+    owner: Pubkey,
+    wallet: Pubkey,
+    dapp: Pubkey,
+    wallet_token: Pubkey,
+    recipient: Pubkey,
+}
+
+/// A wallet holding 1000 tokens and a dApp that isn't approved for anything yet
+fn setup(runtime: &mut Runtime) -> Setup {
+    let (owner, wallet) = runtime.create_wallet(LAMPORTS_PER_SOL);
+    let mint = runtime.add_mint(&spl_token::id(), 6);
+    let wallet_token = runtime.add_token_account(&spl_token::id(), &mint, &wallet, 1000);
+    let recipient = runtime.add_token_account(&spl_token::id(), &mint, &Pubkey::new_unique(), 0);
+    let dapp = Pubkey::new_unique();
+    runtime.fund(&dapp, LAMPORTS_PER_SOL);
+    Setup { owner, wallet, dapp, wallet_token, recipient }
+}
+
+fn approve_cpi(runtime: &mut Runtime, setup: &Setup, target_program: &Pubkey, expiry: i64) -> Result<(), ProgramError> {
+    let (approval, _) = derive_cpi_approval_address(&setup.wallet, &setup.dapp, target_program, &runtime.program_id);
+    let metas = [
+        AccountMeta::new(setup.owner, true),
+        AccountMeta::new_readonly(setup.wallet, false),
+        AccountMeta::new_readonly(setup.dapp, false),
+        AccountMeta::new_readonly(*target_program, false),
+        AccountMeta::new(approval, false),
+        AccountMeta::new_readonly(system_program::ID, false),
+    ];
+    runtime.process(&metas, &WalletInstruction::ApproveCpi { max_amount: MAX_AMOUNT, expiry }.pack())
+}
+
+/// Has `authority` make the wallet run `instruction`
+fn execute_cpi(runtime: &mut Runtime, setup: &Setup, authority: &Pubkey, instruction: Instruction) -> Result<(), ProgramError> {
+    let (approval, _) = derive_cpi_approval_address(&setup.wallet, authority, &instruction.program_id, &runtime.program_id);
+    let mut metas = vec![
+        AccountMeta::new_readonly(*authority, true),
+        AccountMeta::new_readonly(setup.owner, false),
+        AccountMeta::new(setup.wallet, false),
+        AccountMeta::new_readonly(approval, false),
+        AccountMeta::new_readonly(instruction.program_id, false),
+    ];
+    // The wallet signs through the program, not the transaction
+    metas.extend(instruction.accounts.iter().map(|meta| AccountMeta { is_signer: false, ..meta.clone() }));
+    runtime.process(&metas, &WalletInstruction::ExecuteCpi { instruction_data: instruction.data }.pack())
+}
+
+fn token_transfer(setup: &Setup, amount: u64) -> Instruction {
+    spl_token::instruction::transfer(&spl_token::id(), &setup.wallet_token, &setup.recipient, &setup.wallet, &[], amount).unwrap()
+}
+
+#[test]
+fn approved_dapps_can_move_up_to_their_limit() {
+    let mut runtime = Runtime::new();
+    let setup = setup(&mut runtime);
+    approve_cpi(&mut runtime, &setup, &spl_token::id(), NOW + 3600).unwrap();
+
+    assert_eq!(
+        execute_cpi(&mut runtime, &setup, &setup.dapp, token_transfer(&setup, MAX_AMOUNT + 1)),
+        Err(ProgramError::InsufficientFunds)
+    );
+    assert_eq!(runtime.token_balance(&setup.wallet_token), 1000);
+
+    execute_cpi(&mut runtime, &setup, &setup.dapp, token_transfer(&setup, MAX_AMOUNT)).unwrap();
+    assert_eq!(runtime.token_balance(&setup.recipient), MAX_AMOUNT);
+    assert_eq!(
+        take_events(),
+        [WalletEvent::CpiExecuted { wallet: setup.wallet, authority: setup.dapp, program_id: spl_token::id() }]
+    );
+}
+
+#[test]
+fn dapps_need_an_unexpired_approval_for_the_target() {
+    let mut runtime = Runtime::new();
+    let setup = setup(&mut runtime);
+
+    assert_eq!(
+        execute_cpi(&mut runtime, &setup, &setup.dapp, token_transfer(&setup, 1)),
+        Err(ProgramError::IllegalOwner)
+    );
+
+    // An approval for one program doesn't cover another
+    approve_cpi(&mut runtime, &setup, &spl_token_2022::id(), NOW + 3600).unwrap();
+    assert_eq!(
+        execute_cpi(&mut runtime, &setup, &setup.dapp, token_transfer(&setup, 1)),
+        Err(ProgramError::IllegalOwner)
+    );
+
+    approve_cpi(&mut runtime, &setup, &spl_token::id(), NOW + 3600).unwrap();
+    execute_cpi(&mut runtime, &setup, &setup.dapp, token_transfer(&setup, 1)).unwrap();
+    set_time(NOW + 3600);
+    assert_eq!(
+        execute_cpi(&mut runtime, &setup, &setup.dapp, token_transfer(&setup, 1)),
+        Err(WalletErrorCode::ApprovalExpired.into())
+    );
+}
+
+#[test]
+fn dapps_cannot_change_token_account_authorities() {
+    let mut runtime = Runtime::new();
+    let setup = setup(&mut runtime);
+    approve_cpi(&mut runtime, &setup, &spl_token::id(), NOW + 3600).unwrap();
+
+    // A delegate could drain the account later, outside any limit
+    let delegate = spl_token::instruction::approve(&spl_token::id(), &setup.wallet_token, &setup.dapp, &setup.wallet, &[], 1).unwrap();
+    assert_eq!(execute_cpi(&mut runtime, &setup, &setup.dapp, delegate), Err(ProgramError::InvalidAccountData));
+
+    let close_authority = spl_token::instruction::set_authority(
+        &spl_token::id(),
+        &setup.wallet_token,
+        Some(&setup.dapp),
+        AuthorityType::CloseAccount,
+        &setup.wallet,
+        &[],
+    )
+    .unwrap();
+    assert_eq!(execute_cpi(&mut runtime, &setup, &setup.dapp, close_authority), Err(ProgramError::InvalidAccountData));
+}
+
+#[test]
+fn dapps_cannot_drain_or_take_over_the_wallets_stake() {
+    let mut runtime = Runtime::new();
+    let setup = setup(&mut runtime);
+    let stake_account = runtime.add_stake_account(&setup.wallet, 2 * LAMPORTS_PER_SOL);
+    approve_cpi(&mut runtime, &setup, &stake::program::id(), NOW + 3600).unwrap();
+
+    // Withdrawals land outside the wallet, so its own balance would look untouched
+    let withdraw = stake::instruction::withdraw(&stake_account, &setup.wallet, &setup.dapp, LAMPORTS_PER_SOL, None);
+    assert_eq!(execute_cpi(&mut runtime, &setup, &setup.dapp, withdraw), Err(ProgramError::InvalidAccountData));
+
+    for stake_authorize in [StakeAuthorize::Staker, StakeAuthorize::Withdrawer] {
+        let authorize = stake::instruction::authorize(&stake_account, &setup.wallet, &setup.dapp, stake_authorize, None);
+        assert_eq!(execute_cpi(&mut runtime, &setup, &setup.dapp, authorize), Err(ProgramError::InvalidAccountData));
+    }
+    assert_eq!(runtime.lamports(&stake_account), 2 * LAMPORTS_PER_SOL);
+
+    // The owner manages the stake as they like
+    let withdraw = stake::instruction::withdraw(&stake_account, &setup.wallet, &setup.owner, LAMPORTS_PER_SOL, None);
+    execute_cpi(&mut runtime, &setup, &setup.owner, withdraw).unwrap();
+    assert_eq!(runtime.lamports(&stake_account), LAMPORTS_PER_SOL);
+}
+
+#[test]
+fn the_owner_needs_no_approval_or_limit() {
+    let mut runtime = Runtime::new();
+    let setup = setup(&mut runtime);
+
+    execute_cpi(&mut runtime, &setup, &setup.owner, token_transfer(&setup, 1000)).unwrap();
+    assert_eq!(runtime.token_balance(&setup.recipient), 1000);
+}
+
+#[test]
+fn the_wallet_program_cannot_be_the_target() {
+    let mut runtime = Runtime::new();
+    let setup = setup(&mut runtime);
+    let program_id = runtime.program_id;
+    let (wallet, _) = derive_wallet_address(&setup.owner, &program_id);
+    let withdraw = Instruction::new_with_bytes(program_id, &[], vec![AccountMeta::new(wallet, false)]);
+
+    assert_eq!(approve_cpi(&mut runtime, &setup, &program_id, NOW + 3600), Err(ProgramError::IncorrectProgramId));
+    assert_eq!(execute_cpi(&mut runtime, &setup, &setup.owner, withdraw), Err(ProgramError::IncorrectProgramId));
+}
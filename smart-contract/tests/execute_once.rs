@@ -0,0 +1,84 @@
+//! `ExecuteTransactionOnce`: transfers a dApp can only make once per request id
+
+mod common;
+
+use smart_contract::{
+    instruction::{TransferType, WalletInstruction},
+    state::{derive_receipt_address, ExecutionReceipt},
+};
+use solana_program::{
+    instruction::AccountMeta, native_token::LAMPORTS_PER_SOL, program_error::ProgramError, pubkey::Pubkey,
+    system_program,
+};
+
+use crate::common::{set_time, Runtime, NOW};
+
+struct Setup {
+    owner: Pubkey,
+    wallet: Pubkey,
+    dapp: Pubkey,
+    approval: Pubkey,
+    recipient: Pubkey,
+}
+
+fn setup(runtime: &mut Runtime) -> Setup {
+    let (owner, wallet) = runtime.create_wallet(LAMPORTS_PER_SOL);
+    let dapp = Pubkey::new_unique();
+    let approval = runtime.approve(&owner, &dapp, &Pubkey::default(), LAMPORTS_PER_SOL);
+    Setup { owner, wallet, dapp, approval, recipient: Pubkey::new_unique() }
+}
+
+fn execute_once(runtime: &mut Runtime, setup: &Setup, amount: u64, request_id: [u8; 32]) -> Result<(), ProgramError> {
+    let (receipt, _) = derive_receipt_address(&setup.approval, &request_id, &runtime.program_id);
+    let metas = [
+        AccountMeta::new(setup.dapp, true),
+        AccountMeta::new(setup.owner, false),
+        AccountMeta::new(setup.wallet, false),
+        AccountMeta::new(setup.approval, false),
+        AccountMeta::new(setup.recipient, false),
+        AccountMeta::new_readonly(system_program::ID, false),
+        AccountMeta::new(receipt, false),
+    ];
+    let instruction = WalletInstruction::ExecuteTransactionOnce { amount, transfer_type: TransferType::Sol, request_id };
+    runtime.process(&metas, &instruction.pack())
+}
+
+#[test]
+fn each_request_executes_once_and_leaves_a_receipt() {
+    let mut runtime = Runtime::new();
+    let setup = setup(&mut runtime);
+
+    execute_once(&mut runtime, &setup, 100, [1; 32]).unwrap();
+    assert_eq!(runtime.lamports(&setup.recipient), 100);
+    let (receipt, _) = derive_receipt_address(&setup.approval, &[1; 32], &runtime.program_id);
+    assert_eq!(runtime.accounts[&receipt].owner, runtime.program_id);
+    let receipt_data: ExecutionReceipt = runtime.state(&receipt);
+    assert_eq!(u64::from(receipt_data.amount), 100);
+    assert_eq!(i64::from(receipt_data.executed_at), NOW);
+
+    // A retry of the same request is refused, however much later it comes
+    set_time(NOW + 60);
+    assert_eq!(execute_once(&mut runtime, &setup, 100, [1; 32]), Err(ProgramError::AccountAlreadyInitialized));
+    assert_eq!(runtime.lamports(&setup.recipient), 100);
+
+    execute_once(&mut runtime, &setup, 250, [2; 32]).unwrap();
+    assert_eq!(runtime.lamports(&setup.recipient), 350);
+    let (receipt, _) = derive_receipt_address(&setup.approval, &[2; 32], &runtime.program_id);
+    let receipt_data: ExecutionReceipt = runtime.state(&receipt);
+    assert_eq!(u64::from(receipt_data.amount), 250);
+    assert_eq!(i64::from(receipt_data.executed_at), NOW + 60);
+}
+
+#[test]
+fn receipts_belong_to_their_approval() {
+    let mut runtime = Runtime::new();
+    let setup = setup(&mut runtime);
+    execute_once(&mut runtime, &setup, 100, [1; 32]).unwrap();
+
+    // Another dApp's approval has receipts of its own, so the same id is a new request there
+    let other_dapp = Pubkey::new_unique();
+    let other_approval = runtime.approve(&setup.owner, &other_dapp, &Pubkey::default(), LAMPORTS_PER_SOL);
+    let other = Setup { dapp: other_dapp, approval: other_approval, ..setup };
+    execute_once(&mut runtime, &other, 100, [1; 32]).unwrap();
+    assert_eq!(runtime.lamports(&other.recipient), 200);
+}
@@ -0,0 +1,571 @@
+//! Checks the generated IDL against the processor and keeps `idl.json` up to date.
+//! Run with `UPDATE_IDL=1` to regenerate the file after changing the interface.
+
+mod common;
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::Path,
+};
+
+use serde_json::Value;
+use smart_contract::{
+    client::{self, TokenTransfer},
+    error::WalletErrorCode,
+    idl::{idl, instruction_accounts, program_errors},
+    instruction::{TransferType, WalletInstruction},
+    permit::{ApprovalPermit, TransferIntent},
+    process_instruction,
+    state::{
+        derive_approval_address, derive_cpi_approval_address, derive_pending_execution_address, derive_permit_address, derive_receipt_address,
+        derive_stake_address, derive_standing_order_address, derive_wallet_address, AccountKind, UsdLimit,
+    },
+};
+use solana_program::{
+    account_info::AccountInfo,
+    instruction::{AccountMeta, Instruction},
+    native_token::LAMPORTS_PER_SOL,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    stake, system_program, sysvar,
+};
+
+use crate::common::{Runtime, NOW};
+
+/// One instruction of every variant, in tag order
+fn sample_instructions() -> Vec<WalletInstruction> {
+    vec![
+        WalletInstruction::CreateWallet,
+        WalletInstruction::ApproveDapp { max_amount: 1, expiry: 1 },
+        WalletInstruction::ExecuteTransaction { amount: 1, transfer_type: TransferType::Sol },
+        WalletInstruction::Withdraw { amount: 1, transfer_type: TransferType::Sol },
+        WalletInstruction::CreateStandingOrder { order_id: 1, amount: 1, interval: 1, start_time: 1 },
+        WalletInstruction::CrankStandingOrder,
+        WalletInstruction::CancelStandingOrder,
+        WalletInstruction::ExecuteBatch { transfers: vec![(0, 1)], transfer_type: TransferType::Sol },
+        WalletInstruction::ApproveCpi { max_amount: 1, expiry: 1 },
+        WalletInstruction::ExecuteCpi { instruction_data: vec![] },
+        WalletInstruction::WrapSol { amount: 1 },
+        WalletInstruction::UnwrapSol,
+        WalletInstruction::ExecuteTransactionOnce { amount: 1, transfer_type: TransferType::Sol, request_id: [1; 32] },
+        WalletInstruction::ApproveDappWithPermit { max_amount: 1, expiry: 1, nonce: 1 },
+        WalletInstruction::ExecuteIntent { amount: 1, transfer_type: TransferType::Sol, nonce: 1, deadline: 1 },
+        WalletInstruction::UpdateApproval {
+            max_amount: None,
+            expiry: None,
+            recipients: None,
+            is_approved: None,
+            valid_from: None,
+            max_executions: None,
+            single_use: None,
+            usd_limit: None,
+            auto_limit: None,
+            cosign_limit: None,
+        },
+        WalletInstruction::MigrateAccount { kind: AccountKind::Wallet },
+        WalletInstruction::ProposeExecution { amount: 1, request_id: [1; 32] },
+        WalletInstruction::ConfirmExecution,
+        WalletInstruction::CancelExecution,
+        WalletInstruction::CreateStake { stake_id: 1, amount: 1 },
+        WalletInstruction::DelegateStake { stake_id: 1 },
+        WalletInstruction::DeactivateStake { stake_id: 1 },
+        WalletInstruction::WithdrawStake { stake_id: 1, amount: 1 },
+        WalletInstruction::CancelPermit { nonce: 1 },
+    ]
+}
+
+/// Runs `instruction` with the IDL's leading unconditional accounts, cut to `count`, as fresh
+/// empty accounts. Signer flags are taken from the IDL unless `signers` is false
+fn run(program_id: &Pubkey, instruction: &WalletInstruction, name: &str, count: usize, signers: bool) -> Result<(), ProgramError> {
+    let expected = instruction_accounts(name).unwrap();
+    let metas: Vec<_> = expected.accounts.iter().take(count).collect();
+    let keys: Vec<Pubkey> = metas.iter().map(|_| Pubkey::new_unique()).collect();
+    let mut lamports = vec![0u64; metas.len()];
+    let mut data = vec![Vec::<u8>::new(); metas.len()];
+    let owner = Pubkey::default();
+
+    let accounts: Vec<AccountInfo> = metas
+        .iter()
+        .zip(&keys)
+        .zip(lamports.iter_mut().zip(data.iter_mut()))
+        .map(|((meta, key), (lamports, data))| {
+            AccountInfo::new(key, signers && meta.signer, meta.writable, lamports, data, &owner, false, 0)
+        })
+        .collect();
+
+    process_instruction(program_id, &accounts, &instruction.pack())
+}
+
+fn required_accounts(name: &str) -> usize {
+    instruction_accounts(name)
+        .unwrap()
+        .accounts
+        .iter()
+        .take_while(|account| account.when.is_none())
+        .count()
+}
+
+#[test]
+fn instructions_match_their_discriminants() {
+    let idl = idl();
+    let instructions = idl["instructions"].as_array().unwrap();
+    let samples = sample_instructions();
+    assert_eq!(instructions.len(), samples.len());
+
+    for (entry, sample) in instructions.iter().zip(&samples) {
+        let data = sample.pack();
+        assert_eq!(entry["discriminant"], u64::from(data[1]), "{}", entry["name"]);
+    }
+}
+
+/// Every condition of the IDL's optional accounts, so the happy paths pass all of them
+const ALL_CONDITIONS: &[&str] = &[
+    "the approval has a USD limit",
+    "transfer_type is Token",
+    "the order pays out tokens",
+    "kind is DAppApproval",
+    "kind is Wallet or DAppApproval",
+    "the approval is for a token",
+];
+
+const AUTO_LIMIT: u64 = 1000;
+const COSIGN_LIMIT: u64 = 10_000;
+
+/// A wallet holding tokens, with a token approval that has a USD limit and co-signing, and
+/// the address of every account an instruction can name
+struct Fixture {
+    runtime: Runtime,
+    keys: BTreeMap<&'static str, Pubkey>,
+}
+
+impl Fixture {
+    fn new() -> Self {
+        let mut runtime = Runtime::new();
+        let program_id = runtime.program_id;
+        let (owner, wallet) = runtime.create_wallet(LAMPORTS_PER_SOL);
+        let mint = runtime.add_mint(&spl_token::id(), 6);
+        let wallet_token = runtime.add_token_account(&spl_token::id(), &mint, &wallet, 1_000_000);
+        let recipient = runtime.add_token_account(&spl_token::id(), &mint, &Pubkey::new_unique(), 0);
+        let dapp = Pubkey::new_unique();
+        let approval = runtime.approve(&owner, &dapp, &mint, 1_000_000);
+
+        // One token is worth one dollar
+        let price_feed = runtime.add_price_feed(100_000_000, -8);
+        let limits = WalletInstruction::UpdateApproval {
+            max_amount: None,
+            expiry: None,
+            recipients: None,
+            is_approved: None,
+            valid_from: None,
+            max_executions: None,
+            single_use: None,
+            usd_limit: Some(Some(UsdLimit { price_feed, max_usd: 1_000_000_000 })),
+            auto_limit: Some(AUTO_LIMIT),
+            cosign_limit: Some(COSIGN_LIMIT),
+        };
+        let metas = [
+            AccountMeta::new(owner, true),
+            AccountMeta::new_readonly(wallet, false),
+            AccountMeta::new_readonly(dapp, false),
+            AccountMeta::new(approval, false),
+            AccountMeta::new_readonly(system_program::ID, false),
+        ];
+        runtime.process(&metas, &limits.pack()).unwrap();
+
+        let (stake_account, _) = derive_stake_address(&wallet, 1, &program_id);
+        let (cpi_approval, _) = derive_cpi_approval_address(&wallet, &dapp, &spl_token::id(), &program_id);
+        let keys = BTreeMap::from([
+            ("owner", owner),
+            ("wallet", wallet),
+            ("dapp", dapp),
+            ("approval", approval),
+            ("token_mint", mint),
+            ("wallet_token_account", wallet_token),
+            ("token_program", spl_token::id()),
+            ("recipient", recipient),
+            ("price_feed", price_feed),
+            ("system_program", system_program::ID),
+            ("standing_order", derive_standing_order_address(&wallet, 1, &program_id).0),
+            ("cranker", Pubkey::new_unique()),
+            ("target_program", spl_token::id()),
+            ("cpi_approval", cpi_approval),
+            ("instructions_sysvar", sysvar::instructions::ID),
+            ("relayer", Pubkey::new_unique()),
+            ("stake_account", stake_account),
+            ("stake_program", stake::program::id()),
+            ("rent_sysvar", sysvar::rent::ID),
+            ("clock_sysvar", sysvar::clock::ID),
+            ("stake_history_sysvar", sysvar::stake_history::ID),
+            ("stake_config", solana_program::pubkey!("StakeConfig11111111111111111111111111111111")),
+            ("vote_account", Pubkey::new_unique()),
+        ]);
+        Fixture { runtime, keys }
+    }
+
+    fn key(&self, name: &str) -> Pubkey {
+        *self.keys.get(name).unwrap_or_else(|| panic!("no account named {}", name))
+    }
+
+    /// Runs `instruction` with the accounts the IDL lists for it, looked up by name and
+    /// flagged as the IDL says, followed by `remaining`
+    fn run(&mut self, instruction: &WalletInstruction, remaining: &[AccountMeta]) -> Result<(), ProgramError> {
+        let data = instruction.pack();
+        let idl = idl();
+        let name = idl["instructions"][data[1] as usize]["name"].as_str().unwrap();
+        let mut metas: Vec<_> = instruction_accounts(name)
+            .unwrap()
+            .accounts
+            .iter()
+            .filter(|account| account.when.is_none_or(|when| ALL_CONDITIONS.contains(&when)))
+            .map(|account| AccountMeta {
+                pubkey: self.key(account.name),
+                is_signer: account.signer,
+                is_writable: account.writable,
+            })
+            .collect();
+        metas.extend_from_slice(remaining);
+        self.runtime.process(&metas, &data)
+    }
+
+    /// Puts an ed25519 instruction verifying the owner's signature over `message` before
+    /// the next instruction
+    fn sign(&mut self, message: &[u8]) {
+        let signature = client::ed25519_signature(&self.key("owner"), &[7; 64], message);
+        let next = Instruction::new_with_bytes(self.runtime.program_id, &[], vec![]);
+        self.runtime.set_instructions(&[signature, next], 1);
+    }
+}
+
+/// Runs `instruction` in a fixture prepared for it, with the accounts taken from the IDL
+fn run_happy_path(instruction: &WalletInstruction) -> Result<(), ProgramError> {
+    let mut fixture = Fixture::new();
+    let program_id = fixture.runtime.program_id;
+    let token_order = WalletInstruction::CreateStandingOrder { order_id: 1, amount: 100, interval: 60, start_time: NOW };
+    let proposal = WalletInstruction::ProposeExecution { amount: COSIGN_LIMIT, request_id: [1; 32] };
+    let new_stake = WalletInstruction::CreateStake { stake_id: 1, amount: LAMPORTS_PER_SOL / 2 };
+    let mut remaining = vec![];
+
+    match instruction {
+        WalletInstruction::CreateWallet => {
+            let owner = Pubkey::new_unique();
+            fixture.runtime.fund(&owner, LAMPORTS_PER_SOL);
+            fixture.keys.insert("owner", owner);
+            fixture.keys.insert("wallet", derive_wallet_address(&owner, &program_id).0);
+        }
+        WalletInstruction::ApproveDapp { .. } => {
+            fixture.keys.insert("dapp", Pubkey::new_unique());
+            let (approval, _) = derive_approval_address(
+                &fixture.key("wallet"),
+                &fixture.key("dapp"),
+                &fixture.key("token_mint"),
+                &program_id,
+            );
+            fixture.keys.insert("approval", approval);
+        }
+        WalletInstruction::CrankStandingOrder | WalletInstruction::CancelStandingOrder => fixture.run(&token_order, &[])?,
+        WalletInstruction::ExecuteBatch { .. } => remaining.push(AccountMeta::new(fixture.key("recipient"), false)),
+        WalletInstruction::ExecuteCpi { .. } => {
+            fixture.run(&WalletInstruction::ApproveCpi { max_amount: 100, expiry: NOW + 60 }, &[])?;
+            fixture.keys.insert("authority", fixture.key("dapp"));
+            let transfer = spl_token::instruction::transfer(
+                &spl_token::id(),
+                &fixture.key("wallet_token_account"),
+                &fixture.key("recipient"),
+                &fixture.key("wallet"),
+                &[],
+                100,
+            )
+            .unwrap();
+            remaining = transfer.accounts.into_iter().map(|meta| AccountMeta { is_signer: false, ..meta }).collect();
+        }
+        WalletInstruction::WrapSol { .. } | WalletInstruction::UnwrapSol => {
+            let native_mint = fixture.runtime.add_native_mint(&spl_token::id());
+            let wallet_token =
+                spl_associated_token_account::get_associated_token_address(&fixture.key("wallet"), &native_mint);
+            if let WalletInstruction::UnwrapSol = instruction {
+                fixture.runtime.add_token_account(&spl_token::id(), &native_mint, &fixture.key("wallet"), 100);
+            }
+            fixture.keys.insert("native_mint", native_mint);
+            fixture.keys.insert("wallet_token_account", wallet_token);
+            fixture.keys.insert("associated_token_program", spl_associated_token_account::id());
+        }
+        WalletInstruction::ExecuteTransactionOnce { request_id, .. } => {
+            let receipt = derive_receipt_address(&fixture.key("approval"), request_id, &program_id).0;
+            fixture.keys.insert("receipt", receipt);
+        }
+        WalletInstruction::ApproveDappWithPermit { max_amount, expiry, nonce } => {
+            fixture.keys.insert("dapp", Pubkey::new_unique());
+            fixture.runtime.fund(&fixture.key("dapp"), LAMPORTS_PER_SOL);
+            let (approval, _) = derive_approval_address(
+                &fixture.key("wallet"),
+                &fixture.key("dapp"),
+                &fixture.key("token_mint"),
+                &program_id,
+            );
+            fixture.keys.insert("approval", approval);
+            fixture.keys.insert("used_permit", derive_permit_address(&fixture.key("wallet"), *nonce, &program_id).0);
+            let permit = ApprovalPermit {
+                wallet: fixture.key("wallet"),
+                dapp: fixture.key("dapp"),
+                token_mint: fixture.key("token_mint"),
+                max_amount: *max_amount,
+                expiry: *expiry,
+                nonce: *nonce,
+            };
+            fixture.sign(&permit.message());
+        }
+        WalletInstruction::ExecuteIntent { amount, nonce, deadline, .. } => {
+            let intent = TransferIntent {
+                wallet: fixture.key("wallet"),
+                recipient: fixture.key("recipient"),
+                token_mint: fixture.key("token_mint"),
+                amount: *amount,
+                nonce: *nonce,
+                deadline: *deadline,
+            };
+            fixture.sign(&intent.message());
+        }
+        WalletInstruction::MigrateAccount { .. } => {
+            fixture.keys.insert("payer", fixture.key("owner"));
+            fixture.keys.insert("account", fixture.key("approval"));
+        }
+        WalletInstruction::ProposeExecution { request_id, .. } => {
+            let pending = derive_pending_execution_address(&fixture.key("approval"), request_id, &program_id).0;
+            fixture.keys.insert("pending_execution", pending);
+        }
+        WalletInstruction::ConfirmExecution | WalletInstruction::CancelExecution => {
+            let pending = derive_pending_execution_address(&fixture.key("approval"), &[1; 32], &program_id).0;
+            fixture.keys.insert("pending_execution", pending);
+            fixture.keys.insert("authority", fixture.key("owner"));
+            fixture.run(&proposal, &[])?;
+        }
+        WalletInstruction::DelegateStake { .. } => fixture.run(&new_stake, &[])?,
+        WalletInstruction::DeactivateStake { .. } | WalletInstruction::WithdrawStake { .. } => {
+            fixture.run(&new_stake, &[])?;
+            fixture.run(&WalletInstruction::DelegateStake { stake_id: 1 }, &[])?;
+            if let WalletInstruction::WithdrawStake { .. } = instruction {
+                fixture.run(&WalletInstruction::DeactivateStake { stake_id: 1 }, &[])?;
+            }
+        }
+        WalletInstruction::CancelPermit { nonce } => {
+            fixture.keys.insert("used_permit", derive_permit_address(&fixture.key("wallet"), *nonce, &program_id).0);
+        }
+        _ => {}
+    }
+
+    fixture.run(instruction, &remaining)
+}
+
+/// Instructions that succeed with every optional account of the IDL present
+fn happy_path_instructions() -> Vec<WalletInstruction> {
+    vec![
+        WalletInstruction::CreateWallet,
+        WalletInstruction::ApproveDapp { max_amount: 1, expiry: NOW + 60 },
+        WalletInstruction::ExecuteTransaction { amount: 100, transfer_type: TransferType::Token },
+        WalletInstruction::Withdraw { amount: 100, transfer_type: TransferType::Token },
+        WalletInstruction::CreateStandingOrder { order_id: 1, amount: 100, interval: 60, start_time: NOW },
+        WalletInstruction::CrankStandingOrder,
+        WalletInstruction::CancelStandingOrder,
+        WalletInstruction::ExecuteBatch { transfers: vec![(0, 100)], transfer_type: TransferType::Token },
+        WalletInstruction::ApproveCpi { max_amount: 1, expiry: NOW + 60 },
+        WalletInstruction::ExecuteCpi { instruction_data: spl_token::instruction::TokenInstruction::Transfer { amount: 100 }.pack() },
+        WalletInstruction::WrapSol { amount: 100 },
+        WalletInstruction::UnwrapSol,
+        WalletInstruction::ExecuteTransactionOnce { amount: 100, transfer_type: TransferType::Token, request_id: [1; 32] },
+        WalletInstruction::ApproveDappWithPermit { max_amount: 1, expiry: NOW + 60, nonce: 1 },
+        WalletInstruction::ExecuteIntent { amount: 100, transfer_type: TransferType::Token, nonce: 0, deadline: NOW + 60 },
+        WalletInstruction::UpdateApproval {
+            max_amount: Some(2_000_000),
+            expiry: None,
+            recipients: None,
+            is_approved: None,
+            valid_from: None,
+            max_executions: None,
+            single_use: None,
+            usd_limit: None,
+            auto_limit: None,
+            cosign_limit: None,
+        },
+        WalletInstruction::MigrateAccount { kind: AccountKind::DAppApproval },
+        WalletInstruction::ProposeExecution { amount: COSIGN_LIMIT, request_id: [2; 32] },
+        WalletInstruction::ConfirmExecution,
+        WalletInstruction::CancelExecution,
+        WalletInstruction::CreateStake { stake_id: 1, amount: LAMPORTS_PER_SOL / 2 },
+        WalletInstruction::DelegateStake { stake_id: 1 },
+        WalletInstruction::DeactivateStake { stake_id: 1 },
+        WalletInstruction::WithdrawStake { stake_id: 1, amount: LAMPORTS_PER_SOL / 2 },
+        WalletInstruction::CancelPermit { nonce: 1 },
+    ]
+}
+
+#[test]
+fn processor_reads_the_listed_accounts() {
+    let program_id = Pubkey::new_unique();
+
+    for (entry, instruction) in idl()["instructions"].as_array().unwrap().iter().zip(sample_instructions()) {
+        let name = entry["name"].as_str().unwrap();
+        let required = required_accounts(name);
+
+        // The processor reads at least the leading unconditional accounts
+        assert_eq!(
+            run(&program_id, &instruction, name, required - 1, true),
+            Err(ProgramError::NotEnoughAccountKeys),
+            "{} should need {} accounts",
+            name,
+            required
+        );
+
+        // and checks a signer among them before touching any state
+        assert_eq!(
+            run(&program_id, &instruction, name, required, false),
+            Err(ProgramError::MissingRequiredSignature),
+            "{} should require a signer",
+            name
+        );
+    }
+
+    // Every account in the order and with the flags the IDL lists them in, including all the
+    // optional ones, makes a successful call. The runtime rejects writes to accounts the IDL
+    // lists as read-only, and a different order puts the wrong account where one is checked
+    let happy_paths = happy_path_instructions();
+    assert_eq!(happy_paths.len(), sample_instructions().len());
+    for (entry, instruction) in idl()["instructions"].as_array().unwrap().iter().zip(&happy_paths) {
+        assert_eq!(run_happy_path(instruction), Ok(()), "{} with the IDL's accounts", entry["name"]);
+    }
+}
+
+/// Checks that `instruction` passes the IDL's accounts of `name`, with the conditional ones
+/// whose condition is in `conditions`
+fn check_builder(name: &str, instruction: Instruction, conditions: &[&str]) {
+    let expected: Vec<_> = instruction_accounts(name)
+        .unwrap()
+        .accounts
+        .into_iter()
+        .filter(|account| account.when.is_none_or(|when| conditions.contains(&when)))
+        .collect();
+    assert_eq!(instruction.accounts.len(), expected.len(), "{} builder passes the wrong number of accounts", name);
+    for (meta, account) in instruction.accounts.iter().zip(&expected) {
+        assert_eq!(
+            (meta.is_signer, meta.is_writable),
+            (account.signer, account.writable),
+            "{} builder flags {} differently",
+            name,
+            account.name
+        );
+    }
+    assert!(WalletInstruction::unpack(&instruction.data).is_ok());
+}
+
+#[test]
+fn client_builders_match_the_idl() {
+    let program_id = Pubkey::new_unique();
+    let (owner, dapp, recipient, price_feed) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    let token = TokenTransfer {
+        mint: Pubkey::new_unique(),
+        wallet_token_account: Pubkey::new_unique(),
+        token_program: spl_token_2022::id(),
+    };
+    let usd_limit = "the approval has a USD limit";
+    let token_transfer = "transfer_type is Token";
+
+    check_builder("CreateWallet", client::create_wallet(&program_id, &owner), &[]);
+    check_builder("ApproveDapp", client::approve_dapp(&program_id, &owner, &dapp, &token.mint, 1, 1), &[]);
+    check_builder("UpdateApproval", client::set_approval_status(&program_id, &owner, &dapp, &token.mint, false), &[]);
+    check_builder("CancelPermit", client::cancel_permit(&program_id, &owner, 1), &[]);
+    check_builder(
+        "ExecuteTransaction",
+        client::execute_transaction(&program_id, &dapp, &owner, &recipient, 1, None, None),
+        &[],
+    );
+    check_builder(
+        "ExecuteTransaction",
+        client::execute_transaction(&program_id, &dapp, &owner, &recipient, 1, Some(&price_feed), Some(&token)),
+        &[usd_limit, token_transfer],
+    );
+    check_builder("Withdraw", client::withdraw(&program_id, &owner, &recipient, 1, None), &[]);
+    check_builder("Withdraw", client::withdraw(&program_id, &owner, &recipient, 1, Some(&token)), &[token_transfer]);
+}
+
+#[test]
+fn idl_lists_every_error_the_program_returns() {
+    let sources = [
+        include_str!("../src/lib.rs"),
+        include_str!("../src/processor.rs"),
+        include_str!("../src/instruction.rs"),
+        include_str!("../src/state.rs"),
+        include_str!("../src/permit.rs"),
+        include_str!("../src/price.rs"),
+        include_str!("../src/event.rs"),
+    ];
+    let idl = idl();
+    let listed: BTreeSet<&str> = idl["errors"].as_array().unwrap().iter().map(|error| error["name"].as_str().unwrap()).collect();
+
+    // Custom codes only come from `WalletErrorCode`, so `ProgramError::Custom` is not listed
+    for source in sources {
+        for prefix in ["ProgramError::", "WalletErrorCode::"] {
+            for (index, _) in source.match_indices(prefix) {
+                let name: String = source[index + prefix.len()..]
+                    .chars()
+                    .take_while(|c| c.is_ascii_alphanumeric())
+                    .collect();
+                assert!(listed.contains(name.as_str()), "{}{} is missing from the IDL errors", prefix, name);
+            }
+        }
+    }
+}
+
+#[test]
+fn idl_error_codes_match_the_program() {
+    let idl = idl();
+    let codes: Vec<(&str, u64)> = idl["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|error| (error["name"].as_str().unwrap(), error["code"].as_u64().unwrap()))
+        .collect();
+
+    let expected: Vec<(String, u64)> = WalletErrorCode::ALL
+        .into_iter()
+        .map(|error| (format!("{:?}", error), u64::from(error.code())))
+        .chain(program_errors().into_iter().map(|error| (format!("{:?}", error), u64::from(error))))
+        .collect();
+    assert_eq!(codes, expected.iter().map(|(name, code)| (name.as_str(), *code)).collect::<Vec<_>>());
+
+    for error in WalletErrorCode::ALL {
+        assert_eq!(ProgramError::from(error), ProgramError::Custom(error.code()));
+        assert_eq!(WalletErrorCode::from_code(error.code()), Some(error));
+    }
+}
+
+#[test]
+fn defined_types_are_listed() {
+    let idl = idl();
+    let types: BTreeSet<&str> = idl["types"].as_array().unwrap().iter().map(|entry| entry["name"].as_str().unwrap()).collect();
+
+    fn check(value: &Value, types: &BTreeSet<&str>) {
+        match value {
+            Value::Object(map) => {
+                if let Some(Value::String(name)) = map.get("defined") {
+                    assert!(types.contains(name.as_str()), "type {} is missing from the IDL", name);
+                }
+                map.values().for_each(|value| check(value, types));
+            }
+            Value::Array(values) => values.iter().for_each(|value| check(value, types)),
+            _ => {}
+        }
+    }
+    check(&idl, &types);
+}
+
+#[test]
+fn idl_file_is_up_to_date() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("idl.json");
+    let generated = serde_json::to_string_pretty(&idl()).unwrap() + "\n";
+
+    if std::env::var_os("UPDATE_IDL").is_some() {
+        fs::write(&path, &generated).unwrap();
+    }
+    let checked_in = fs::read_to_string(&path).unwrap_or_default();
+    assert!(checked_in == generated, "idl.json is out of date; run `UPDATE_IDL=1 cargo test --test idl`");
+}
@@ -0,0 +1,158 @@
+//! `ExecuteIntent`: transfers the owner signed off-chain, submitted by any relayer
+
+mod common;
+
+use smart_contract::{
+    client::ed25519_signature,
+    event::WalletEvent,
+    instruction::{TransferType, WalletInstruction},
+    permit::TransferIntent,
+    state::WalletState,
+};
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    native_token::LAMPORTS_PER_SOL,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    system_program, sysvar,
+};
+
+use crate::common::{take_events, Runtime, NOW, TRANSACTION_FEE};
+
+const SIGNATURE: [u8; 64] = [7; 64];
+const AMOUNT: u64 = LAMPORTS_PER_SOL / 10;
+
+struct Setup {
+    owner: Pubkey,
+    wallet: Pubkey,
+    relayer: Pubkey,
+}
+
+fn setup(runtime: &mut Runtime) -> Setup {
+    let (owner, wallet) = runtime.create_wallet(LAMPORTS_PER_SOL);
+    Setup { owner, wallet, relayer: Pubkey::new_unique() }
+}
+
+fn sol_intent(setup: &Setup, recipient: &Pubkey, nonce: u64, deadline: i64) -> TransferIntent {
+    TransferIntent { wallet: setup.wallet, recipient: *recipient, token_mint: Pubkey::default(), amount: AMOUNT, nonce, deadline }
+}
+
+/// Relays `intent` right after an ed25519 instruction verifying `signer`'s signature over
+/// `signed`, with `token_accounts` appended for token intents
+fn relay(
+    runtime: &mut Runtime,
+    setup: &Setup,
+    intent: &TransferIntent,
+    signer: &Pubkey,
+    signed: &TransferIntent,
+    token_accounts: &[AccountMeta],
+) -> Result<(), ProgramError> {
+    let transfer_type = if token_accounts.is_empty() { TransferType::Sol } else { TransferType::Token };
+    let mut accounts = vec![
+        AccountMeta::new(setup.relayer, true),
+        AccountMeta::new_readonly(setup.owner, false),
+        AccountMeta::new(setup.wallet, false),
+        AccountMeta::new(intent.recipient, false),
+        AccountMeta::new_readonly(system_program::ID, false),
+        AccountMeta::new_readonly(sysvar::instructions::ID, false),
+    ];
+    accounts.extend_from_slice(token_accounts);
+    let instruction = Instruction::new_with_bytes(
+        runtime.program_id,
+        &WalletInstruction::ExecuteIntent { amount: intent.amount, transfer_type, nonce: intent.nonce, deadline: intent.deadline }.pack(),
+        accounts,
+    );
+    runtime.set_instructions(&[ed25519_signature(signer, &SIGNATURE, &signed.message()), instruction.clone()], 1);
+    runtime.execute(&instruction)
+}
+
+fn intent_nonce(runtime: &Runtime, setup: &Setup) -> u64 {
+    let state: WalletState = runtime.state(&setup.wallet);
+    u64::from(state.intent_nonce)
+}
+
+#[test]
+fn intents_pay_the_recipient_and_reimburse_the_relayer() {
+    let mut runtime = Runtime::new();
+    let setup = setup(&mut runtime);
+    let recipient = Pubkey::new_unique();
+    let wallet_before = runtime.lamports(&setup.wallet);
+
+    let intent = sol_intent(&setup, &recipient, 0, NOW + 60);
+    relay(&mut runtime, &setup, &intent, &setup.owner, &intent, &[]).unwrap();
+    assert_eq!(
+        take_events(),
+        [WalletEvent::IntentExecuted { wallet: setup.wallet, recipient, token_mint: Pubkey::default(), amount: AMOUNT, nonce: 0 }]
+    );
+    assert_eq!(runtime.lamports(&recipient), AMOUNT);
+    assert_eq!(runtime.lamports(&setup.relayer), TRANSACTION_FEE);
+    assert_eq!(runtime.lamports(&setup.wallet), wallet_before - AMOUNT - TRANSACTION_FEE);
+    assert_eq!(intent_nonce(&runtime, &setup), 1);
+}
+
+#[test]
+fn intents_execute_once_and_in_nonce_order() {
+    let mut runtime = Runtime::new();
+    let setup = setup(&mut runtime);
+    let recipient = Pubkey::new_unique();
+
+    let skipped = sol_intent(&setup, &recipient, 1, NOW + 60);
+    assert_eq!(relay(&mut runtime, &setup, &skipped, &setup.owner, &skipped, &[]), Err(ProgramError::InvalidArgument));
+
+    let first = sol_intent(&setup, &recipient, 0, NOW + 60);
+    relay(&mut runtime, &setup, &first, &setup.owner, &first, &[]).unwrap();
+    assert_eq!(relay(&mut runtime, &setup, &first, &setup.owner, &first, &[]), Err(ProgramError::InvalidArgument));
+    relay(&mut runtime, &setup, &skipped, &setup.owner, &skipped, &[]).unwrap();
+    assert_eq!(runtime.lamports(&recipient), 2 * AMOUNT);
+}
+
+#[test]
+fn intents_expire_at_their_deadline() {
+    let mut runtime = Runtime::new();
+    let setup = setup(&mut runtime);
+
+    // The same rule as a permit's expiry
+    let expired = sol_intent(&setup, &Pubkey::new_unique(), 0, NOW);
+    assert_eq!(relay(&mut runtime, &setup, &expired, &setup.owner, &expired, &[]), Err(ProgramError::InvalidArgument));
+    let valid = sol_intent(&setup, &Pubkey::new_unique(), 0, NOW + 1);
+    relay(&mut runtime, &setup, &valid, &setup.owner, &valid, &[]).unwrap();
+}
+
+#[test]
+fn intents_need_the_owners_signature_over_their_exact_terms() {
+    let mut runtime = Runtime::new();
+    let setup = setup(&mut runtime);
+    let intent = sol_intent(&setup, &Pubkey::new_unique(), 0, NOW + 60);
+
+    assert_eq!(
+        relay(&mut runtime, &setup, &intent, &Pubkey::new_unique(), &intent, &[]),
+        Err(ProgramError::MissingRequiredSignature)
+    );
+    let redirected = sol_intent(&setup, &Pubkey::new_unique(), 0, NOW + 60);
+    assert_eq!(relay(&mut runtime, &setup, &redirected, &setup.owner, &intent, &[]), Err(ProgramError::InvalidInstructionData));
+    assert_eq!(intent_nonce(&runtime, &setup), 0);
+}
+
+#[test]
+fn token_intents_sign_over_the_mint() {
+    let mut runtime = Runtime::new();
+    let setup = setup(&mut runtime);
+    let mint = runtime.add_mint(&spl_token::id(), 6);
+    let wallet_token = runtime.add_token_account(&spl_token::id(), &mint, &setup.wallet, 1000);
+    let recipient = runtime.add_token_account(&spl_token::id(), &mint, &Pubkey::new_unique(), 0);
+    let token_accounts = [
+        AccountMeta::new_readonly(mint, false),
+        AccountMeta::new(wallet_token, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+    ];
+    let intent = TransferIntent { wallet: setup.wallet, recipient, token_mint: mint, amount: 400, nonce: 0, deadline: NOW + 60 };
+
+    // A signature over a SOL intent doesn't cover tokens
+    let sol = TransferIntent { token_mint: Pubkey::default(), ..intent };
+    assert_eq!(relay(&mut runtime, &setup, &intent, &setup.owner, &sol, &token_accounts), Err(ProgramError::InvalidInstructionData));
+
+    let intent = TransferIntent { token_mint: mint, ..sol };
+    relay(&mut runtime, &setup, &intent, &setup.owner, &intent, &token_accounts).unwrap();
+    assert_eq!(runtime.token_balance(&recipient), 400);
+    assert_eq!(runtime.token_balance(&wallet_token), 600);
+}
@@ -0,0 +1,315 @@
+//! Property tests. Instruction decoding is fed arbitrary bytes, and random sequences of wallet
+//! operations, including arbitrary instructions over arbitrary account lists, run against an
+//! in-memory runtime that checks the wallet's invariants after every step.
+
+mod common;
+
+use proptest::{collection::vec, prelude::*};
+use smart_contract::{
+    instruction::{TransferType, WalletInstruction},
+    state::{derive_approval_address, derive_wallet_address, unpack_account_data, DAppApproval},
+};
+use solana_program::{instruction::AccountMeta, native_token::LAMPORTS_PER_SOL, pubkey::Pubkey, rent::Rent, system_program};
+
+use crate::common::{Account, Runtime, NOW, TRANSACTION_FEE};
+
+/// A wallet with two dApps, three recipients and all the accounts they use
+struct Scenario {
+    runtime: Runtime,
+    owner: Pubkey,
+    wallet: Pubkey,
+    dapps: [Pubkey; 2],
+    approvals: [Pubkey; 2],
+    recipients: [Pubkey; 3],
+}
+
+impl Scenario {
+    fn new() -> Self {
+        let mut runtime = Runtime::new();
+        let program_id = runtime.program_id;
+        let owner = Pubkey::new_unique();
+        let (wallet, _) = derive_wallet_address(&owner, &program_id);
+        let dapps = [Pubkey::new_unique(), Pubkey::new_unique()];
+        let approvals = dapps.map(|dapp| derive_approval_address(&wallet, &dapp, &Pubkey::default(), &program_id).0);
+        let recipients = [Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique()];
+
+        let accounts = &mut runtime.accounts;
+        accounts.insert(owner, Account::system(20 * LAMPORTS_PER_SOL));
+        for key in [wallet].iter().chain(&approvals).chain(&recipients) {
+            accounts.insert(*key, Account::system(0));
+        }
+        for dapp in dapps {
+            accounts.insert(dapp, Account::system(LAMPORTS_PER_SOL));
+        }
+
+        let mut scenario = Scenario {
+            runtime,
+            owner,
+            wallet,
+            dapps,
+            approvals,
+            recipients,
+        };
+        let metas = [
+            AccountMeta::new(owner, true),
+            AccountMeta::new(wallet, false),
+            AccountMeta::new_readonly(system_program::ID, false),
+        ];
+        scenario.runtime.process(&metas, &WalletInstruction::CreateWallet.pack()).unwrap();
+        scenario
+    }
+
+    /// Every account of the scenario, which `Op::Raw` picks its accounts from
+    fn pool(&self) -> Vec<Pubkey> {
+        let mut pool = vec![self.owner, self.wallet];
+        pool.extend(self.dapps.iter().chain(&self.approvals).chain(&self.recipients));
+        pool.push(system_program::ID);
+        pool
+    }
+
+    fn is_keypair(&self, key: &Pubkey) -> bool {
+        *key == self.owner || self.dapps.contains(key) || self.recipients.contains(key)
+    }
+
+    fn lamports(&self, key: &Pubkey) -> u64 {
+        self.runtime.accounts[key].lamports
+    }
+
+    fn approval(&self, dapp: usize) -> Option<DAppApproval> {
+        let account = &self.runtime.accounts[&self.approvals[dapp]];
+        if account.owner != self.runtime.program_id {
+            return None;
+        }
+        unpack_account_data(&account.data).ok()
+    }
+
+    fn spendable(&self) -> u64 {
+        let wallet = &self.runtime.accounts[&self.wallet];
+        wallet.lamports.saturating_sub(Rent::default().minimum_balance(wallet.data.len()))
+    }
+
+    fn check_invariants(&self, approvals_before: &[Option<DAppApproval>; 2]) {
+        let wallet = &self.runtime.accounts[&self.wallet];
+        assert_eq!(wallet.owner, self.runtime.program_id, "wallet was closed or reassigned");
+        assert!(
+            wallet.lamports >= Rent::default().minimum_balance(wallet.data.len()),
+            "wallet fell below rent exemption: {} lamports",
+            wallet.lamports
+        );
+
+        // A single instruction never spends more than the approval's limit
+        for (dapp, before) in approvals_before.iter().enumerate() {
+            if let (Some(before), Some(after)) = (before, self.approval(dapp)) {
+                let spent = u64::from(after.spent_amount).saturating_sub(u64::from(before.spent_amount));
+                assert!(
+                    spent <= u64::from(before.max_amount),
+                    "approval spent {} in one instruction, above its limit of {}",
+                    spent,
+                    u64::from(before.max_amount)
+                );
+            }
+        }
+    }
+}
+
+/// An approval with none of the optional restrictions, whose outcome the tests can predict
+fn is_plain(approval: &DAppApproval) -> bool {
+    bool::from(approval.is_approved)
+        && NOW < i64::from(approval.expiry)
+        && i64::from(approval.valid_from) <= NOW
+        && u64::from(approval.max_executions) == 0
+        && !bool::from(approval.single_use)
+        && approval.usd_limit().is_none()
+        && u64::from(approval.cosign_limit) == 0
+        && approval.recipients().is_empty()
+}
+
+#[derive(Clone, Debug)]
+enum Op {
+    /// The owner sends lamports to the wallet
+    Deposit { amount: u64 },
+    Approve { dapp: usize, max_amount: u64, expires_in: i64 },
+    Execute { dapp: usize, recipient: usize, amount: u64, signed: bool },
+    Batch { dapp: usize, transfers: Vec<(u8, u64)> },
+    Withdraw { recipient: usize, amount: u64, signed: bool },
+    /// Any instruction with arbitrary fields, over accounts picked from the scenario
+    Raw { tag: u8, fields: Vec<u8>, accounts: Vec<(usize, bool, bool)> },
+}
+
+fn amount() -> impl Strategy<Value = u64> {
+    prop_oneof![
+        4 => 0..=2 * LAMPORTS_PER_SOL,
+        1 => Just(u64::MAX),
+        1 => u64::MAX - TRANSACTION_FEE..=u64::MAX,
+    ]
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        amount().prop_map(|amount| Op::Deposit { amount }),
+        (0..2usize, amount(), -100..1_000i64)
+            .prop_map(|(dapp, max_amount, expires_in)| Op::Approve { dapp, max_amount, expires_in }),
+        (0..2usize, 0..3usize, amount(), any::<bool>())
+            .prop_map(|(dapp, recipient, amount, signed)| Op::Execute { dapp, recipient, amount, signed }),
+        (0..2usize, vec((0..4u8, amount()), 0..4)).prop_map(|(dapp, transfers)| Op::Batch { dapp, transfers }),
+        (0..3usize, amount(), any::<bool>())
+            .prop_map(|(recipient, amount, signed)| Op::Withdraw { recipient, amount, signed }),
+        (0..24u8, vec(any::<u8>(), 0..96), vec((0..10usize, any::<bool>(), any::<bool>()), 0..10))
+            .prop_map(|(tag, fields, accounts)| Op::Raw { tag, fields, accounts }),
+    ]
+}
+
+fn apply(scenario: &mut Scenario, op: &Op) {
+    let approvals_before = [scenario.approval(0), scenario.approval(1)];
+    let (owner, wallet) = (scenario.owner, scenario.wallet);
+
+    match *op {
+        Op::Deposit { amount } => {
+            if scenario.lamports(&owner) >= amount {
+                scenario.runtime.accounts.get_mut(&owner).unwrap().lamports -= amount;
+                scenario.runtime.accounts.get_mut(&wallet).unwrap().lamports += amount;
+            }
+        }
+        Op::Approve { dapp, max_amount, expires_in } => {
+            let metas = [
+                AccountMeta::new(owner, true),
+                AccountMeta::new_readonly(wallet, false),
+                AccountMeta::new_readonly(scenario.dapps[dapp], false),
+                AccountMeta::new_readonly(Pubkey::default(), false),
+                AccountMeta::new(scenario.approvals[dapp], false),
+                AccountMeta::new_readonly(system_program::ID, false),
+            ];
+            let instruction = WalletInstruction::ApproveDapp { max_amount, expiry: NOW + expires_in };
+            if scenario.runtime.process(&metas, &instruction.pack()).is_ok() {
+                let approval = scenario.approval(dapp).unwrap();
+                assert_eq!(u64::from(approval.max_amount), max_amount);
+                assert_eq!(u64::from(approval.spent_amount), 0);
+            }
+        }
+        Op::Execute { dapp, recipient, amount, signed } => {
+            let (dapp_key, recipient_key) = (scenario.dapps[dapp], scenario.recipients[recipient]);
+            let metas = [
+                AccountMeta::new(dapp_key, signed),
+                AccountMeta::new(owner, false),
+                AccountMeta::new(wallet, false),
+                AccountMeta::new(scenario.approvals[dapp], false),
+                AccountMeta::new(recipient_key, false),
+                AccountMeta::new_readonly(system_program::ID, false),
+            ];
+            let approval = approvals_before[dapp];
+            let wallet_before = scenario.lamports(&wallet);
+            let recipient_before = scenario.lamports(&recipient_key);
+            let spendable = scenario.spendable();
+
+            let instruction = WalletInstruction::ExecuteTransaction { amount, transfer_type: TransferType::Sol };
+            let result = scenario.runtime.process(&metas, &instruction.pack());
+
+            if result.is_ok() {
+                let approval = approval.expect("executed without an approval");
+                assert!(signed, "executed without the dApp's signature");
+                assert!(amount <= u64::from(approval.max_amount));
+                assert_eq!(scenario.lamports(&wallet), wallet_before - amount - TRANSACTION_FEE);
+                assert_eq!(scenario.lamports(&recipient_key), recipient_before + amount);
+                assert_eq!(
+                    u64::from(scenario.approval(dapp).unwrap().spent_amount),
+                    u64::from(approval.spent_amount) + amount
+                );
+            } else if let Some(approval) = approval.filter(is_plain) {
+                let affordable = amount.checked_add(TRANSACTION_FEE).is_some_and(|outflow| outflow <= spendable);
+                assert!(
+                    !signed || amount > u64::from(approval.max_amount) || !affordable,
+                    "valid execution failed: {:?}",
+                    result
+                );
+            }
+        }
+        Op::Batch { dapp, ref transfers } => {
+            let mut metas = vec![
+                AccountMeta::new(scenario.dapps[dapp], true),
+                AccountMeta::new(owner, false),
+                AccountMeta::new(wallet, false),
+                AccountMeta::new(scenario.approvals[dapp], false),
+                AccountMeta::new_readonly(system_program::ID, false),
+            ];
+            metas.extend(scenario.recipients.iter().map(|recipient| AccountMeta::new(*recipient, false)));
+
+            let instruction = WalletInstruction::ExecuteBatch {
+                transfers: transfers.clone(),
+                transfer_type: TransferType::Sol,
+            };
+            if scenario.runtime.process(&metas, &instruction.pack()).is_ok() {
+                let approval = approvals_before[dapp].expect("executed without an approval");
+                let total: u64 = transfers.iter().map(|(_, amount)| amount).sum();
+                assert!(total <= u64::from(approval.max_amount));
+            }
+        }
+        Op::Withdraw { recipient, amount, signed } => {
+            let recipient_key = scenario.recipients[recipient];
+            let metas = [
+                AccountMeta::new(owner, signed),
+                AccountMeta::new(wallet, false),
+                AccountMeta::new(recipient_key, false),
+                AccountMeta::new_readonly(system_program::ID, false),
+            ];
+            let spendable = scenario.spendable();
+            let recipient_before = scenario.lamports(&recipient_key);
+
+            let instruction = WalletInstruction::Withdraw { amount, transfer_type: TransferType::Sol };
+            let result = scenario.runtime.process(&metas, &instruction.pack());
+
+            assert_eq!(result.is_ok(), signed && amount <= spendable, "withdraw result: {:?}", result);
+            if result.is_ok() {
+                assert_eq!(scenario.lamports(&recipient_key), recipient_before + amount);
+            }
+        }
+        Op::Raw { tag, ref fields, ref accounts } => {
+            let pool = scenario.pool();
+            let metas: Vec<AccountMeta> = accounts
+                .iter()
+                .map(|&(index, signer, writable)| {
+                    let key = pool[index];
+                    // Only accounts with a private key can sign a transaction
+                    let signer = signer && scenario.is_keypair(&key);
+                    if writable {
+                        AccountMeta::new(key, signer)
+                    } else {
+                        AccountMeta::new_readonly(key, signer)
+                    }
+                })
+                .collect();
+            let mut data = WalletInstruction::CreateWallet.pack();
+            data[1] = tag;
+            data.extend_from_slice(fields);
+            let _ = scenario.runtime.process(&metas, &data);
+        }
+    }
+
+    scenario.check_invariants(&approvals_before);
+}
+
+proptest! {
+    #[test]
+    fn unpack_accepts_only_canonical_encodings(data in vec(any::<u8>(), 0..128)) {
+        if let Ok(instruction) = WalletInstruction::unpack(&data) {
+            prop_assert_eq!(instruction.pack(), data);
+        }
+    }
+
+    #[test]
+    fn unpack_handles_any_instruction_fields(tag in 0..32u8, fields in vec(any::<u8>(), 0..128)) {
+        let mut data = WalletInstruction::CreateWallet.pack();
+        data[1] = tag;
+        data.extend_from_slice(&fields);
+        if let Ok(instruction) = WalletInstruction::unpack(&data) {
+            prop_assert_eq!(instruction.pack(), data);
+        }
+    }
+
+    #[test]
+    fn wallet_invariants_hold(ops in vec(op(), 1..32)) {
+        let mut scenario = Scenario::new();
+        for op in &ops {
+            apply(&mut scenario, op);
+        }
+    }
+}
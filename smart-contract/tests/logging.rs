@@ -0,0 +1,66 @@
+//! Diagnostics logging: without `debug-logs` the hot-path instructions log nothing on success
+//! but their structured events. Run with and without `--features debug-logs`; the
+//! `compute-units` crate measures what the diagnostics cost on the SBF build.
+
+mod common;
+
+use smart_contract::{
+    client::{self, TokenTransfer},
+    event::WalletEvent,
+};
+use solana_program::{native_token::LAMPORTS_PER_SOL, pubkey::Pubkey};
+
+use crate::common::{take_events, take_logs, Runtime};
+
+fn check_diagnostics(instruction: &str) {
+    let logs = take_logs();
+    if cfg!(feature = "debug-logs") {
+        assert!(!logs.is_empty(), "{} should log diagnostics with debug-logs", instruction);
+    } else {
+        assert!(logs.is_empty(), "{} should not log on success without debug-logs: {:?}", instruction, logs);
+    }
+}
+
+#[test]
+fn withdrawals_log_their_event() {
+    let mut runtime = Runtime::new();
+    let program_id = runtime.program_id;
+    let (owner, wallet) = runtime.create_wallet(LAMPORTS_PER_SOL);
+    let mint = runtime.add_mint(&spl_token::id(), 6);
+    let wallet_token_account = runtime.add_token_account(&spl_token::id(), &mint, &wallet, 1000);
+    let token = TokenTransfer { mint, wallet_token_account, token_program: spl_token::id() };
+    let recipient = Pubkey::new_unique();
+    let recipient_token_account = runtime.add_token_account(&spl_token::id(), &mint, &recipient, 0);
+    take_logs();
+    take_events();
+
+    runtime.execute(&client::withdraw(&program_id, &owner, &recipient, 1000, None)).unwrap();
+    check_diagnostics("Withdraw");
+    let sol = Pubkey::default();
+    assert_eq!(take_events(), [WalletEvent::Withdrawn { wallet, recipient, token_mint: sol, amount: 1000 }]);
+
+    // The token program logs its own instructions, so only the event is checked here
+    runtime.execute(&client::withdraw(&program_id, &owner, &recipient_token_account, 400, Some(&token))).unwrap();
+    let recipient = recipient_token_account;
+    assert_eq!(take_events(), [WalletEvent::Withdrawn { wallet, recipient, token_mint: mint, amount: 400 }]);
+}
+
+#[test]
+fn executions_log_their_event() {
+    let mut runtime = Runtime::new();
+    let program_id = runtime.program_id;
+    let (owner, wallet) = runtime.create_wallet(LAMPORTS_PER_SOL);
+    let dapp = Pubkey::new_unique();
+    let approval = runtime.approve(&owner, &dapp, &Pubkey::default(), LAMPORTS_PER_SOL);
+    let recipient = Pubkey::new_unique();
+    take_logs();
+    take_events();
+
+    runtime.execute(&client::execute_transaction(&program_id, &dapp, &owner, &recipient, 1000, None, None)).unwrap();
+    check_diagnostics("ExecuteTransaction");
+    let token_mint = Pubkey::default();
+    assert_eq!(
+        take_events(),
+        [WalletEvent::TransferExecuted { approval, wallet, recipient, token_mint, amount: 1000, debited_amount: 1000 }]
+    );
+}
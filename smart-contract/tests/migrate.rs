@@ -0,0 +1,163 @@
+//! `MigrateAccount`: rewriting accounts created with an older layout in the current one
+
+mod common;
+
+use smart_contract::{
+    client,
+    instruction::WalletInstruction,
+    state::{
+        derive_approval_address, derive_wallet_address, AccountKind, DAppApproval, ProgramAccount, WalletState, ACCOUNT_HEADER_SIZE,
+        DAPP_APPROVAL_SIZE, WALLET_STATE_SIZE,
+    },
+};
+use solana_program::{
+    instruction::AccountMeta, native_token::LAMPORTS_PER_SOL, program_error::ProgramError, pubkey::Pubkey, system_program,
+};
+
+use crate::common::{Account, Runtime, NOW};
+
+const MAX_AMOUNT: u64 = 1_000_000;
+const EXPIRY: i64 = NOW + 3600;
+
+struct Approval {
+    owner: Pubkey,
+    wallet: Pubkey,
+    dapp: Pubkey,
+    address: Pubkey,
+    bump: u8,
+}
+
+/// A wallet and the address of a SOL approval for a new dApp, which the test writes itself
+fn approval(runtime: &mut Runtime) -> Approval {
+    let (owner, wallet) = runtime.create_wallet(LAMPORTS_PER_SOL);
+    let dapp = Pubkey::new_unique();
+    runtime.fund(&dapp, LAMPORTS_PER_SOL);
+    let (address, bump) = derive_approval_address(&wallet, &dapp, &Pubkey::default(), &runtime.program_id);
+    Approval { owner, wallet, dapp, address, bump }
+}
+
+/// The approval layout of the baseline program: `is_approved`, `max_amount`, `expiry` and
+/// `token_mint`, Borsh-encoded without a header
+fn baseline_approval(is_approved: bool) -> Vec<u8> {
+    let mut data = vec![is_approved as u8];
+    data.extend_from_slice(&MAX_AMOUNT.to_le_bytes());
+    data.extend_from_slice(&EXPIRY.to_le_bytes());
+    data.extend_from_slice(Pubkey::default().as_ref());
+    data
+}
+
+fn migrate(runtime: &mut Runtime, kind: AccountKind, account: &Pubkey, extra: &[Pubkey]) -> Result<(), ProgramError> {
+    let payer = Pubkey::new_unique();
+    runtime.fund(&payer, LAMPORTS_PER_SOL);
+    let mut metas = vec![
+        AccountMeta::new(payer, true),
+        AccountMeta::new(*account, false),
+        AccountMeta::new_readonly(system_program::ID, false),
+    ];
+    metas.extend(extra.iter().map(|key| AccountMeta::new_readonly(*key, false)));
+    runtime.process(&metas, &WalletInstruction::MigrateAccount { kind }.pack())
+}
+
+fn migrate_approval(runtime: &mut Runtime, approval: &Approval) -> Result<(), ProgramError> {
+    migrate(runtime, AccountKind::DAppApproval, &approval.address, &[approval.wallet, approval.dapp, approval.owner])
+}
+
+/// Checks the migrated account is in the current layout with the keys and bump filled in
+fn assert_current(runtime: &Runtime, approval: &Approval) -> DAppApproval {
+    let data = &runtime.accounts[&approval.address].data;
+    assert_eq!(data.len(), DAPP_APPROVAL_SIZE);
+    assert_eq!(data[..ACCOUNT_HEADER_SIZE], [AccountKind::DAppApproval as u8, DAppApproval::VERSION]);
+
+    let state: DAppApproval = runtime.state(&approval.address);
+    assert_eq!((state.wallet, state.dapp, state.owner), (approval.wallet, approval.dapp, approval.owner));
+    assert_eq!(state.bump, approval.bump);
+    assert_eq!(u64::from(state.max_amount), MAX_AMOUNT);
+    assert_eq!(i64::from(state.expiry), EXPIRY);
+    assert_eq!(state.token_mint, Pubkey::default());
+    state
+}
+
+fn pay(runtime: &mut Runtime, approval: &Approval, recipient: &Pubkey, amount: u64) -> Result<(), ProgramError> {
+    let instruction = client::execute_transaction(&runtime.program_id, &approval.dapp, &approval.owner, recipient, amount, None, None);
+    runtime.execute(&instruction)
+}
+
+#[test]
+fn baseline_approvals_migrate_to_the_current_layout() {
+    let mut runtime = Runtime::new();
+    let approval = approval(&mut runtime);
+    runtime.accounts.insert(approval.address, Account::with_data(runtime.program_id, baseline_approval(false)));
+
+    migrate_approval(&mut runtime, &approval).unwrap();
+    let state = assert_current(&runtime, &approval);
+    assert!(!bool::from(state.is_approved));
+    assert_eq!(u64::from(state.spent_amount), 0);
+    assert_eq!(state.recipients(), &[]);
+
+    // Migrating again leaves the account as it is
+    let migrated = runtime.accounts[&approval.address].clone();
+    migrate_approval(&mut runtime, &approval).unwrap();
+    assert_eq!(runtime.accounts[&approval.address], migrated);
+}
+
+#[test]
+fn baseline_approvals_can_be_used_before_and_after_migrating() {
+    let mut runtime = Runtime::new();
+    let approval = approval(&mut runtime);
+    let recipient = Pubkey::new_unique();
+    runtime.accounts.insert(approval.address, Account::with_data(runtime.program_id, baseline_approval(true)));
+
+    pay(&mut runtime, &approval, &recipient, 100).unwrap();
+    migrate_approval(&mut runtime, &approval).unwrap();
+    pay(&mut runtime, &approval, &recipient, 100).unwrap();
+
+    assert_eq!(runtime.lamports(&recipient), 200);
+    let state = assert_current(&runtime, &approval);
+    assert_eq!(u64::from(state.spent_amount), 200);
+    assert_eq!(u64::from(state.execution_count), 2);
+}
+
+#[test]
+fn approvals_migrate_only_for_their_own_wallet_and_dapp() {
+    let mut runtime = Runtime::new();
+    let approval = approval(&mut runtime);
+    let other = self::approval(&mut runtime);
+    runtime.accounts.insert(approval.address, Account::with_data(runtime.program_id, baseline_approval(true)));
+    let before = runtime.accounts[&approval.address].clone();
+
+    let wrong_dapp = [approval.wallet, other.dapp, approval.owner];
+    assert_eq!(migrate(&mut runtime, AccountKind::DAppApproval, &approval.address, &wrong_dapp), Err(ProgramError::InvalidAccountData));
+    let wrong_wallet = [other.wallet, approval.dapp, other.owner];
+    assert_eq!(migrate(&mut runtime, AccountKind::DAppApproval, &approval.address, &wrong_wallet), Err(ProgramError::InvalidAccountData));
+    assert_eq!(runtime.accounts[&approval.address], before);
+}
+
+#[test]
+fn baseline_wallets_migrate_to_the_current_layout() {
+    let mut runtime = Runtime::new();
+    let owner = Pubkey::new_unique();
+    let (wallet, bump) = derive_wallet_address(&owner, &runtime.program_id);
+
+    // The baseline program created wallets without any data
+    runtime.accounts.insert(wallet, Account::with_data(runtime.program_id, vec![]));
+    migrate(&mut runtime, AccountKind::Wallet, &wallet, &[owner]).unwrap();
+
+    let data = &runtime.accounts[&wallet].data;
+    assert_eq!(data.len(), WALLET_STATE_SIZE);
+    assert_eq!(data[..ACCOUNT_HEADER_SIZE], [AccountKind::Wallet as u8, WalletState::VERSION]);
+    let state: WalletState = runtime.state(&wallet);
+    assert_eq!(state.owner, owner);
+    assert_eq!(state.bump, bump);
+    assert_eq!(u64::from(state.intent_nonce), 0);
+}
+
+#[test]
+fn only_wallets_and_approvals_have_older_layouts() {
+    let mut runtime = Runtime::new();
+    let approval = approval(&mut runtime);
+    runtime.accounts.insert(approval.address, Account::with_data(runtime.program_id, baseline_approval(true)));
+
+    let extra = [approval.wallet, approval.dapp, approval.owner];
+    assert_eq!(migrate(&mut runtime, AccountKind::CpiApproval, &approval.address, &extra), Err(ProgramError::InvalidArgument));
+    assert_eq!(runtime.accounts[&approval.address].data, baseline_approval(true));
+}
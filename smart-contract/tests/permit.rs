@@ -0,0 +1,164 @@
+//! `ApproveDappWithPermit`: approvals the owner signed off-chain, submitted by the dApp, and
+//! `CancelPermit`, which withdraws them before they are used
+
+mod common;
+
+use smart_contract::{
+    client::{self, ed25519_signature},
+    instruction::WalletInstruction,
+    permit::ApprovalPermit,
+    state::{derive_approval_address, derive_permit_address, DAppApproval},
+};
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    native_token::LAMPORTS_PER_SOL,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    system_program, sysvar,
+};
+
+use crate::common::{Runtime, NOW};
+
+const SIGNATURE: [u8; 64] = [7; 64];
+
+struct Setup {
+    owner: Pubkey,
+    wallet: Pubkey,
+    dapp: Pubkey,
+}
+
+fn setup(runtime: &mut Runtime) -> Setup {
+    let (owner, wallet) = runtime.create_wallet(0);
+    let dapp = Pubkey::new_unique();
+    runtime.fund(&dapp, LAMPORTS_PER_SOL);
+    Setup { owner, wallet, dapp }
+}
+
+fn new_permit(setup: &Setup, max_amount: u64, expiry: i64, nonce: u64) -> ApprovalPermit {
+    ApprovalPermit { wallet: setup.wallet, dapp: setup.dapp, token_mint: Pubkey::default(), max_amount, expiry, nonce }
+}
+
+/// Submits `permit` right after an ed25519 instruction verifying `signer`'s signature over
+/// `signed`
+fn submit(runtime: &mut Runtime, setup: &Setup, permit: &ApprovalPermit, signer: &Pubkey, signed: &ApprovalPermit) -> Result<(), ProgramError> {
+    let program_id = runtime.program_id;
+    let (approval, _) = derive_approval_address(&setup.wallet, &setup.dapp, &permit.token_mint, &program_id);
+    let (permit_account, _) = derive_permit_address(&setup.wallet, permit.nonce, &program_id);
+    let instruction = Instruction::new_with_bytes(
+        program_id,
+        &WalletInstruction::ApproveDappWithPermit { max_amount: permit.max_amount, expiry: permit.expiry, nonce: permit.nonce }.pack(),
+        vec![
+            AccountMeta::new(setup.dapp, true),
+            AccountMeta::new_readonly(setup.owner, false),
+            AccountMeta::new_readonly(setup.wallet, false),
+            AccountMeta::new_readonly(permit.token_mint, false),
+            AccountMeta::new(approval, false),
+            AccountMeta::new(permit_account, false),
+            AccountMeta::new_readonly(system_program::ID, false),
+            AccountMeta::new_readonly(sysvar::instructions::ID, false),
+        ],
+    );
+    runtime.set_instructions(&[ed25519_signature(signer, &SIGNATURE, &signed.message()), instruction.clone()], 1);
+    runtime.execute(&instruction)
+}
+
+#[test]
+fn signed_permits_approve_the_dapp() {
+    let mut runtime = Runtime::new();
+    let setup = setup(&mut runtime);
+    let permit = new_permit(&setup, 1000, NOW + 3600, 1);
+
+    submit(&mut runtime, &setup, &permit, &setup.owner, &permit).unwrap();
+    let (approval, _) = derive_approval_address(&setup.wallet, &setup.dapp, &Pubkey::default(), &runtime.program_id);
+    let approval: DAppApproval = runtime.state(&approval);
+    assert_eq!(u64::from(approval.max_amount), 1000);
+    assert_eq!(i64::from(approval.expiry), NOW + 3600);
+    assert_eq!(approval.owner, setup.owner);
+}
+
+#[test]
+fn permits_cannot_be_replayed() {
+    let mut runtime = Runtime::new();
+    let setup = setup(&mut runtime);
+    let permit = new_permit(&setup, 1000, NOW + 3600, 1);
+    submit(&mut runtime, &setup, &permit, &setup.owner, &permit).unwrap();
+
+    assert_eq!(submit(&mut runtime, &setup, &permit, &setup.owner, &permit), Err(ProgramError::AccountAlreadyInitialized));
+
+    // The nonce is spent even for a different permit
+    let other = ApprovalPermit { token_mint: Pubkey::new_unique(), ..permit };
+    assert_eq!(submit(&mut runtime, &setup, &other, &setup.owner, &other), Err(ProgramError::AccountAlreadyInitialized));
+}
+
+#[test]
+fn permits_expire_at_their_expiry() {
+    let mut runtime = Runtime::new();
+    let setup = setup(&mut runtime);
+
+    let expired = new_permit(&setup, 1000, NOW, 1);
+    assert_eq!(submit(&mut runtime, &setup, &expired, &setup.owner, &expired), Err(ProgramError::InvalidArgument));
+    let valid = new_permit(&setup, 1000, NOW + 1, 1);
+    submit(&mut runtime, &setup, &valid, &setup.owner, &valid).unwrap();
+}
+
+#[test]
+fn permits_need_the_owners_signature_over_their_exact_terms() {
+    let mut runtime = Runtime::new();
+    let setup = setup(&mut runtime);
+    let permit = new_permit(&setup, 1000, NOW + 3600, 1);
+
+    assert_eq!(
+        submit(&mut runtime, &setup, &permit, &Pubkey::new_unique(), &permit),
+        Err(ProgramError::MissingRequiredSignature)
+    );
+    let raised = ApprovalPermit { max_amount: 1_000_000, ..permit };
+    assert_eq!(submit(&mut runtime, &setup, &raised, &setup.owner, &permit), Err(ProgramError::InvalidInstructionData));
+
+    // A permit signed for one dApp can't be used by another
+    let other_dapp = Setup { dapp: Pubkey::new_unique(), ..setup };
+    runtime.fund(&other_dapp.dapp, LAMPORTS_PER_SOL);
+    let signed = new_permit(&setup, 1000, NOW + 3600, 1);
+    let submitted = new_permit(&other_dapp, 1000, NOW + 3600, 1);
+    assert_eq!(
+        submit(&mut runtime, &other_dapp, &submitted, &other_dapp.owner, &signed),
+        Err(ProgramError::InvalidInstructionData)
+    );
+}
+
+#[test]
+fn cancelled_permits_cannot_be_used() {
+    let mut runtime = Runtime::new();
+    let setup = setup(&mut runtime);
+    let permit = new_permit(&setup, 1000, NOW + 3600, 1);
+    let program_id = runtime.program_id;
+
+    // Only the owner cancels, and each nonce only once
+    let mut foreign = client::cancel_permit(&program_id, &setup.owner, permit.nonce);
+    foreign.accounts[0] = AccountMeta::new(setup.dapp, true);
+    assert_eq!(runtime.execute(&foreign), Err(ProgramError::InvalidAccountData));
+    runtime.execute(&client::cancel_permit(&program_id, &setup.owner, permit.nonce)).unwrap();
+    assert_eq!(
+        runtime.execute(&client::cancel_permit(&program_id, &setup.owner, permit.nonce)),
+        Err(ProgramError::AccountAlreadyInitialized)
+    );
+
+    assert_eq!(submit(&mut runtime, &setup, &permit, &setup.owner, &permit), Err(ProgramError::AccountAlreadyInitialized));
+    let (approval, _) = derive_approval_address(&setup.wallet, &setup.dapp, &Pubkey::default(), &program_id);
+    assert!(!runtime.exists(&approval));
+}
+
+#[test]
+fn permits_cannot_overwrite_an_existing_approval() {
+    let mut runtime = Runtime::new();
+    let setup = setup(&mut runtime);
+    let approval = runtime.approve(&setup.owner, &setup.dapp, &Pubkey::default(), 1000);
+    let program_id = runtime.program_id;
+    runtime.execute(&client::set_approval_status(&program_id, &setup.owner, &setup.dapp, &Pubkey::default(), false)).unwrap();
+
+    // An old permit would otherwise resume the paused approval and reset its usage
+    let permit = new_permit(&setup, 1_000_000, NOW + 3600, 1);
+    assert_eq!(submit(&mut runtime, &setup, &permit, &setup.owner, &permit), Err(ProgramError::AccountAlreadyInitialized));
+    let approval: DAppApproval = runtime.state(&approval);
+    assert!(!bool::from(approval.is_approved));
+    assert_eq!(u64::from(approval.max_amount), 1000);
+}
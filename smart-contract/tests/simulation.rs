@@ -0,0 +1,273 @@
+//! Decoding of simulated failures into `WalletError`s, against the error codes the processor
+//! returns and the messages it logs. The instruction runs natively and its logs are framed the
+//! way the runtime reports them.
+
+use std::{cell::RefCell, convert::Infallible};
+
+use smart_contract::{
+    client::{self, check_simulation, SimulationOutcome, WalletError},
+    error::WalletErrorCode,
+    process_instruction,
+    state::{derive_approval_address, derive_wallet_address, pack_account, DAppApproval, WalletState, DAPP_APPROVAL_SIZE, WALLET_STATE_SIZE},
+};
+use solana_program::{
+    account_info::AccountInfo,
+    clock::Clock,
+    instruction::{Instruction, InstructionError},
+    program_error::ProgramError,
+    program_stubs::{set_syscall_stubs, SyscallStubs},
+    pubkey::Pubkey,
+    rent::Rent,
+    system_program,
+};
+use spl_pod::primitives::PodBool;
+
+const NOW: i64 = 1_700_000_000;
+const MAX_AMOUNT: u64 = 5_000_000;
+const UNITS_CONSUMED: u64 = 4_321;
+
+thread_local! {
+    static LOGS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+struct LoggingStubs;
+
+impl SyscallStubs for LoggingStubs {
+    fn sol_log(&self, message: &str) {
+        LOGS.with(|logs| logs.borrow_mut().push(format!("Program log: {}", message)));
+    }
+
+    fn sol_log_data(&self, _fields: &[&[u8]]) {}
+
+    fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+        let clock = Clock {
+            unix_timestamp: NOW,
+            ..Clock::default()
+        };
+        unsafe { *(var_addr as *mut Clock) = clock };
+        0
+    }
+
+    fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+        unsafe { *(var_addr as *mut Rent) = Rent::default() };
+        0
+    }
+}
+
+fn wallet_data(owner: &Pubkey, bump: u8, program_id: &Pubkey) -> Vec<u8> {
+    let mut data = vec![0; WALLET_STATE_SIZE];
+    let mut lamports = 0;
+    let key = Pubkey::new_unique();
+    let account = AccountInfo::new(&key, false, true, &mut lamports, &mut data, program_id, false, 0);
+    pack_account(&WalletState::new(*owner, bump), &account).unwrap();
+    drop(account);
+    data
+}
+
+/// Simulates a SOL `ExecuteTransaction` of `amount` under an approval adjusted by `configure`
+fn simulate_execute(amount: u64, configure: impl FnOnce(&mut DAppApproval)) -> Result<u64, WalletError> {
+    let program_id = Pubkey::new_unique();
+    let owner = Pubkey::new_unique();
+    let dapp = Pubkey::new_unique();
+    let recipient = Pubkey::new_unique();
+    let (wallet, wallet_bump) = derive_wallet_address(&owner, &program_id);
+    let (approval, approval_bump) = derive_approval_address(&wallet, &dapp, &Pubkey::default(), &program_id);
+
+    let mut approval_data = vec![0; DAPP_APPROVAL_SIZE];
+    {
+        let mut lamports = 0;
+        let account = AccountInfo::new(&approval, false, true, &mut lamports, &mut approval_data, &program_id, false, 0);
+        let mut approval_state = DAppApproval::new(wallet, dapp, owner, NOW, MAX_AMOUNT, NOW + 3600, Pubkey::default());
+        approval_state.bump = approval_bump;
+        configure(&mut approval_state);
+        pack_account(&approval_state, &account).unwrap();
+    }
+
+    let mut dapp_lamports = 1_000_000;
+    let mut owner_lamports = 0;
+    let mut wallet_lamports = 10_000_000;
+    let mut approval_lamports = 10_000_000;
+    let mut recipient_lamports = 0;
+    let mut system_lamports = 0;
+    let mut wallet_state = wallet_data(&owner, wallet_bump, &program_id);
+    let (mut dapp_data, mut owner_data, mut recipient_data, mut system_data) = (vec![], vec![], vec![], vec![]);
+
+    let accounts = [
+        AccountInfo::new(&dapp, true, true, &mut dapp_lamports, &mut dapp_data, &system_program::ID, false, 0),
+        AccountInfo::new(&owner, false, true, &mut owner_lamports, &mut owner_data, &system_program::ID, false, 0),
+        AccountInfo::new(&wallet, false, true, &mut wallet_lamports, &mut wallet_state, &program_id, false, 0),
+        AccountInfo::new(&approval, false, true, &mut approval_lamports, &mut approval_data, &program_id, false, 0),
+        AccountInfo::new(&recipient, false, true, &mut recipient_lamports, &mut recipient_data, &system_program::ID, false, 0),
+        AccountInfo::new(&system_program::ID, false, false, &mut system_lamports, &mut system_data, &system_program::ID, true, 0),
+    ];
+
+    let instruction = client::execute_transaction(&program_id, &dapp, &owner, &recipient, amount, None, None);
+    check_simulation(&program_id, &[instruction], |instructions| {
+        set_syscall_stubs(Box::new(LoggingStubs));
+        LOGS.with(|logs| logs.replace(vec![format!("Program {} invoke [1]", program_id)]));
+        let result = process_instruction(&program_id, &accounts, &instructions[0].data);
+
+        let mut logs = LOGS.with(RefCell::take);
+        logs.push(format!("Program {} consumed {} of 200000 compute units", program_id, UNITS_CONSUMED));
+        let instruction_error = result.err().map(|error| (0, InstructionError::from(u64::from(error))));
+        match &instruction_error {
+            Some((_, error)) => logs.push(format!("Program {} failed: {}", program_id, error)),
+            None => logs.push(format!("Program {} success", program_id)),
+        }
+
+        Ok::<_, Infallible>(SimulationOutcome {
+            instruction_error,
+            transaction_error: None,
+            logs,
+            units_consumed: Some(UNITS_CONSUMED),
+        })
+    })
+}
+
+#[test]
+fn successful_simulation_returns_consumed_units() {
+    assert_eq!(simulate_execute(1_000_000, |_| {}), Ok(UNITS_CONSUMED));
+}
+
+#[test]
+fn execute_failures_decode_to_wallet_errors() {
+    let allowed = Pubkey::new_unique();
+
+    assert_eq!(
+        simulate_execute(1_000_000, |approval| approval.is_approved = PodBool::from(false)),
+        Err(WalletError::ApprovalRevoked)
+    );
+    assert_eq!(
+        simulate_execute(1_000_000, |approval| approval.expiry = (NOW - 60).into()),
+        Err(WalletError::ApprovalExpired { expiry: Some(NOW - 60) })
+    );
+    assert_eq!(
+        simulate_execute(1_000_000, |approval| approval.expiry = (-60).into()),
+        Err(WalletError::ApprovalExpired { expiry: Some(-60) })
+    );
+    assert_eq!(
+        simulate_execute(1_000_000, |approval| approval.valid_from = (NOW + 60).into()),
+        Err(WalletError::ApprovalNotYetValid { valid_from: Some(NOW + 60) })
+    );
+    assert_eq!(
+        simulate_execute(1_000_000, |approval| {
+            approval.max_executions = 2.into();
+            approval.execution_count = 2.into();
+        }),
+        Err(WalletError::ExecutionLimitReached { executions: Some(2) })
+    );
+    assert_eq!(
+        simulate_execute(MAX_AMOUNT + 1, |_| {}),
+        Err(WalletError::AllowanceExceeded { amount: Some(MAX_AMOUNT + 1), max_amount: Some(MAX_AMOUNT) })
+    );
+    assert!(matches!(
+        simulate_execute(1_000_000, |approval| approval.set_recipients(&[allowed]).unwrap()),
+        Err(WalletError::RecipientNotAllowed { recipient: Some(recipient) }) if recipient != allowed
+    ));
+    assert_eq!(
+        simulate_execute(1_000_000, |approval| {
+            approval.auto_limit = 100.into();
+            approval.cosign_limit = MAX_AMOUNT.into();
+        }),
+        Err(WalletError::CosignRequired)
+    );
+    assert_eq!(
+        simulate_execute(9_500_000, |approval| approval.max_amount = 10_000_000.into()),
+        Err(WalletError::InsufficientFunds)
+    );
+}
+
+#[test]
+fn only_the_wallet_programs_own_messages_are_decoded() {
+    let program_id = Pubkey::new_unique();
+    let other_program = Pubkey::new_unique();
+    let instructions = [
+        Instruction::new_with_bytes(other_program, &[], vec![]),
+        Instruction::new_with_bytes(program_id, &[], vec![]),
+    ];
+    let lines = |lines: &[&str]| lines.iter().map(|line| line.to_string()).collect::<Vec<_>>();
+
+    // A message logged by a program the wallet calls is not the wallet's reason
+    let outcome = SimulationOutcome {
+        instruction_error: Some((1, InstructionError::InvalidAccountData)),
+        logs: lines(&[
+            &format!("Program {} invoke [1]", other_program),
+            &format!("Program {} success", other_program),
+            &format!("Program {} invoke [1]", program_id),
+            "Program log: Approval is not for SOL",
+            &format!("Program {} invoke [2]", other_program),
+            "Program log: Approval expired at 5",
+            &format!("Program {} success", other_program),
+            &format!("Program {} failed: invalid account data for instruction", program_id),
+        ]),
+        ..SimulationOutcome::default()
+    };
+    assert_eq!(
+        check_simulation(&program_id, &instructions, |_| Ok::<_, Infallible>(outcome)),
+        Err(WalletError::Program {
+            error: ProgramError::InvalidAccountData,
+            message: Some("Approval is not for SOL".to_string()),
+        })
+    );
+
+    // Nor is the failure of another instruction in the transaction
+    let outcome = SimulationOutcome {
+        instruction_error: Some((0, InstructionError::InvalidAccountData)),
+        logs: lines(&["Program log: Approval expired at 5"]),
+        ..SimulationOutcome::default()
+    };
+    assert!(matches!(
+        check_simulation(&program_id, &instructions, |_| Ok::<_, Infallible>(outcome)),
+        Err(WalletError::Transaction(_))
+    ));
+}
+
+#[test]
+fn errors_are_decoded_by_code_and_logs_only_add_details() {
+    let program_id = Pubkey::new_unique();
+    let instructions = [Instruction::new_with_bytes(program_id, &[], vec![])];
+    let failure = |error: ProgramError, logs: &[&str]| SimulationOutcome {
+        instruction_error: Some((0, InstructionError::from(u64::from(error)))),
+        logs: std::iter::once(format!("Program {} invoke [1]", program_id))
+            .chain(logs.iter().map(|line| format!("Program log: {}", line)))
+            .collect(),
+        ..SimulationOutcome::default()
+    };
+    let check = |outcome| check_simulation(&program_id, &instructions, |_| Ok::<_, Infallible>(outcome));
+
+    // Without a logged reason the code alone decides the error
+    assert_eq!(
+        check(failure(WalletErrorCode::ApprovalExpired.into(), &[])),
+        Err(WalletError::ApprovalExpired { expiry: None })
+    );
+    assert_eq!(
+        check(failure(WalletErrorCode::AllowanceExceeded.into(), &["Something else 1 and 2"])),
+        Err(WalletError::AllowanceExceeded { amount: None, max_amount: None })
+    );
+
+    // A message that reads like a wallet error does not turn a builtin error into one
+    assert_eq!(
+        check(failure(ProgramError::InvalidAccountData, &["Approval expired at 5"])),
+        Err(WalletError::Program {
+            error: ProgramError::InvalidAccountData,
+            message: Some("Approval expired at 5".to_string()),
+        })
+    );
+
+    // Custom codes the wallet does not define are passed through
+    assert_eq!(
+        check(failure(ProgramError::Custom(1_000), &[])),
+        Err(WalletError::Program { error: ProgramError::Custom(1_000), message: None })
+    );
+}
+
+#[test]
+fn simulation_failures_are_reported() {
+    let program_id = Pubkey::new_unique();
+    let instructions = [Instruction::new_with_bytes(program_id, &[], vec![])];
+
+    assert_eq!(
+        check_simulation(&program_id, &instructions, |_| Err("connection refused")),
+        Err(WalletError::Simulation("connection refused".to_string()))
+    );
+}
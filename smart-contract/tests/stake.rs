@@ -0,0 +1,151 @@
+//! Staking from the wallet: creating, delegating, deactivating and withdrawing stake accounts
+
+mod common;
+
+use smart_contract::{instruction::WalletInstruction, state::derive_stake_address};
+use solana_program::{
+    instruction::AccountMeta,
+    native_token::LAMPORTS_PER_SOL,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    rent::Rent,
+    stake::{self, state::StakeStateV2},
+    system_program, sysvar,
+};
+
+use crate::common::{Account, Runtime};
+
+const STAKE_ID: u64 = 7;
+/// The stake config account, whose id `solana_program` only exports as deprecated
+const STAKE_CONFIG: Pubkey = solana_program::pubkey!("StakeConfig11111111111111111111111111111111");
+
+struct Stake {
+    owner: Pubkey,
+    wallet: Pubkey,
+    address: Pubkey,
+}
+
+fn new_stake(runtime: &mut Runtime, wallet_lamports: u64) -> Stake {
+    let (owner, wallet) = runtime.create_wallet(wallet_lamports);
+    let (address, _) = derive_stake_address(&wallet, STAKE_ID, &runtime.program_id);
+    Stake { owner, wallet, address }
+}
+
+fn reserve() -> u64 {
+    Rent::default().minimum_balance(StakeStateV2::size_of())
+}
+
+fn create(runtime: &mut Runtime, stake: &Stake, owner: &Pubkey, amount: u64) -> Result<(), ProgramError> {
+    let metas = [
+        AccountMeta::new_readonly(*owner, true),
+        AccountMeta::new(stake.wallet, false),
+        AccountMeta::new(stake.address, false),
+        AccountMeta::new_readonly(system_program::ID, false),
+        AccountMeta::new_readonly(stake::program::id(), false),
+        AccountMeta::new_readonly(sysvar::rent::ID, false),
+    ];
+    runtime.process(&metas, &WalletInstruction::CreateStake { stake_id: STAKE_ID, amount }.pack())
+}
+
+fn delegate(runtime: &mut Runtime, stake: &Stake, owner: &Pubkey) -> Result<(), ProgramError> {
+    let metas = [
+        AccountMeta::new_readonly(*owner, true),
+        AccountMeta::new_readonly(stake.wallet, false),
+        AccountMeta::new(stake.address, false),
+        AccountMeta::new_readonly(Pubkey::new_unique(), false),
+        AccountMeta::new_readonly(sysvar::clock::ID, false),
+        AccountMeta::new_readonly(sysvar::stake_history::ID, false),
+        AccountMeta::new_readonly(STAKE_CONFIG, false),
+        AccountMeta::new_readonly(stake::program::id(), false),
+    ];
+    runtime.process(&metas, &WalletInstruction::DelegateStake { stake_id: STAKE_ID }.pack())
+}
+
+fn deactivate(runtime: &mut Runtime, stake: &Stake, owner: &Pubkey) -> Result<(), ProgramError> {
+    let metas = [
+        AccountMeta::new_readonly(*owner, true),
+        AccountMeta::new_readonly(stake.wallet, false),
+        AccountMeta::new(stake.address, false),
+        AccountMeta::new_readonly(sysvar::clock::ID, false),
+        AccountMeta::new_readonly(stake::program::id(), false),
+    ];
+    runtime.process(&metas, &WalletInstruction::DeactivateStake { stake_id: STAKE_ID }.pack())
+}
+
+fn withdraw(runtime: &mut Runtime, stake: &Stake, owner: &Pubkey, amount: u64) -> Result<(), ProgramError> {
+    let metas = [
+        AccountMeta::new_readonly(*owner, true),
+        AccountMeta::new(stake.wallet, false),
+        AccountMeta::new(stake.address, false),
+        AccountMeta::new_readonly(sysvar::clock::ID, false),
+        AccountMeta::new_readonly(sysvar::stake_history::ID, false),
+        AccountMeta::new_readonly(stake::program::id(), false),
+    ];
+    runtime.process(&metas, &WalletInstruction::WithdrawStake { stake_id: STAKE_ID, amount }.pack())
+}
+
+#[test]
+fn stake_goes_through_its_lifecycle_back_to_the_wallet() {
+    let mut runtime = Runtime::new();
+    let stake = new_stake(&mut runtime, 2 * LAMPORTS_PER_SOL);
+    let wallet_before = runtime.lamports(&stake.wallet);
+
+    create(&mut runtime, &stake, &stake.owner, LAMPORTS_PER_SOL).unwrap();
+    assert_eq!(runtime.lamports(&stake.address), LAMPORTS_PER_SOL);
+    assert_eq!(runtime.lamports(&stake.wallet), wallet_before - LAMPORTS_PER_SOL);
+    assert_eq!(runtime.accounts[&stake.address].owner, stake::program::id());
+    assert_eq!(create(&mut runtime, &stake, &stake.owner, LAMPORTS_PER_SOL), Err(ProgramError::AccountAlreadyInitialized));
+
+    // Delegated stake stays locked until it is deactivated
+    delegate(&mut runtime, &stake, &stake.owner).unwrap();
+    assert_eq!(withdraw(&mut runtime, &stake, &stake.owner, 1), Err(ProgramError::InsufficientFunds));
+    deactivate(&mut runtime, &stake, &stake.owner).unwrap();
+
+    withdraw(&mut runtime, &stake, &stake.owner, LAMPORTS_PER_SOL).unwrap();
+    assert_eq!(runtime.lamports(&stake.wallet), wallet_before);
+    assert!(!runtime.exists(&stake.address));
+}
+
+#[test]
+fn stake_accounts_need_their_reserve_and_leave_the_wallet_rent_exempt() {
+    let mut runtime = Runtime::new();
+    let stake = new_stake(&mut runtime, LAMPORTS_PER_SOL);
+
+    assert_eq!(create(&mut runtime, &stake, &stake.owner, reserve() - 1), Err(ProgramError::InsufficientFunds));
+    assert_eq!(create(&mut runtime, &stake, &stake.owner, LAMPORTS_PER_SOL + 1), Err(ProgramError::InsufficientFunds));
+    create(&mut runtime, &stake, &stake.owner, LAMPORTS_PER_SOL).unwrap();
+    assert_eq!(runtime.spendable(&stake.wallet), 0);
+}
+
+#[test]
+fn pre_funded_stake_addresses_are_taken_over() {
+    let mut runtime = Runtime::new();
+    let stake = new_stake(&mut runtime, LAMPORTS_PER_SOL);
+
+    // Anyone can send lamports to the address before the owner creates the account
+    runtime.fund(&stake.address, reserve());
+    create(&mut runtime, &stake, &stake.owner, 0).unwrap();
+    assert_eq!(runtime.lamports(&stake.address), reserve());
+
+    // An address that already holds data can't be taken over
+    let allocated = new_stake(&mut runtime, LAMPORTS_PER_SOL);
+    runtime.accounts.insert(allocated.address, Account::with_data(system_program::ID, vec![0; 8]));
+    assert_eq!(create(&mut runtime, &allocated, &allocated.owner, reserve()), Err(ProgramError::AccountAlreadyInitialized));
+}
+
+#[test]
+fn only_the_owner_manages_the_wallets_stake() {
+    let mut runtime = Runtime::new();
+    let stake = new_stake(&mut runtime, 2 * LAMPORTS_PER_SOL);
+    let (other_owner, _) = runtime.create_wallet(0);
+
+    assert_eq!(create(&mut runtime, &stake, &other_owner, LAMPORTS_PER_SOL), Err(ProgramError::InvalidAccountData));
+    create(&mut runtime, &stake, &stake.owner, LAMPORTS_PER_SOL).unwrap();
+    assert_eq!(delegate(&mut runtime, &stake, &other_owner), Err(ProgramError::InvalidAccountData));
+    assert_eq!(withdraw(&mut runtime, &stake, &other_owner, 1), Err(ProgramError::InvalidAccountData));
+
+    // Stake accounts are per stake id
+    let (other_address, _) = derive_stake_address(&stake.wallet, STAKE_ID + 1, &runtime.program_id);
+    let other_id = Stake { address: other_address, ..stake };
+    assert_eq!(deactivate(&mut runtime, &other_id, &other_id.owner), Err(ProgramError::InvalidAccountData));
+}
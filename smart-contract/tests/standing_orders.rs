@@ -0,0 +1,196 @@
+//! Standing orders: creation, payouts when due and cancellation
+
+mod common;
+
+use smart_contract::{
+    event::WalletEvent,
+    instruction::WalletInstruction,
+    state::{derive_standing_order_address, StandingOrder},
+};
+use solana_program::{
+    instruction::AccountMeta, native_token::LAMPORTS_PER_SOL, program_error::ProgramError, pubkey::Pubkey,
+    rent::Rent, system_program,
+};
+
+use crate::common::{set_time, take_events, Runtime, NOW};
+
+const CRANK_REWARD: u64 = 5000;
+const AMOUNT: u64 = LAMPORTS_PER_SOL / 10;
+const INTERVAL: i64 = 3600;
+
+struct Order {
+    owner: Pubkey,
+    wallet: Pubkey,
+    recipient: Pubkey,
+    address: Pubkey,
+}
+
+fn create(runtime: &mut Runtime, owner: &Pubkey, wallet: &Pubkey, recipient: &Pubkey, mint: &Pubkey, start_time: i64) -> Result<Pubkey, ProgramError> {
+    let (address, _) = derive_standing_order_address(wallet, 1, &runtime.program_id);
+    let metas = [
+        AccountMeta::new(*owner, true),
+        AccountMeta::new_readonly(*wallet, false),
+        AccountMeta::new_readonly(*recipient, false),
+        AccountMeta::new_readonly(*mint, false),
+        AccountMeta::new(address, false),
+        AccountMeta::new_readonly(system_program::ID, false),
+    ];
+    let instruction = WalletInstruction::CreateStandingOrder { order_id: 1, amount: AMOUNT, interval: INTERVAL, start_time };
+    runtime.process(&metas, &instruction.pack()).map(|()| address)
+}
+
+/// A SOL order of `AMOUNT` every `INTERVAL`, first due now
+fn sol_order(runtime: &mut Runtime, wallet_lamports: u64) -> Order {
+    let (owner, wallet) = runtime.create_wallet(wallet_lamports);
+    let recipient = Pubkey::new_unique();
+    let address = create(runtime, &owner, &wallet, &recipient, &Pubkey::default(), NOW).unwrap();
+    Order { owner, wallet, recipient, address }
+}
+
+fn crank(runtime: &mut Runtime, order: &Order, cranker: &Pubkey, token_accounts: &[AccountMeta]) -> Result<(), ProgramError> {
+    let mut metas = vec![
+        AccountMeta::new(*cranker, true),
+        AccountMeta::new(order.wallet, false),
+        AccountMeta::new(order.address, false),
+        AccountMeta::new(order.recipient, false),
+        AccountMeta::new_readonly(system_program::ID, false),
+    ];
+    metas.extend_from_slice(token_accounts);
+    runtime.process(&metas, &WalletInstruction::CrankStandingOrder.pack())
+}
+
+fn cancel(runtime: &mut Runtime, order: &Order, owner: &Pubkey) -> Result<(), ProgramError> {
+    let metas = [
+        AccountMeta::new(*owner, true),
+        AccountMeta::new_readonly(order.wallet, false),
+        AccountMeta::new(order.address, false),
+    ];
+    runtime.process(&metas, &WalletInstruction::CancelStandingOrder.pack())
+}
+
+#[test]
+fn due_orders_pay_the_recipient_and_the_cranker() {
+    let mut runtime = Runtime::new();
+    let order = sol_order(&mut runtime, LAMPORTS_PER_SOL);
+    let cranker = Pubkey::new_unique();
+    let wallet_before = runtime.lamports(&order.wallet);
+
+    crank(&mut runtime, &order, &cranker, &[]).unwrap();
+    assert_eq!(
+        take_events(),
+        [WalletEvent::StandingOrderPaid {
+            order: order.address,
+            wallet: order.wallet,
+            recipient: order.recipient,
+            token_mint: Pubkey::default(),
+            amount: AMOUNT,
+            next_due: NOW + INTERVAL,
+        }]
+    );
+    assert_eq!(runtime.lamports(&order.recipient), AMOUNT);
+    assert_eq!(runtime.lamports(&cranker), CRANK_REWARD);
+    assert_eq!(runtime.lamports(&order.wallet), wallet_before - AMOUNT - CRANK_REWARD);
+    let state: StandingOrder = runtime.state(&order.address);
+    assert_eq!(i64::from(state.next_due), NOW + INTERVAL);
+
+    // The next payment waits for the next interval
+    assert_eq!(crank(&mut runtime, &order, &cranker, &[]), Err(ProgramError::InvalidAccountData));
+    set_time(NOW + INTERVAL);
+    crank(&mut runtime, &order, &cranker, &[]).unwrap();
+    assert_eq!(runtime.lamports(&order.recipient), 2 * AMOUNT);
+}
+
+#[test]
+fn missed_intervals_are_skipped_rather_than_paid_back_to_back() {
+    let mut runtime = Runtime::new();
+    let order = sol_order(&mut runtime, LAMPORTS_PER_SOL);
+    let cranker = Pubkey::new_unique();
+
+    // Nobody cranked for three and a half intervals
+    set_time(NOW + 3 * INTERVAL + INTERVAL / 2);
+    crank(&mut runtime, &order, &cranker, &[]).unwrap();
+    let state: StandingOrder = runtime.state(&order.address);
+    assert_eq!(i64::from(state.next_due), NOW + 4 * INTERVAL);
+
+    assert_eq!(crank(&mut runtime, &order, &cranker, &[]), Err(ProgramError::InvalidAccountData));
+    assert_eq!(runtime.lamports(&order.recipient), AMOUNT);
+
+    set_time(NOW + 4 * INTERVAL);
+    crank(&mut runtime, &order, &cranker, &[]).unwrap();
+    assert_eq!(runtime.lamports(&order.recipient), 2 * AMOUNT);
+}
+
+#[test]
+fn orders_cannot_start_in_the_past() {
+    let mut runtime = Runtime::new();
+    let (owner, wallet) = runtime.create_wallet(LAMPORTS_PER_SOL);
+
+    // Otherwise the first payment would fall due immediately
+    assert_eq!(
+        create(&mut runtime, &owner, &wallet, &Pubkey::new_unique(), &Pubkey::default(), NOW - 10 * INTERVAL),
+        Err(ProgramError::InvalidArgument)
+    );
+    assert!(create(&mut runtime, &owner, &wallet, &Pubkey::new_unique(), &Pubkey::default(), NOW + INTERVAL).is_ok());
+}
+
+#[test]
+fn orders_only_pay_their_recipient() {
+    let mut runtime = Runtime::new();
+    let mut order = sol_order(&mut runtime, LAMPORTS_PER_SOL);
+    order.recipient = Pubkey::new_unique();
+
+    assert_eq!(crank(&mut runtime, &order, &Pubkey::new_unique(), &[]), Err(ProgramError::InvalidAccountData));
+}
+
+#[test]
+fn payouts_leave_the_wallet_rent_exempt() {
+    let mut runtime = Runtime::new();
+    let order = sol_order(&mut runtime, AMOUNT + CRANK_REWARD - 1);
+
+    assert_eq!(crank(&mut runtime, &order, &Pubkey::new_unique(), &[]), Err(ProgramError::InsufficientFunds));
+}
+
+#[test]
+fn token_orders_pay_tokens_and_the_reward_in_lamports() {
+    let mut runtime = Runtime::new();
+    let (owner, wallet) = runtime.create_wallet(0);
+    let mint = runtime.add_mint(&spl_token::id(), 6);
+    let wallet_token = runtime.add_token_account(&spl_token::id(), &mint, &wallet, 3 * AMOUNT);
+    let recipient_owner = Pubkey::new_unique();
+    let recipient = runtime.add_token_account(&spl_token::id(), &mint, &recipient_owner, 0);
+    let address = create(&mut runtime, &owner, &wallet, &recipient, &mint, NOW).unwrap();
+    let order = Order { owner, wallet, recipient, address };
+    let token_accounts = [
+        AccountMeta::new_readonly(mint, false),
+        AccountMeta::new(wallet_token, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+    ];
+
+    // The wallet holds only its rent, so it can't pay the reward
+    assert_eq!(
+        crank(&mut runtime, &order, &Pubkey::new_unique(), &token_accounts),
+        Err(ProgramError::InsufficientFunds)
+    );
+
+    runtime.fund(&wallet, CRANK_REWARD);
+    crank(&mut runtime, &order, &Pubkey::new_unique(), &token_accounts).unwrap();
+    assert_eq!(runtime.token_balance(&recipient), AMOUNT);
+    assert_eq!(runtime.token_balance(&wallet_token), 2 * AMOUNT);
+    assert_eq!(runtime.lamports(&wallet), Rent::default().minimum_balance(runtime.accounts[&wallet].data.len()));
+}
+
+#[test]
+fn only_the_owner_cancels_an_order() {
+    let mut runtime = Runtime::new();
+    let order = sol_order(&mut runtime, LAMPORTS_PER_SOL);
+    let order_rent = runtime.lamports(&order.address);
+
+    let (other_owner, _) = runtime.create_wallet(0);
+    assert_eq!(cancel(&mut runtime, &order, &other_owner), Err(ProgramError::InvalidAccountData));
+
+    let owner_before = runtime.lamports(&order.owner);
+    cancel(&mut runtime, &order, &order.owner).unwrap();
+    assert!(!runtime.exists(&order.address));
+    assert_eq!(runtime.lamports(&order.owner), owner_before + order_rent);
+    assert!(crank(&mut runtime, &order, &Pubkey::new_unique(), &[]).is_err());
+}
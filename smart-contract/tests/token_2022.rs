@@ -0,0 +1,217 @@
+//! Token-2022 mints with extensions: transfer hooks see the wallet's transfers, and transfer
+//! fees come out of the wallet and the dApp's allowance
+
+mod common;
+
+use std::cell::RefCell;
+
+use smart_contract::{
+    client::{self, TokenTransfer},
+    error::WalletErrorCode,
+    instruction::{TransferType, WalletInstruction},
+    state::DAppApproval,
+};
+use solana_program::{
+    account_info::AccountInfo, entrypoint::ProgramResult, instruction::AccountMeta, native_token::LAMPORTS_PER_SOL,
+    program_error::ProgramError, pubkey::Pubkey, system_program,
+};
+use spl_tlv_account_resolution::{account::ExtraAccountMeta, state::ExtraAccountMetaList};
+use spl_token_2022::extension::{
+    transfer_fee::{TransferFee, TransferFeeConfig},
+    transfer_hook::TransferHook,
+    ExtensionType,
+};
+use spl_transfer_hook_interface::{
+    get_extra_account_metas_address,
+    instruction::{ExecuteInstruction, TransferHookInstruction},
+};
+
+use crate::common::{register_program, Account, Runtime};
+
+const MAX_AMOUNT: u64 = 1000;
+/// 1%, rounded up
+const FEE_BASIS_POINTS: u16 = 100;
+
+thread_local! {
+    /// The amount and accounts of each `Execute` the hook program received
+    static HOOK_CALLS: RefCell<Vec<(u64, Vec<Pubkey>)>> = const { RefCell::new(Vec::new()) };
+}
+
+fn hook_program(_program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+    let TransferHookInstruction::Execute { amount } = TransferHookInstruction::unpack(data)? else {
+        return Err(ProgramError::InvalidInstructionData);
+    };
+    let keys = accounts.iter().map(|account| *account.key).collect();
+    HOOK_CALLS.with(|calls| calls.borrow_mut().push((amount, keys)));
+    Ok(())
+}
+
+struct Hooked {
+    owner: Pubkey,
+    wallet: Pubkey,
+    dapp: Pubkey,
+    approval: Pubkey,
+    token: TokenTransfer,
+    recipient: Pubkey,
+    validation: Pubkey,
+    extra_account: Pubkey,
+    /// The accounts the wallet forwards to the hook, after the instruction's own
+    hook_accounts: Vec<AccountMeta>,
+}
+
+/// A wallet holding 1000 tokens of a mint whose hook program asks for one extra account,
+/// and a dApp approved to spend them
+fn hooked(runtime: &mut Runtime) -> Hooked {
+    let hook_program_id = Pubkey::new_unique();
+    register_program(hook_program_id, hook_program);
+    let mint = runtime.add_token_2022_mint(6, &[ExtensionType::TransferHook], |mint| {
+        mint.init_extension::<TransferHook>(true).unwrap().program_id = Some(hook_program_id).try_into().unwrap();
+    });
+
+    let extra_account = Pubkey::new_unique();
+    let validation = get_extra_account_metas_address(&mint, &hook_program_id);
+    let mut data = vec![0; ExtraAccountMetaList::size_of(1).unwrap()];
+    let extra_metas = [ExtraAccountMeta::new_with_pubkey(&extra_account, false, false).unwrap()];
+    ExtraAccountMetaList::init::<ExecuteInstruction>(&mut data, &extra_metas).unwrap();
+    runtime.accounts.insert(validation, Account::with_data(hook_program_id, data));
+
+    let (owner, wallet) = runtime.create_wallet(LAMPORTS_PER_SOL);
+    let wallet_token = runtime.add_token_account(&spl_token_2022::id(), &mint, &wallet, 1000);
+    let recipient = runtime.add_token_account(&spl_token_2022::id(), &mint, &Pubkey::new_unique(), 0);
+    let dapp = Pubkey::new_unique();
+    let approval = runtime.approve(&owner, &dapp, &mint, MAX_AMOUNT);
+    Hooked {
+        owner,
+        wallet,
+        dapp,
+        approval,
+        token: TokenTransfer { mint, wallet_token_account: wallet_token, token_program: spl_token_2022::id() },
+        recipient,
+        validation,
+        extra_account,
+        hook_accounts: vec![
+            AccountMeta::new_readonly(hook_program_id, false),
+            AccountMeta::new_readonly(validation, false),
+            AccountMeta::new_readonly(extra_account, false),
+        ],
+    }
+}
+
+impl Hooked {
+    /// The accounts the hook should receive for a transfer to `recipient`
+    fn expected_hook_accounts(&self, recipient: &Pubkey) -> Vec<Pubkey> {
+        vec![self.token.wallet_token_account, self.token.mint, *recipient, self.wallet, self.validation, self.extra_account]
+    }
+}
+
+#[test]
+fn transfers_run_the_mints_hook_with_its_extra_accounts() {
+    let mut runtime = Runtime::new();
+    let hooked = hooked(&mut runtime);
+    let mut instruction = client::execute_transaction(
+        &runtime.program_id,
+        &hooked.dapp,
+        &hooked.owner,
+        &hooked.recipient,
+        100,
+        None,
+        Some(&hooked.token),
+    );
+
+    // Token-2022 can't find the hook's accounts without them
+    assert!(runtime.execute(&instruction).is_err());
+
+    instruction.accounts.extend_from_slice(&hooked.hook_accounts);
+    runtime.execute(&instruction).unwrap();
+    assert_eq!(runtime.token_balance(&hooked.recipient), 100);
+    assert_eq!(HOOK_CALLS.with(RefCell::take), [(100, hooked.expected_hook_accounts(&hooked.recipient))]);
+}
+
+#[test]
+fn batches_run_the_mints_hook_for_every_transfer() {
+    let mut runtime = Runtime::new();
+    let hooked = hooked(&mut runtime);
+    let other_recipient = runtime.add_token_account(&spl_token_2022::id(), &hooked.token.mint, &Pubkey::new_unique(), 0);
+
+    let mut metas = vec![
+        AccountMeta::new(hooked.dapp, true),
+        AccountMeta::new(hooked.owner, false),
+        AccountMeta::new(hooked.wallet, false),
+        AccountMeta::new(hooked.approval, false),
+        AccountMeta::new_readonly(system_program::ID, false),
+        AccountMeta::new_readonly(hooked.token.mint, false),
+        AccountMeta::new(hooked.token.wallet_token_account, false),
+        AccountMeta::new_readonly(spl_token_2022::id(), false),
+        AccountMeta::new(hooked.recipient, false),
+        AccountMeta::new(other_recipient, false),
+    ];
+    metas.extend_from_slice(&hooked.hook_accounts);
+    let instruction = WalletInstruction::ExecuteBatch { transfers: vec![(0, 100), (1, 200)], transfer_type: TransferType::Token };
+    runtime.process(&metas, &instruction.pack()).unwrap();
+
+    assert_eq!(runtime.token_balance(&hooked.recipient), 100);
+    assert_eq!(runtime.token_balance(&other_recipient), 200);
+    assert_eq!(
+        HOOK_CALLS.with(RefCell::take),
+        [
+            (100, hooked.expected_hook_accounts(&hooked.recipient)),
+            (200, hooked.expected_hook_accounts(&other_recipient)),
+        ]
+    );
+}
+
+struct FeeCharging {
+    owner: Pubkey,
+    dapp: Pubkey,
+    approval: Pubkey,
+    token: TokenTransfer,
+    recipient: Pubkey,
+}
+
+/// A wallet holding 2000 tokens of a mint that charges `FEE_BASIS_POINTS` on transfers, and a
+/// dApp approved for transfers of up to `MAX_AMOUNT` of them
+fn fee_charging(runtime: &mut Runtime) -> FeeCharging {
+    let mint = runtime.add_token_2022_mint(6, &[ExtensionType::TransferFeeConfig], |mint| {
+        let transfer_fee = TransferFee {
+            epoch: 0.into(),
+            maximum_fee: u64::MAX.into(),
+            transfer_fee_basis_points: FEE_BASIS_POINTS.into(),
+        };
+        let config = mint.init_extension::<TransferFeeConfig>(true).unwrap();
+        config.older_transfer_fee = transfer_fee;
+        config.newer_transfer_fee = transfer_fee;
+    });
+    let (owner, wallet) = runtime.create_wallet(LAMPORTS_PER_SOL);
+    let wallet_token = runtime.add_token_account(&spl_token_2022::id(), &mint, &wallet, 2000);
+    let recipient = runtime.add_token_account(&spl_token_2022::id(), &mint, &Pubkey::new_unique(), 0);
+    let dapp = Pubkey::new_unique();
+    let approval = runtime.approve(&owner, &dapp, &mint, MAX_AMOUNT);
+    let token = TokenTransfer { mint, wallet_token_account: wallet_token, token_program: spl_token_2022::id() };
+    FeeCharging { owner, dapp, approval, token, recipient }
+}
+
+impl FeeCharging {
+    fn send(&self, runtime: &mut Runtime, amount: u64) -> Result<(), ProgramError> {
+        let program_id = runtime.program_id;
+        runtime.execute(&client::execute_transaction(&program_id, &self.dapp, &self.owner, &self.recipient, amount, None, Some(&self.token)))
+    }
+}
+
+#[test]
+fn recipients_get_the_full_amount_and_the_fee_counts_against_the_limit() {
+    let mut runtime = Runtime::new();
+    let charging = fee_charging(&mut runtime);
+
+    // Sending 495 takes 500 out of the wallet: 1% of 500 is the 5 the mint withholds
+    charging.send(&mut runtime, 495).unwrap();
+    assert_eq!(runtime.token_balance(&charging.recipient), 495);
+    assert_eq!(runtime.token_balance(&charging.token.wallet_token_account), 1500);
+    assert_eq!(u64::from(runtime.state::<DAppApproval>(&charging.approval).spent_amount), 500);
+
+    // 991 is under the limit on its own, but its fee of 11 takes it over
+    assert_eq!(charging.send(&mut runtime, 991), Err(WalletErrorCode::AllowanceExceeded.into()));
+    charging.send(&mut runtime, 990).unwrap();
+    assert_eq!(runtime.token_balance(&charging.recipient), 495 + 990);
+    assert_eq!(runtime.token_balance(&charging.token.wallet_token_account), 500);
+    assert_eq!(u64::from(runtime.state::<DAppApproval>(&charging.approval).spent_amount), 500 + MAX_AMOUNT);
+}
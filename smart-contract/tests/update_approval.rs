@@ -0,0 +1,221 @@
+//! `UpdateApproval`: changing an approval in place without resetting its usage
+
+mod common;
+
+use smart_contract::{
+    client,
+    error::WalletErrorCode,
+    event::WalletEvent,
+    instruction::WalletInstruction,
+    state::{DAppApproval, MAX_APPROVAL_RECIPIENTS},
+};
+use solana_program::{
+    instruction::AccountMeta, native_token::LAMPORTS_PER_SOL, program_error::ProgramError, pubkey::Pubkey,
+    system_program,
+};
+
+use crate::common::{take_events, Runtime, NOW};
+
+const MAX_AMOUNT: u64 = LAMPORTS_PER_SOL;
+
+struct Approval {
+    owner: Pubkey,
+    wallet: Pubkey,
+    dapp: Pubkey,
+    address: Pubkey,
+}
+
+fn approval(runtime: &mut Runtime) -> Approval {
+    let (owner, wallet) = runtime.create_wallet(2 * LAMPORTS_PER_SOL);
+    let dapp = Pubkey::new_unique();
+    let address = runtime.approve(&owner, &dapp, &Pubkey::default(), MAX_AMOUNT);
+    Approval { owner, wallet, dapp, address }
+}
+
+/// The fields of an `UpdateApproval`, all left unchanged by default
+#[derive(Default)]
+struct Changes {
+    max_amount: Option<u64>,
+    expiry: Option<i64>,
+    recipients: Option<Vec<Pubkey>>,
+    is_approved: Option<bool>,
+    valid_from: Option<i64>,
+    max_executions: Option<u64>,
+    single_use: Option<bool>,
+    auto_limit: Option<u64>,
+    cosign_limit: Option<u64>,
+}
+
+fn update(runtime: &mut Runtime, approval: &Approval, owner: &Pubkey, changes: Changes) -> Result<(), ProgramError> {
+    let metas = [
+        AccountMeta::new(*owner, true),
+        AccountMeta::new_readonly(approval.wallet, false),
+        AccountMeta::new_readonly(approval.dapp, false),
+        AccountMeta::new(approval.address, false),
+        AccountMeta::new_readonly(system_program::ID, false),
+    ];
+    let instruction = WalletInstruction::UpdateApproval {
+        max_amount: changes.max_amount,
+        expiry: changes.expiry,
+        recipients: changes.recipients,
+        is_approved: changes.is_approved,
+        valid_from: changes.valid_from,
+        max_executions: changes.max_executions,
+        single_use: changes.single_use,
+        usd_limit: None,
+        auto_limit: changes.auto_limit,
+        cosign_limit: changes.cosign_limit,
+    };
+    runtime.process(&metas, &instruction.pack())
+}
+
+fn pay(runtime: &mut Runtime, approval: &Approval, recipient: &Pubkey, amount: u64) -> Result<(), ProgramError> {
+    let program_id = runtime.program_id;
+    runtime.execute(&client::execute_transaction(&program_id, &approval.dapp, &approval.owner, recipient, amount, None, None))
+}
+
+#[test]
+fn updates_keep_usage_and_unchanged_fields() {
+    let mut runtime = Runtime::new();
+    let approval = approval(&mut runtime);
+    pay(&mut runtime, &approval, &Pubkey::new_unique(), 100).unwrap();
+
+    let changes = Changes { max_amount: Some(2 * MAX_AMOUNT), max_executions: Some(2), ..Changes::default() };
+    update(&mut runtime, &approval, &approval.owner, changes).unwrap();
+    let state: DAppApproval = runtime.state(&approval.address);
+    assert_eq!(u64::from(state.max_amount), 2 * MAX_AMOUNT);
+    assert_eq!(u64::from(state.max_executions), 2);
+    assert_eq!(i64::from(state.expiry), NOW + 86_400);
+    assert_eq!(u64::from(state.spent_amount), 100);
+    assert_eq!(u64::from(state.execution_count), 1);
+
+    // Indexers see the approval as it now stands
+    match take_events().as_slice() {
+        [WalletEvent::ApprovalUpdated { approval: address, max_amount, spent_amount, execution_count, .. }] => {
+            assert_eq!(*address, approval.address);
+            assert_eq!((*max_amount, *spent_amount, *execution_count), (2 * MAX_AMOUNT, 100, 1));
+        }
+        events => panic!("unexpected events {:?}", events),
+    }
+
+    // One execution is left
+    pay(&mut runtime, &approval, &Pubkey::new_unique(), 100).unwrap();
+    assert_eq!(pay(&mut runtime, &approval, &Pubkey::new_unique(), 100), Err(WalletErrorCode::ExecutionLimitReached.into()));
+}
+
+#[test]
+fn approving_again_leaves_the_approval_to_update_approval() {
+    let mut runtime = Runtime::new();
+    let approval = approval(&mut runtime);
+    pay(&mut runtime, &approval, &Pubkey::new_unique(), 100).unwrap();
+
+    // Re-approving would reset the usage counters, so it's refused
+    let program_id = runtime.program_id;
+    let reapprove = client::approve_dapp(&program_id, &approval.owner, &approval.dapp, &Pubkey::default(), 2 * MAX_AMOUNT, NOW + 1);
+    assert_eq!(runtime.execute(&reapprove), Err(ProgramError::AccountAlreadyInitialized));
+    let state: DAppApproval = runtime.state(&approval.address);
+    assert_eq!(u64::from(state.max_amount), MAX_AMOUNT);
+    assert_eq!(i64::from(state.expiry), NOW + 86_400);
+    assert_eq!(u64::from(state.spent_amount), 100);
+    assert_eq!(u64::from(state.execution_count), 1);
+}
+
+#[test]
+fn updates_pause_and_delay_the_approval() {
+    let mut runtime = Runtime::new();
+    let approval = approval(&mut runtime);
+    let recipient = Pubkey::new_unique();
+
+    update(&mut runtime, &approval, &approval.owner, Changes { is_approved: Some(false), ..Changes::default() }).unwrap();
+    assert_eq!(pay(&mut runtime, &approval, &recipient, 1), Err(WalletErrorCode::ApprovalRevoked.into()));
+
+    let changes = Changes { is_approved: Some(true), valid_from: Some(NOW + 1), ..Changes::default() };
+    update(&mut runtime, &approval, &approval.owner, changes).unwrap();
+    assert_eq!(pay(&mut runtime, &approval, &recipient, 1), Err(WalletErrorCode::ApprovalNotYetValid.into()));
+
+    update(&mut runtime, &approval, &approval.owner, Changes { valid_from: Some(0), ..Changes::default() }).unwrap();
+    pay(&mut runtime, &approval, &recipient, 1).unwrap();
+
+    update(&mut runtime, &approval, &approval.owner, Changes { expiry: Some(NOW), ..Changes::default() }).unwrap();
+    assert_eq!(pay(&mut runtime, &approval, &recipient, 1), Err(WalletErrorCode::ApprovalExpired.into()));
+}
+
+#[test]
+fn single_use_approvals_close_after_one_execution() {
+    let mut runtime = Runtime::new();
+    let approval = approval(&mut runtime);
+    update(&mut runtime, &approval, &approval.owner, Changes { single_use: Some(true), ..Changes::default() }).unwrap();
+    let rent = runtime.lamports(&approval.address);
+    let owner_lamports = runtime.lamports(&approval.owner);
+
+    // The approval's rent goes back to the owner, not to the dApp that used it
+    let recipient = Pubkey::new_unique();
+    pay(&mut runtime, &approval, &recipient, 100).unwrap();
+    assert_eq!(runtime.lamports(&recipient), 100);
+    assert!(!runtime.exists(&approval.address));
+    assert_eq!(runtime.lamports(&approval.owner), owner_lamports + rent);
+
+    assert_eq!(pay(&mut runtime, &approval, &recipient, 100), Err(ProgramError::IllegalOwner));
+    assert_eq!(runtime.lamports(&recipient), 100);
+}
+
+#[test]
+fn recipient_allowlists_restrict_and_can_be_lifted() {
+    let mut runtime = Runtime::new();
+    let approval = approval(&mut runtime);
+    let allowed = Pubkey::new_unique();
+
+    let too_many = vec![Pubkey::new_unique(); MAX_APPROVAL_RECIPIENTS + 1];
+    assert_eq!(
+        update(&mut runtime, &approval, &approval.owner, Changes { recipients: Some(too_many), ..Changes::default() }),
+        Err(ProgramError::InvalidArgument)
+    );
+
+    let changes = Changes { recipients: Some(vec![allowed]), ..Changes::default() };
+    update(&mut runtime, &approval, &approval.owner, changes).unwrap();
+    pay(&mut runtime, &approval, &allowed, 1).unwrap();
+    assert_eq!(pay(&mut runtime, &approval, &Pubkey::new_unique(), 1), Err(WalletErrorCode::RecipientNotAllowed.into()));
+
+    update(&mut runtime, &approval, &approval.owner, Changes { recipients: Some(vec![]), ..Changes::default() }).unwrap();
+    pay(&mut runtime, &approval, &Pubkey::new_unique(), 1).unwrap();
+}
+
+#[test]
+fn co_signing_limits_must_nest() {
+    let mut runtime = Runtime::new();
+    let approval = approval(&mut runtime);
+    let limits = |auto_limit, cosign_limit| Changes {
+        auto_limit: Some(auto_limit),
+        cosign_limit: Some(cosign_limit),
+        ..Changes::default()
+    };
+
+    assert_eq!(update(&mut runtime, &approval, &approval.owner, limits(200, 100)), Err(ProgramError::InvalidArgument));
+    assert_eq!(update(&mut runtime, &approval, &approval.owner, limits(100, MAX_AMOUNT + 1)), Err(ProgramError::InvalidArgument));
+    update(&mut runtime, &approval, &approval.owner, limits(100, MAX_AMOUNT)).unwrap();
+
+    // Lowering the allowance below the co-sign limit would break the nesting too
+    assert_eq!(
+        update(&mut runtime, &approval, &approval.owner, Changes { max_amount: Some(MAX_AMOUNT - 1), ..Changes::default() }),
+        Err(ProgramError::InvalidArgument)
+    );
+
+    // With co-signing off the auto limit isn't checked
+    update(&mut runtime, &approval, &approval.owner, limits(u64::MAX, 0)).unwrap();
+}
+
+#[test]
+fn only_the_owner_updates_their_approval() {
+    let mut runtime = Runtime::new();
+    let approval = approval(&mut runtime);
+    let (other_owner, _) = runtime.create_wallet(0);
+    let changes = || Changes { max_amount: Some(u64::MAX), ..Changes::default() };
+
+    assert_eq!(update(&mut runtime, &approval, &other_owner, changes()), Err(ProgramError::InvalidAccountData));
+
+    // The approval has to be the one of the given dApp
+    let other_dapp = Approval { dapp: Pubkey::new_unique(), ..approval };
+    assert_eq!(update(&mut runtime, &other_dapp, &other_dapp.owner, changes()), Err(ProgramError::InvalidAccountData));
+    let state: DAppApproval = runtime.state(&other_dapp.address);
+    assert_eq!(u64::from(state.max_amount), MAX_AMOUNT);
+}
@@ -0,0 +1,132 @@
+//! USD limits: transfers valued with the approval's price feed, which has to be the right
+//! account, fresh and certain enough
+
+mod common;
+
+use smart_contract::{
+    client,
+    error::WalletErrorCode,
+    instruction::WalletInstruction,
+    price::mock_price_account_data,
+    state::UsdLimit,
+};
+use solana_program::{
+    instruction::AccountMeta, native_token::LAMPORTS_PER_SOL, program_error::ProgramError, pubkey::Pubkey,
+    system_program,
+};
+
+use crate::common::{set_time, Runtime, NOW};
+
+/// $150.00000000 per SOL
+const PRICE: i64 = 15_000_000_000;
+const EXPO: i32 = -8;
+/// $15 in micro-dollars, the value of 0.1 SOL
+const MAX_USD: u64 = 15_000_000;
+
+struct Limited {
+    owner: Pubkey,
+    dapp: Pubkey,
+    feed: Pubkey,
+}
+
+/// A SOL approval whose USD limit is worth 0.1 SOL, well under its `max_amount`
+fn limited(runtime: &mut Runtime) -> Limited {
+    let (owner, wallet) = runtime.create_wallet(10 * LAMPORTS_PER_SOL);
+    let dapp = Pubkey::new_unique();
+    let approval = runtime.approve(&owner, &dapp, &Pubkey::default(), LAMPORTS_PER_SOL);
+    let feed = runtime.add_price_feed(PRICE, EXPO);
+
+    let metas = [
+        AccountMeta::new(owner, true),
+        AccountMeta::new_readonly(wallet, false),
+        AccountMeta::new_readonly(dapp, false),
+        AccountMeta::new(approval, false),
+        AccountMeta::new_readonly(system_program::ID, false),
+    ];
+    let instruction = WalletInstruction::UpdateApproval {
+        max_amount: None,
+        expiry: None,
+        recipients: None,
+        is_approved: None,
+        valid_from: None,
+        max_executions: None,
+        single_use: None,
+        usd_limit: Some(Some(UsdLimit { price_feed: feed, max_usd: MAX_USD })),
+        auto_limit: None,
+        cosign_limit: None,
+    };
+    runtime.process(&metas, &instruction.pack()).unwrap();
+    Limited { owner, dapp, feed }
+}
+
+impl Limited {
+    fn pay(&self, runtime: &mut Runtime, recipient: &Pubkey, amount: u64, feed: &Pubkey) -> Result<(), ProgramError> {
+        let program_id = runtime.program_id;
+        runtime.execute(&client::execute_transaction(&program_id, &self.dapp, &self.owner, recipient, amount, Some(feed), None))
+    }
+}
+
+#[test]
+fn transfers_up_to_the_usd_limit_go_through() {
+    let mut runtime = Runtime::new();
+    let limited = limited(&mut runtime);
+    let recipient = Pubkey::new_unique();
+
+    limited.pay(&mut runtime, &recipient, LAMPORTS_PER_SOL / 10, &limited.feed).unwrap();
+    assert_eq!(runtime.lamports(&recipient), LAMPORTS_PER_SOL / 10);
+
+    // One lamport more is worth a fraction of a micro-dollar, which is rounded up
+    assert_eq!(
+        limited.pay(&mut runtime, &recipient, LAMPORTS_PER_SOL / 10 + 1, &limited.feed),
+        Err(WalletErrorCode::UsdLimitExceeded.into())
+    );
+    assert_eq!(runtime.lamports(&recipient), LAMPORTS_PER_SOL / 10);
+}
+
+#[test]
+fn stale_prices_are_refused() {
+    let mut runtime = Runtime::new();
+    let limited = limited(&mut runtime);
+    let recipient = Pubkey::new_unique();
+
+    set_time(NOW + 60);
+    limited.pay(&mut runtime, &recipient, 100, &limited.feed).unwrap();
+    set_time(NOW + 61);
+    assert_eq!(limited.pay(&mut runtime, &recipient, 100, &limited.feed), Err(ProgramError::InvalidAccountData));
+    assert_eq!(runtime.lamports(&recipient), 100);
+}
+
+#[test]
+fn uncertain_prices_are_refused() {
+    let mut runtime = Runtime::new();
+    let limited = limited(&mut runtime);
+    let recipient = Pubkey::new_unique();
+
+    // Transfers are valued at the top of the confidence interval, which is at most 2% wide
+    let conf = PRICE as u64 / 50;
+    runtime.accounts.get_mut(&limited.feed).unwrap().data = mock_price_account_data(PRICE, conf, EXPO, NOW);
+    assert_eq!(
+        limited.pay(&mut runtime, &recipient, LAMPORTS_PER_SOL / 10, &limited.feed),
+        Err(WalletErrorCode::UsdLimitExceeded.into())
+    );
+    limited.pay(&mut runtime, &recipient, LAMPORTS_PER_SOL / 100, &limited.feed).unwrap();
+
+    runtime.accounts.get_mut(&limited.feed).unwrap().data = mock_price_account_data(PRICE, conf + 1, EXPO, NOW);
+    assert_eq!(limited.pay(&mut runtime, &recipient, 100, &limited.feed), Err(ProgramError::InvalidAccountData));
+    assert_eq!(runtime.lamports(&recipient), LAMPORTS_PER_SOL / 100);
+}
+
+#[test]
+fn only_the_approvals_price_feed_is_accepted() {
+    let mut runtime = Runtime::new();
+    let limited = limited(&mut runtime);
+    let recipient = Pubkey::new_unique();
+
+    // A feed quoting a lower price would let more through
+    let other_feed = runtime.add_price_feed(PRICE / 100, EXPO);
+    assert_eq!(
+        limited.pay(&mut runtime, &recipient, LAMPORTS_PER_SOL / 2, &other_feed),
+        Err(ProgramError::InvalidAccountData)
+    );
+    assert_eq!(runtime.lamports(&recipient), 0);
+}
@@ -0,0 +1,149 @@
+//! `WrapSol` and `UnwrapSol`: moving the wallet's lamports in and out of its native-mint token
+//! account, which native-mint approvals also top up from the wallet's lamports
+
+mod common;
+
+use smart_contract::{
+    client::{self, TokenTransfer},
+    instruction::WalletInstruction,
+};
+use solana_program::{
+    instruction::AccountMeta, native_token::LAMPORTS_PER_SOL, program_error::ProgramError, pubkey::Pubkey, rent::Rent,
+    system_program,
+};
+use spl_associated_token_account::get_associated_token_address_with_program_id;
+
+use crate::common::{Runtime, TRANSACTION_FEE};
+
+struct Wallet {
+    owner: Pubkey,
+    wallet: Pubkey,
+    native_mint: Pubkey,
+    wallet_token: Pubkey,
+    token_program: Pubkey,
+}
+
+fn wallet(runtime: &mut Runtime, token_program: Pubkey, lamports: u64) -> Wallet {
+    let (owner, wallet) = runtime.create_wallet(lamports);
+    let native_mint = runtime.add_native_mint(&token_program);
+    let wallet_token = get_associated_token_address_with_program_id(&wallet, &native_mint, &token_program);
+    Wallet { owner, wallet, native_mint, wallet_token, token_program }
+}
+
+fn wrap(runtime: &mut Runtime, wallet: &Wallet, signer: &Pubkey, amount: u64) -> Result<(), ProgramError> {
+    let metas = [
+        AccountMeta::new(*signer, true),
+        AccountMeta::new(wallet.wallet, false),
+        AccountMeta::new(wallet.wallet_token, false),
+        AccountMeta::new_readonly(wallet.native_mint, false),
+        AccountMeta::new_readonly(wallet.token_program, false),
+        AccountMeta::new_readonly(system_program::ID, false),
+        AccountMeta::new_readonly(spl_associated_token_account::id(), false),
+    ];
+    runtime.process(&metas, &WalletInstruction::WrapSol { amount }.pack())
+}
+
+fn unwrap(runtime: &mut Runtime, wallet: &Wallet, signer: &Pubkey) -> Result<(), ProgramError> {
+    let metas = [
+        AccountMeta::new(*signer, true),
+        AccountMeta::new(wallet.wallet, false),
+        AccountMeta::new(wallet.wallet_token, false),
+        AccountMeta::new_readonly(wallet.token_program, false),
+    ];
+    runtime.process(&metas, &WalletInstruction::UnwrapSol.pack())
+}
+
+#[test]
+fn wrapping_creates_the_token_account_and_unwrapping_closes_it() {
+    for token_program in [spl_token::id(), spl_token_2022::id()] {
+        let mut runtime = Runtime::new();
+        let wallet = wallet(&mut runtime, token_program, LAMPORTS_PER_SOL);
+        let wallet_before = runtime.lamports(&wallet.wallet);
+        let owner_before = runtime.lamports(&wallet.owner);
+
+        // The owner pays the token account's rent, the wallet only the wrapped amount
+        wrap(&mut runtime, &wallet, &wallet.owner, LAMPORTS_PER_SOL / 2).unwrap();
+        assert_eq!(runtime.token_balance(&wallet.wallet_token), LAMPORTS_PER_SOL / 2);
+        assert_eq!(runtime.lamports(&wallet.wallet), wallet_before - LAMPORTS_PER_SOL / 2);
+        let token_rent = runtime.lamports(&wallet.wallet_token) - LAMPORTS_PER_SOL / 2;
+        assert_eq!(runtime.lamports(&wallet.owner), owner_before - token_rent);
+
+        // Wrapping into the existing account tops it up
+        wrap(&mut runtime, &wallet, &wallet.owner, LAMPORTS_PER_SOL / 4).unwrap();
+        assert_eq!(runtime.token_balance(&wallet.wallet_token), 3 * LAMPORTS_PER_SOL / 4);
+
+        // The token account's rent goes to the wallet along with the wrapped SOL
+        unwrap(&mut runtime, &wallet, &wallet.owner).unwrap();
+        assert!(!runtime.exists(&wallet.wallet_token));
+        assert_eq!(runtime.lamports(&wallet.wallet), wallet_before + token_rent);
+    }
+}
+
+#[test]
+fn wrapping_leaves_the_wallet_rent_exempt() {
+    let mut runtime = Runtime::new();
+    let wallet = wallet(&mut runtime, spl_token::id(), LAMPORTS_PER_SOL);
+
+    assert_eq!(wrap(&mut runtime, &wallet, &wallet.owner, LAMPORTS_PER_SOL + 1), Err(ProgramError::InsufficientFunds));
+    assert!(!runtime.exists(&wallet.wallet_token));
+
+    wrap(&mut runtime, &wallet, &wallet.owner, LAMPORTS_PER_SOL).unwrap();
+    let wallet_rent = Rent::default().minimum_balance(runtime.accounts[&wallet.wallet].data.len());
+    assert_eq!(runtime.lamports(&wallet.wallet), wallet_rent);
+}
+
+#[test]
+fn only_the_owner_wraps_and_unwraps() {
+    let mut runtime = Runtime::new();
+    let wallet = wallet(&mut runtime, spl_token::id(), LAMPORTS_PER_SOL);
+    let (other_owner, _) = runtime.create_wallet(0);
+
+    assert_eq!(wrap(&mut runtime, &wallet, &other_owner, 1), Err(ProgramError::InvalidAccountData));
+    wrap(&mut runtime, &wallet, &wallet.owner, 1).unwrap();
+    assert_eq!(unwrap(&mut runtime, &wallet, &other_owner), Err(ProgramError::InvalidAccountData));
+}
+
+#[test]
+fn only_the_native_mint_account_is_used() {
+    let mut runtime = Runtime::new();
+    let wallet = wallet(&mut runtime, spl_token::id(), LAMPORTS_PER_SOL);
+
+    // The native mint of the other token program
+    let mut mismatched = Wallet { native_mint: runtime.add_native_mint(&spl_token_2022::id()), ..wallet };
+    assert_eq!(wrap(&mut runtime, &mismatched, &mismatched.owner, 1), Err(ProgramError::InvalidAccountData));
+
+    // A token account other than the wallet's associated one
+    mismatched.native_mint = spl_token::native_mint::id();
+    mismatched.wallet_token = runtime.add_token_account(&spl_token::id(), &mismatched.native_mint, &Pubkey::new_unique(), 0);
+    assert_eq!(wrap(&mut runtime, &mismatched, &mismatched.owner, 1), Err(ProgramError::InvalidAccountData));
+    assert_eq!(unwrap(&mut runtime, &mismatched, &mismatched.owner), Err(ProgramError::InvalidAccountData));
+
+    // A program that isn't a token program
+    let mismatched = Wallet { token_program: Pubkey::new_unique(), ..mismatched };
+    assert_eq!(unwrap(&mut runtime, &mismatched, &mismatched.owner), Err(ProgramError::IncorrectProgramId));
+}
+
+#[test]
+fn native_mint_approvals_wrap_the_shortfall_into_the_wallets_own_account() {
+    let mut runtime = Runtime::new();
+    let wallet = wallet(&mut runtime, spl_token::id(), LAMPORTS_PER_SOL);
+    runtime.add_token_account(&spl_token::id(), &wallet.native_mint, &wallet.wallet, 0);
+    let recipient = runtime.add_token_account(&spl_token::id(), &wallet.native_mint, &Pubkey::new_unique(), 0);
+    let dapp = Pubkey::new_unique();
+    runtime.approve(&wallet.owner, &dapp, &wallet.native_mint, LAMPORTS_PER_SOL);
+    let program_id = runtime.program_id;
+    let send = |runtime: &mut Runtime, wallet_token_account: Pubkey| {
+        let token = TokenTransfer { mint: wallet.native_mint, wallet_token_account, token_program: wallet.token_program };
+        runtime.execute(&client::execute_transaction(&program_id, &dapp, &wallet.owner, &recipient, 1000, None, Some(&token)))
+    };
+    let wallet_before = runtime.lamports(&wallet.wallet);
+
+    // Someone else's native-mint account would be funded with the wallet's lamports
+    let foreign = runtime.add_token_account(&spl_token::id(), &wallet.native_mint, &Pubkey::new_unique(), 0);
+    assert_eq!(send(&mut runtime, foreign), Err(ProgramError::InvalidAccountData));
+
+    send(&mut runtime, wallet.wallet_token).unwrap();
+    assert_eq!(runtime.token_balance(&recipient), 1000);
+    assert_eq!(runtime.token_balance(&wallet.wallet_token), 0);
+    assert_eq!(runtime.lamports(&wallet.wallet), wallet_before - 1000 - TRANSACTION_FEE);
+}
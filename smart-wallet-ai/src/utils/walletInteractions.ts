@@ -9,6 +9,9 @@ export enum TransferType {
     Token = 1,
 }
 
+// Version of the program's instruction encoding, sent before the instruction type
+export const INSTRUCTION_VERSION = 1;
+
 // Define instruction types
 export enum WalletInstructionType {
     CreateWallet = 0,
@@ -40,8 +43,9 @@ export function executeTransactionInstruction(amount: bigint, transferType: Tran
 }
 
 export function serializeWalletInstruction(instruction: { type: WalletInstructionType; data: any }): Uint8Array {
-    let buffer = Buffer.alloc(1);
-    buffer.writeUInt8(instruction.type, 0);
+    let buffer = Buffer.alloc(2);
+    buffer.writeUInt8(INSTRUCTION_VERSION, 0);
+    buffer.writeUInt8(instruction.type, 1);
 
     const serializedData = serialize(
         InstructionSchema.ExecuteTransactionInstruction,
@@ -7,6 +7,9 @@ export enum TransferType {
     Token = 1,
 }
 
+// Version of the program's instruction encoding, sent before the instruction type
+export const INSTRUCTION_VERSION = 1;
+
 // Define instruction types
 export enum WalletInstructionType {
     CreateWallet = 0,
@@ -71,9 +74,10 @@ export function executeTransactionInstruction(amount: bigint, transferType: Tran
 export function serializeWalletInstruction(instruction: WalletInstruction): Uint8Array {
     let buffer: Buffer;
 
-    // Create a buffer for the instruction type
-    buffer = Buffer.alloc(1); // 1 byte for the type
-    buffer.writeUInt8(instruction.type, 0);
+    // Create a buffer for the encoding version and the instruction type
+    buffer = Buffer.alloc(2);
+    buffer.writeUInt8(INSTRUCTION_VERSION, 0);
+    buffer.writeUInt8(instruction.type, 1);
 
     // Serialize the data according to its type using Borsh
     let serializedData: Uint8Array;
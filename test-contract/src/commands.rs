@@ -0,0 +1,453 @@
+//! The subcommands, run against an RPC node with the signer's keypair
+
+use std::{collections::HashMap, error::Error, future, path::Path};
+
+use futures::executor::block_on;
+use smart_contract::{
+    client::{self, SimulationOutcome, TokenTransfer},
+    state::{derive_approval_address, derive_wallet_address, DAppApproval},
+};
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::{
+    rpc_client::RpcClient,
+    rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig},
+    rpc_filter::{Memcmp, RpcFilterType},
+};
+use solana_sdk::{
+    commitment_config::CommitmentConfig,
+    instruction::Instruction,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    signature::{read_keypair_file, Keypair, Signature, Signer},
+    system_instruction,
+    transaction::{Transaction, TransactionError},
+};
+use spl_associated_token_account::{
+    get_associated_token_address_with_program_id, instruction::create_associated_token_account_idempotent,
+};
+use spl_token_2022::{
+    extension::StateWithExtensions,
+    offchain::{resolve_extra_transfer_account_metas, AccountDataResult},
+    state::{Account as TokenAccount, Mint},
+};
+
+use crate::format::{format_amount, format_time, now, parse_amount, SOL_DECIMALS};
+
+type CommandResult = Result<(), Box<dyn Error>>;
+
+/// Addresses and data of the accounts returned by `getProgramAccounts`
+type KeyedAccounts = Vec<(Pubkey, Vec<u8>)>;
+
+/// Offset of the owner in a token account
+const TOKEN_ACCOUNT_OWNER_OFFSET: usize = 32;
+
+/// A mint and what is needed to move its tokens
+struct TokenInfo {
+    mint: Pubkey,
+    token_program: Pubkey,
+    decimals: u8,
+}
+
+pub struct Context {
+    client: RpcClient,
+    signer: Keypair,
+    program_id: Pubkey,
+}
+
+impl Context {
+    pub fn new(url: &str, keypair_path: &Path, program_id: Pubkey) -> Result<Self, Box<dyn Error>> {
+        let signer = read_keypair_file(keypair_path)
+            .map_err(|error| format!("Failed to read keypair {}: {}", keypair_path.display(), error))?;
+        Ok(Self {
+            client: RpcClient::new_with_commitment(url.to_string(), CommitmentConfig::confirmed()),
+            signer,
+            program_id,
+        })
+    }
+
+    fn send(&self, instructions: &[Instruction]) -> Result<Signature, Box<dyn Error>> {
+        let blockhash = self.client.get_latest_blockhash()?;
+        let transaction = Transaction::new_signed_with_payer(
+            instructions,
+            Some(&self.signer.pubkey()),
+            &[&self.signer],
+            blockhash,
+        );
+        let units = self.simulate(&transaction, instructions)?;
+        let signature = self.client.send_and_confirm_transaction(&transaction)?;
+        println!("Signature: {} ({} compute units)", signature, units);
+        Ok(signature)
+    }
+
+    /// Simulates `transaction` before it is sent, so wallet failures are reported with their
+    /// reason, and returns the compute units it consumes
+    fn simulate(&self, transaction: &Transaction, instructions: &[Instruction]) -> Result<u64, Box<dyn Error>> {
+        Ok(client::check_simulation(&self.program_id, instructions, |_| {
+            let response = self.client.simulate_transaction(transaction)?.value;
+            Ok::<_, Box<dyn Error>>(SimulationOutcome {
+                instruction_error: match &response.err {
+                    Some(TransactionError::InstructionError(index, error)) => Some((*index, error.clone())),
+                    _ => None,
+                },
+                transaction_error: response.err.map(|error| error.to_string()),
+                logs: response.logs.unwrap_or_default(),
+                units_consumed: response.units_consumed,
+            })
+        })?)
+    }
+
+    fn account_data(&self, address: &Pubkey) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        let response = self.client.get_account_with_commitment(address, self.client.commitment())?;
+        Ok(response.value.map(|account| account.data))
+    }
+
+    /// Account data for resolving transfer hook accounts
+    fn fetch_account_data(&self, address: Pubkey) -> future::Ready<AccountDataResult> {
+        future::ready(
+            self.client
+                .get_account_with_commitment(&address, self.client.commitment())
+                .map(|response| response.value.map(|account| account.data))
+                .map_err(Into::into),
+        )
+    }
+
+    fn token_info(&self, mint: &Pubkey) -> Result<TokenInfo, Box<dyn Error>> {
+        let account = self
+            .client
+            .get_account_with_commitment(mint, self.client.commitment())?
+            .value
+            .ok_or_else(|| format!("Mint {} does not exist", mint))?;
+        if account.owner != spl_token::id() && account.owner != spl_token_2022::id() {
+            return Err(format!("{} is not a token mint", mint).into());
+        }
+        let state = StateWithExtensions::<Mint>::unpack(&account.data)?;
+        Ok(TokenInfo {
+            mint: *mint,
+            token_program: account.owner,
+            decimals: state.base.decimals,
+        })
+    }
+
+    fn decimals(&self, mint: Option<&Pubkey>) -> Result<u8, Box<dyn Error>> {
+        match mint {
+            Some(mint) => Ok(self.token_info(mint)?.decimals),
+            None => Ok(SOL_DECIMALS),
+        }
+    }
+
+    fn wallet_address(&self, owner: &Pubkey) -> Pubkey {
+        derive_wallet_address(owner, &self.program_id).0
+    }
+
+    /// The wallet's token account for `token` and the instruction creating the recipient's,
+    /// paid for by the signer
+    fn token_transfer(&self, wallet: &Pubkey, token: &TokenInfo, recipient: &Pubkey) -> (TokenTransfer, Pubkey, Instruction) {
+        let transfer = TokenTransfer {
+            mint: token.mint,
+            wallet_token_account: get_associated_token_address_with_program_id(wallet, &token.mint, &token.token_program),
+            token_program: token.token_program,
+        };
+        let recipient_token_account = get_associated_token_address_with_program_id(recipient, &token.mint, &token.token_program);
+        let create_recipient_account = create_associated_token_account_idempotent(
+            &self.signer.pubkey(),
+            recipient,
+            &token.mint,
+            &token.token_program,
+        );
+        (transfer, recipient_token_account, create_recipient_account)
+    }
+
+    /// Sends a wallet instruction that moves `amount` of `token` out of `wallet` to `recipient`,
+    /// creating the recipient's token account first if needed
+    fn send_from_wallet<F>(&self, wallet: &Pubkey, recipient: &Pubkey, amount: u64, token: Option<&TokenInfo>, build: F) -> CommandResult
+    where
+        F: FnOnce(&Pubkey, Option<&TokenTransfer>) -> Instruction,
+    {
+        let Some(token) = token else {
+            self.send(&[build(recipient, None)])?;
+            return Ok(());
+        };
+
+        let (transfer, recipient_token_account, create_recipient_account) = self.token_transfer(wallet, token, recipient);
+        let mut instruction = build(&recipient_token_account, Some(&transfer));
+        block_on(client::add_transfer_hook_accounts(
+            &mut instruction,
+            |address| self.fetch_account_data(address),
+            &transfer.wallet_token_account,
+            &token.mint,
+            &recipient_token_account,
+            wallet,
+            amount,
+        ))
+        .map_err(|error| format!("Failed to resolve transfer hook accounts: {}", error))?;
+        self.send(&[create_recipient_account, instruction])?;
+        Ok(())
+    }
+
+    pub fn create(&self) -> CommandResult {
+        let owner = self.signer.pubkey();
+        let wallet = self.wallet_address(&owner);
+        if self.account_data(&wallet)?.is_some() {
+            println!("Wallet {} already exists", wallet);
+            return Ok(());
+        }
+
+        self.send(&[client::create_wallet(&self.program_id, &owner)])?;
+        println!("Created wallet {} for {}", wallet, owner);
+        Ok(())
+    }
+
+    pub fn approve(&self, dapp: &Pubkey, amount: &str, expires_in: i64, mint: Option<Pubkey>) -> CommandResult {
+        let decimals = self.decimals(mint.as_ref())?;
+        let max_amount = parse_amount(amount, decimals)?;
+        let expiry = now() + expires_in;
+        let token_mint = mint.unwrap_or_default();
+
+        self.send(&[client::approve_dapp(&self.program_id, &self.signer.pubkey(), dapp, &token_mint, max_amount, expiry)])?;
+        println!(
+            "Approved {} to spend up to {} {} per transaction until {}",
+            dapp,
+            format_amount(max_amount, decimals),
+            asset_name(&token_mint),
+            format_time(expiry)
+        );
+        Ok(())
+    }
+
+    pub fn revoke(&self, dapp: &Pubkey, mint: Option<Pubkey>) -> CommandResult {
+        let owner = self.signer.pubkey();
+        let token_mint = mint.unwrap_or_default();
+        let (approval, _) = derive_approval_address(&self.wallet_address(&owner), dapp, &token_mint, &self.program_id);
+        if self.account_data(&approval)?.is_none() {
+            return Err(format!("{} has no {} approval from this wallet", dapp, asset_name(&token_mint)).into());
+        }
+
+        self.send(&[client::set_approval_status(&self.program_id, &owner, dapp, &token_mint, false)])?;
+        println!("Revoked the {} approval of {}", asset_name(&token_mint), dapp);
+        Ok(())
+    }
+
+    pub fn list_approvals(&self, owner: Option<Pubkey>) -> CommandResult {
+        let owner = owner.unwrap_or_else(|| self.signer.pubkey());
+        let wallet = self.wallet_address(&owner);
+        let filters = client::approvals_for_wallet_filters(&wallet)
+            .into_iter()
+            .map(|filter| RpcFilterType::Memcmp(Memcmp::new_raw_bytes(filter.offset, filter.bytes)))
+            .collect();
+        let mut approvals: Vec<DAppApproval> = self
+            .get_program_accounts(&self.program_id, filters)?
+            .into_iter()
+            .filter_map(|(_, data)| client::decode_approval(&data).ok())
+            .collect();
+        if approvals.is_empty() {
+            println!("Wallet {} has no approvals", wallet);
+            return Ok(());
+        }
+        approvals.sort_by_key(|approval| i64::from(approval.created_at));
+
+        let mut decimals = HashMap::new();
+        let now = now();
+        println!(
+            "{:<44}  {:<11}  {:>14}  {:>14}  {:>5}  {:<20}  STATUS",
+            "DAPP", "TOKEN", "MAX/TX", "SPENT", "RUNS", "EXPIRES"
+        );
+        for approval in &approvals {
+            let decimals = match decimals.get(&approval.token_mint) {
+                Some(decimals) => *decimals,
+                None => {
+                    let mint = (approval.token_mint != Pubkey::default()).then_some(&approval.token_mint);
+                    *decimals.entry(approval.token_mint).or_insert(self.decimals(mint)?)
+                }
+            };
+            println!(
+                "{:<44}  {:<11}  {:>14}  {:>14}  {:>5}  {:<20}  {}",
+                approval.dapp.to_string(),
+                short_asset_name(&approval.token_mint),
+                format_amount(approval.max_amount.into(), decimals),
+                format_amount(approval.spent_amount.into(), decimals),
+                u64::from(approval.execution_count),
+                format_time(approval.expiry.into()),
+                approval_status(approval, now)
+            );
+        }
+        Ok(())
+    }
+
+    pub fn execute(&self, owner: &Pubkey, recipient: &Pubkey, amount: &str, mint: Option<Pubkey>) -> CommandResult {
+        let dapp = self.signer.pubkey();
+        let wallet = self.wallet_address(owner);
+        let token = mint.as_ref().map(|mint| self.token_info(mint)).transpose()?;
+        let decimals = token.as_ref().map_or(SOL_DECIMALS, |token| token.decimals);
+        let amount = parse_amount(amount, decimals)?;
+        let token_mint = mint.unwrap_or_default();
+
+        let (approval, _) = derive_approval_address(&wallet, &dapp, &token_mint, &self.program_id);
+        let approval = self
+            .account_data(&approval)?
+            .ok_or_else(|| format!("{} has no {} approval from {}'s wallet", dapp, asset_name(&token_mint), owner))?;
+        let price_feed = client::decode_approval(&approval)?.usd_limit().map(|limit| limit.price_feed);
+
+        self.send_from_wallet(&wallet, recipient, amount, token.as_ref(), |recipient, transfer| {
+            client::execute_transaction(&self.program_id, &dapp, owner, recipient, amount, price_feed.as_ref(), transfer)
+        })?;
+        println!(
+            "Sent {} {} from {}'s wallet to {}",
+            format_amount(amount, decimals),
+            asset_name(&token_mint),
+            owner,
+            recipient
+        );
+        Ok(())
+    }
+
+    pub fn withdraw(&self, amount: &str, to: Option<Pubkey>, mint: Option<Pubkey>) -> CommandResult {
+        let owner = self.signer.pubkey();
+        let wallet = self.wallet_address(&owner);
+        let recipient = to.unwrap_or(owner);
+        let token = mint.as_ref().map(|mint| self.token_info(mint)).transpose()?;
+        let decimals = token.as_ref().map_or(SOL_DECIMALS, |token| token.decimals);
+        let amount = parse_amount(amount, decimals)?;
+
+        self.send_from_wallet(&wallet, &recipient, amount, token.as_ref(), |recipient, transfer| {
+            client::withdraw(&self.program_id, &owner, recipient, amount, transfer)
+        })?;
+        println!(
+            "Withdrew {} {} to {}",
+            format_amount(amount, decimals),
+            asset_name(&mint.unwrap_or_default()),
+            recipient
+        );
+        Ok(())
+    }
+
+    pub fn deposit(&self, amount: &str, mint: Option<Pubkey>) -> CommandResult {
+        let owner = self.signer.pubkey();
+        let wallet = self.wallet_address(&owner);
+        if self.account_data(&wallet)?.is_none() {
+            return Err(format!("Wallet {} does not exist; create it with `smart-wallet create`", wallet).into());
+        }
+
+        let Some(mint) = mint else {
+            let lamports = parse_amount(amount, SOL_DECIMALS)?;
+            self.send(&[system_instruction::transfer(&owner, &wallet, lamports)])?;
+            println!("Deposited {} SOL into {}", format_amount(lamports, SOL_DECIMALS), wallet);
+            return Ok(());
+        };
+
+        let token = self.token_info(&mint)?;
+        let amount = parse_amount(amount, token.decimals)?;
+        let source = get_associated_token_address_with_program_id(&owner, &mint, &token.token_program);
+        let destination = get_associated_token_address_with_program_id(&wallet, &mint, &token.token_program);
+        let create_wallet_account = create_associated_token_account_idempotent(&owner, &wallet, &mint, &token.token_program);
+        let mut transfer_ix = spl_token_2022::instruction::transfer_checked(
+            &token.token_program,
+            &source,
+            &mint,
+            &destination,
+            &owner,
+            &[],
+            amount,
+            token.decimals,
+        )?;
+        block_on(resolve_extra_transfer_account_metas(
+            &mut transfer_ix,
+            |address| self.fetch_account_data(address),
+            &mint,
+        ))
+        .map_err(|error| format!("Failed to resolve transfer hook accounts: {}", error))?;
+
+        self.send(&[create_wallet_account, transfer_ix])?;
+        println!("Deposited {} {} into {}", format_amount(amount, token.decimals), mint, wallet);
+        Ok(())
+    }
+
+    pub fn balance(&self, owner: Option<Pubkey>) -> CommandResult {
+        let owner = owner.unwrap_or_else(|| self.signer.pubkey());
+        let wallet = self.wallet_address(&owner);
+        let Some(account) = self.client.get_account_with_commitment(&wallet, self.client.commitment())?.value else {
+            println!("{} has no wallet; create one with `smart-wallet create`", owner);
+            return Ok(());
+        };
+
+        let reserve = self.client.get_minimum_balance_for_rent_exemption(account.data.len())?;
+        println!("Wallet:     {}", wallet);
+        println!("Owner:      {}", owner);
+        println!("SOL:        {}", format_amount(account.lamports, SOL_DECIMALS));
+        println!("Rent:       {}", format_amount(reserve, SOL_DECIMALS));
+        println!("Spendable:  {}", format_amount(account.lamports.saturating_sub(reserve), SOL_DECIMALS));
+
+        let mut tokens = Vec::new();
+        for token_program in [spl_token::id(), spl_token_2022::id()] {
+            let mut filters = vec![RpcFilterType::Memcmp(Memcmp::new_raw_bytes(
+                TOKEN_ACCOUNT_OWNER_OFFSET,
+                wallet.to_bytes().to_vec(),
+            ))];
+            if token_program == spl_token::id() {
+                filters.push(RpcFilterType::DataSize(TokenAccount::LEN as u64));
+            }
+            for (address, data) in self.get_program_accounts(&token_program, filters)? {
+                if let Ok(state) = StateWithExtensions::<TokenAccount>::unpack(&data) {
+                    tokens.push((address, state.base.mint, state.base.amount));
+                }
+            }
+        }
+        if tokens.is_empty() {
+            return Ok(());
+        }
+
+        println!();
+        println!("{:<44}  {:>20}  ACCOUNT", "MINT", "BALANCE");
+        for (address, mint, amount) in tokens {
+            let decimals = self.token_info(&mint)?.decimals;
+            println!("{:<44}  {:>20}  {}", mint.to_string(), format_amount(amount, decimals), address);
+        }
+        Ok(())
+    }
+
+    fn get_program_accounts(&self, program_id: &Pubkey, filters: Vec<RpcFilterType>) -> Result<KeyedAccounts, Box<dyn Error>> {
+        let config = RpcProgramAccountsConfig {
+            filters: Some(filters),
+            account_config: RpcAccountInfoConfig {
+                encoding: Some(UiAccountEncoding::Base64),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let accounts = self.client.get_program_accounts_with_config(program_id, config)?;
+        Ok(accounts.into_iter().map(|(address, account)| (address, account.data)).collect())
+    }
+}
+
+fn asset_name(token_mint: &Pubkey) -> String {
+    if *token_mint == Pubkey::default() {
+        "SOL".to_string()
+    } else {
+        token_mint.to_string()
+    }
+}
+
+/// `asset_name`, with mints shortened to fit a table column
+fn short_asset_name(token_mint: &Pubkey) -> String {
+    let name = asset_name(token_mint);
+    if name.len() > 11 {
+        format!("{}..{}", &name[..4], &name[name.len() - 4..])
+    } else {
+        name
+    }
+}
+
+/// Mirrors the checks `ExecuteTransaction` makes before looking at the amount
+fn approval_status(approval: &DAppApproval, now: i64) -> &'static str {
+    let max_executions = u64::from(approval.max_executions);
+    if !bool::from(approval.is_approved) {
+        "revoked"
+    } else if now >= i64::from(approval.expiry) {
+        "expired"
+    } else if now < i64::from(approval.valid_from) {
+        "not yet valid"
+    } else if max_executions != 0 && u64::from(approval.execution_count) >= max_executions {
+        "used up"
+    } else {
+        "active"
+    }
+}
@@ -0,0 +1,105 @@
+//! Parsing and display of amounts, durations and times
+
+use chrono::{DateTime, Utc};
+
+pub const SOL_DECIMALS: u8 = 9;
+
+/// Parses a decimal amount such as `1.5` into base units with `decimals` decimals
+pub fn parse_amount(text: &str, decimals: u8) -> Result<u64, String> {
+    let invalid = || format!("Invalid amount `{}`", text);
+    let (whole, fraction) = text.split_once('.').unwrap_or((text, ""));
+    if whole.is_empty() && fraction.is_empty() {
+        return Err(invalid());
+    }
+    if fraction.len() > decimals as usize {
+        return Err(format!("`{}` has more than {} decimals", text, decimals));
+    }
+    if !whole.chars().chain(fraction.chars()).all(|c| c.is_ascii_digit()) {
+        return Err(invalid());
+    }
+
+    let digits = format!("{}{:0<width$}", whole, fraction, width = decimals as usize);
+    let digits = digits.trim_start_matches('0');
+    if digits.is_empty() {
+        return Ok(0);
+    }
+    digits.parse().map_err(|_| format!("Amount `{}` is too large", text))
+}
+
+/// Formats base units with `decimals` decimals, without trailing zeros
+pub fn format_amount(amount: u64, decimals: u8) -> String {
+    if decimals == 0 {
+        return amount.to_string();
+    }
+    let scale = 10u64.pow(decimals as u32);
+    let fraction = format!("{:0width$}", amount % scale, width = decimals as usize);
+    let fraction = fraction.trim_end_matches('0');
+    if fraction.is_empty() {
+        (amount / scale).to_string()
+    } else {
+        format!("{}.{}", amount / scale, fraction)
+    }
+}
+
+/// Parses a duration such as `90`, `45m`, `12h`, `30d` or `2w` into seconds
+pub fn parse_duration(text: &str) -> Result<i64, String> {
+    let (number, unit) = text.split_at(text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len()));
+    let number: i64 = number.parse().map_err(|_| format!("Invalid duration `{}`", text))?;
+    let unit_seconds = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(format!("Unknown duration unit in `{}`; use s, m, h, d or w", text)),
+    };
+    number
+        .checked_mul(unit_seconds)
+        .ok_or_else(|| format!("Duration `{}` is too long", text))
+}
+
+/// Formats a Unix timestamp as a UTC date and time
+pub fn format_time(timestamp: i64) -> String {
+    DateTime::<Utc>::from_timestamp(timestamp, 0)
+        .map(|time| time.format("%Y-%m-%d %H:%M UTC").to_string())
+        .unwrap_or_else(|| timestamp.to_string())
+}
+
+pub fn now() -> i64 {
+    Utc::now().timestamp()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn amounts_parse_into_base_units() {
+        assert_eq!(parse_amount("1.5", SOL_DECIMALS), Ok(1_500_000_000));
+        assert_eq!(parse_amount("0.000000001", SOL_DECIMALS), Ok(1));
+        assert_eq!(parse_amount("42", 0), Ok(42));
+        assert_eq!(parse_amount(".25", 2), Ok(25));
+        assert_eq!(parse_amount("0", 6), Ok(0));
+
+        assert!(parse_amount("0.0000000001", SOL_DECIMALS).unwrap_err().contains("more than 9 decimals"));
+        assert!(parse_amount("18446744073709551616", 0).unwrap_err().contains("too large"));
+        assert!(parse_amount("18446744073.709551616", SOL_DECIMALS).unwrap_err().contains("too large"));
+        for invalid in ["", ".", "1.2.3", "-1", "1e9", " 1"] {
+            assert!(parse_amount(invalid, SOL_DECIMALS).is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn durations_parse_into_seconds() {
+        assert_eq!(parse_duration("90"), Ok(90));
+        assert_eq!(parse_duration("45m"), Ok(45 * 60));
+        assert_eq!(parse_duration("12h"), Ok(12 * 60 * 60));
+        assert_eq!(parse_duration("30d"), Ok(30 * 24 * 60 * 60));
+        assert_eq!(parse_duration("2w"), Ok(2 * 7 * 24 * 60 * 60));
+
+        assert!(parse_duration("3y").unwrap_err().contains("Unknown duration unit"));
+        assert!(parse_duration("9223372036854775807w").unwrap_err().contains("too long"));
+        assert!(parse_duration("99999999999999999999").unwrap_err().contains("Invalid duration"));
+        assert!(parse_duration("d").unwrap_err().contains("Invalid duration"));
+    }
+}
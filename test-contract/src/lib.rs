@@ -1,4 +0,0 @@
-pub fn add(left: u64, right: u64) -> u64 {
-    left + right
-}
-
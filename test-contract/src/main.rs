@@ -0,0 +1,144 @@
+//! `smart-wallet`: manage a smart wallet and its dApp approvals from the command line
+
+mod commands;
+mod format;
+
+use std::{error::Error, path::PathBuf, process};
+
+use clap::{Parser, Subcommand};
+use solana_sdk::pubkey::Pubkey;
+
+use crate::commands::Context;
+
+const DEFAULT_RPC_URL: &str = "http://localhost:8899";
+const DEFAULT_PROGRAM_ID: &str = "5UwRT1ngPvSWjUWYcCoRmwVTs5WFUgdDfAW29Ab5XMx2";
+
+#[derive(Parser)]
+#[command(name = "smart-wallet", version, about = "Manage a smart wallet and its dApp approvals")]
+struct Cli {
+    /// RPC URL of the cluster
+    #[arg(short = 'u', long, global = true, env = "SMART_WALLET_RPC_URL", default_value = DEFAULT_RPC_URL)]
+    url: String,
+
+    /// Keypair file of the signer: the wallet owner, or the dApp for `execute`.
+    /// Defaults to ~/.config/solana/id.json
+    #[arg(short, long, global = true, env = "SMART_WALLET_KEYPAIR")]
+    keypair: Option<PathBuf>,
+
+    /// Address of the smart wallet program
+    #[arg(long, global = true, env = "SMART_WALLET_PROGRAM_ID", default_value = DEFAULT_PROGRAM_ID)]
+    program_id: Pubkey,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Create the signer's wallet
+    Create,
+
+    /// Approve a dApp to spend from the wallet
+    Approve {
+        /// Address of the dApp
+        dapp: Pubkey,
+        /// Maximum amount per transaction, e.g. 0.5
+        amount: String,
+        /// How long the approval lasts, e.g. 90m, 12h or 30d
+        #[arg(long, default_value = "30d", value_parser = format::parse_duration)]
+        expires_in: i64,
+        /// Token mint to approve instead of SOL
+        #[arg(long)]
+        mint: Option<Pubkey>,
+    },
+
+    /// Revoke a dApp's approval
+    Revoke {
+        /// Address of the dApp
+        dapp: Pubkey,
+        /// Token mint of the approval instead of SOL
+        #[arg(long)]
+        mint: Option<Pubkey>,
+    },
+
+    /// List the approvals granted by a wallet
+    ListApprovals {
+        /// Owner of the wallet, the signer by default
+        #[arg(long)]
+        owner: Option<Pubkey>,
+    },
+
+    /// Spend from a wallet as an approved dApp; the signer is the dApp
+    Execute {
+        /// Owner of the wallet to spend from
+        #[arg(long)]
+        owner: Pubkey,
+        /// Account to send to; tokens go to its associated token account
+        recipient: Pubkey,
+        /// Amount to send, e.g. 0.5
+        amount: String,
+        /// Token mint to send instead of SOL
+        #[arg(long)]
+        mint: Option<Pubkey>,
+    },
+
+    /// Withdraw from the wallet
+    Withdraw {
+        /// Amount to withdraw, e.g. 0.5
+        amount: String,
+        /// Account to withdraw to, the signer by default; tokens go to its associated
+        /// token account
+        #[arg(long)]
+        to: Option<Pubkey>,
+        /// Token mint to withdraw instead of SOL
+        #[arg(long)]
+        mint: Option<Pubkey>,
+    },
+
+    /// Deposit from the signer into the wallet
+    Deposit {
+        /// Amount to deposit, e.g. 0.5
+        amount: String,
+        /// Token mint to deposit instead of SOL
+        #[arg(long)]
+        mint: Option<Pubkey>,
+    },
+
+    /// Show the balances of a wallet
+    Balance {
+        /// Owner of the wallet, the signer by default
+        #[arg(long)]
+        owner: Option<Pubkey>,
+    },
+}
+
+fn default_keypair_path() -> Result<PathBuf, Box<dyn Error>> {
+    let home = std::env::var_os("HOME").ok_or("HOME is not set; pass --keypair")?;
+    Ok(PathBuf::from(home).join(".config/solana/id.json"))
+}
+
+fn run(cli: Cli) -> Result<(), Box<dyn Error>> {
+    let keypair_path = match cli.keypair {
+        Some(path) => path,
+        None => default_keypair_path()?,
+    };
+    let context = Context::new(&cli.url, &keypair_path, cli.program_id)?;
+
+    match cli.command {
+        Command::Create => context.create(),
+        Command::Approve { dapp, amount, expires_in, mint } => context.approve(&dapp, &amount, expires_in, mint),
+        Command::Revoke { dapp, mint } => context.revoke(&dapp, mint),
+        Command::ListApprovals { owner } => context.list_approvals(owner),
+        Command::Execute { owner, recipient, amount, mint } => context.execute(&owner, &recipient, &amount, mint),
+        Command::Withdraw { amount, to, mint } => context.withdraw(&amount, to, mint),
+        Command::Deposit { amount, mint } => context.deposit(&amount, mint),
+        Command::Balance { owner } => context.balance(owner),
+    }
+}
+
+fn main() {
+    if let Err(error) = run(Cli::parse()) {
+        eprintln!("Error: {}", error);
+        process::exit(1);
+    }
+}